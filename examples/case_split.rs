@@ -0,0 +1,85 @@
+//! Allocations made by case splits on a datatype with many constructors:
+//! every case of a split but the last copies the parent goal, e-graph included,
+//! though only once the case is taken, so the pending cases of a split share one goal.
+//!
+//! Run with `cargo run --release --example case_split -- examples/wide-enum.ceg`
+//! (the prover's command-line options apply, e.g. `--cyclic`).
+
+use cyclegg::config::{ARGS, CONFIG};
+use cyclegg::goal::{prove, Goal};
+use cyclegg::parser::parse_file;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The system allocator, counting the allocations and the bytes allocated,
+/// and keeping track of the most bytes allocated at once
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+    let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+    PEAK.fetch_max(live, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const RUNS: u32 = 20;
+
+fn main() {
+  let state = parse_file(&ARGS.filename).unwrap();
+  for raw in state.raw_goals.iter() {
+    let (reductions, defns) =
+      state.get_reductions_and_definitions(raw, raw.local_rules.clone(), &HashSet::new());
+    let goal = || {
+      Goal::top(
+        &raw.name,
+        &raw.equations,
+        &raw.premises,
+        raw.params.clone(),
+        &state.env,
+        &state.context,
+        &reductions,
+        None,
+        &defns,
+        &CONFIG,
+      )
+      .unwrap()
+    };
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let live = LIVE.load(Ordering::Relaxed);
+    PEAK.store(live, Ordering::Relaxed);
+    let start = Instant::now();
+    let mut splits = 0;
+    for _ in 0..RUNS {
+      let (_, proof_state) = prove(goal());
+      splits = proof_state.num_splits;
+    }
+    println!(
+      "{}: {} splits, {} allocations, {} KiB allocated, {} KiB at most at once, {:.2} ms per proof",
+      raw.name,
+      splits,
+      (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / RUNS as usize,
+      (BYTES.load(Ordering::Relaxed) - bytes) / RUNS as usize / 1024,
+      (PEAK.load(Ordering::Relaxed) - live) / 1024,
+      start.elapsed().as_secs_f64() * 1000.0 / RUNS as f64
+    );
+  }
+}
@@ -0,0 +1,45 @@
+//! Proves each goal of a file twice: once with all of its rewrites,
+//! and once with a rewrite filter (see `Goal::top`) that drops the recursive case of `app`.
+//!
+//! Run with `cargo run --release --example rewrite_filter -- examples/minimal-lemmas.ceg`
+//! (the prover's command-line options apply, e.g. `--cyclic`).
+
+use cyclegg::config::{ARGS, CONFIG};
+use cyclegg::goal::{prove, Goal, RewriteFilter};
+use cyclegg::parser::parse_file;
+use std::collections::HashSet;
+
+/// Everything but the rule `(app (Cons ?x ?xs) ?ys) => (Cons ?x (app ?xs ?ys))`
+fn without_app_cons(name: &str) -> bool {
+  !name.starts_with("(app (Cons")
+}
+
+fn main() {
+  let state = parse_file(&ARGS.filename).unwrap();
+  for raw in state.raw_goals.iter() {
+    let (reductions, defns) =
+      state.get_reductions_and_definitions(raw, raw.local_rules.clone(), &HashSet::new());
+    let outcome = |filter: Option<RewriteFilter>| {
+      let goal = Goal::top(
+        &raw.name,
+        &raw.equations,
+        &raw.premises,
+        raw.params.clone(),
+        &state.env,
+        &state.context,
+        &reductions,
+        filter,
+        &defns,
+        &CONFIG,
+      )
+      .unwrap();
+      prove(goal).0
+    };
+    println!(
+      "{}: all rewrites: {}, without the Cons case of app: {}",
+      raw.name,
+      outcome(None),
+      outcome(Some(without_app_cons))
+    );
+  }
+}
@@ -0,0 +1,58 @@
+//! Time spent proving goals with and without memoizing termination checks
+//! (see `ProverConfig::memoize_termination_checks`):
+//! lemmas are checked to apply to smaller arguments at every match during saturation,
+//! which with deep recursion compares the same variables with the same values over and over.
+//!
+//! Run with `cargo run --release --example termination_check -- examples/deep-recursion.ceg`
+//! (the prover's command-line options apply, e.g. `--cyclic`).
+
+use cyclegg::config::{ARGS, CONFIG};
+use cyclegg::goal::{prove, Goal, Outcome};
+use cyclegg::parser::parse_file;
+use std::collections::HashSet;
+use std::time::Instant;
+
+const RUNS: u32 = 10;
+
+fn main() {
+  let state = parse_file(&ARGS.filename).unwrap();
+  let configs = [
+    CONFIG.clone().with_memoize_termination_checks(false),
+    CONFIG.clone().with_memoize_termination_checks(true),
+  ];
+  for raw in state.raw_goals.iter() {
+    let (reductions, defns) =
+      state.get_reductions_and_definitions(raw, raw.local_rules.clone(), &HashSet::new());
+    let times: Vec<(Outcome, f64)> = configs
+      .iter()
+      .map(|config| {
+        let start = Instant::now();
+        let mut outcome = Outcome::Valid;
+        for _ in 0..RUNS {
+          let goal = Goal::top(
+            &raw.name,
+            &raw.equations,
+            &raw.premises,
+            raw.params.clone(),
+            &state.env,
+            &state.context,
+            &reductions,
+            None,
+            &defns,
+            config,
+          )
+          .unwrap();
+          outcome = prove(goal).0;
+        }
+        (
+          outcome,
+          start.elapsed().as_secs_f64() * 1000.0 / RUNS as f64,
+        )
+      })
+      .collect();
+    println!(
+      "{}: {}, {:.2} ms per proof without the memo, {:.2} ms with it",
+      raw.name, times[1].0, times[0].1, times[1].1
+    );
+  }
+}
@@ -0,0 +1,15 @@
+; TIP-style problem
+(declare-datatype Nat ((Z) (S (p Nat))))
+(declare-datatypes ((List 1)) ((par (a) ((Nil) (Cons (head a) (tail (List a)))))))
+(define-fun-rec add ((x Nat) (y Nat)) Nat
+  (match x ((Z y) ((S n) (S (add n y))))))
+(define-fun-rec (par (a) (len ((xs (List a))) Nat
+  (match xs ((Nil Z) ((Cons y ys) (S (len ys))))))))
+(define-fun-rec (par (a) (app ((xs (List a)) (ys (List a))) (List a)
+  (match xs ((Nil ys) (_ (Cons (head xs) (app (tail xs) ys))))))))
+(define-fun-rec leq ((x Nat) (y Nat)) Bool
+  (match x ((Z true) ((S n) (match y ((Z false) ((S m) (leq n m))))))))
+(prove (forall ((n Nat)) (= (add n Z) n)))
+(prove (par (a) (forall ((xs (List a)) (ys (List a))) (= (len (app xs ys)) (add (len xs) (len ys))))))
+(assert (not (forall ((n Nat)) (leq n n))))
+(check-sat)
@@ -2,7 +2,12 @@ use egg::*;
 use lazy_static::lazy_static;
 
 use indexmap::IndexMap;
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Display,
+  ops::{Deref, DerefMut},
+  str::FromStr,
+};
 use symbolic_expressions::{Sexp, SexpError};
 
 use crate::config::CONFIG;
@@ -38,6 +43,21 @@ impl Type {
     }
   }
 
+  /// Substitute the type variables of this type according to instantiations (see `find_instantiations`)
+  pub fn instantiate(&self, instantiations: &SSubst) -> Type {
+    Type::new(resolve_sexp(&self.repr, instantiations))
+  }
+
+  /// Is this the built-in type of integers?
+  pub fn is_int(&self) -> bool {
+    matches!(&self.repr, Sexp::String(s) if *s == *INT_TYPE)
+  }
+
+  /// Is this a function type?
+  pub fn is_arrow(&self) -> bool {
+    matches!(&self.repr, Sexp::List(xs) if xs.first().and_then(|x| x.string().ok()).is_some_and(|s| s == ARROW))
+  }
+
   /// Split a type into arguments and return value
   /// (arguments are empty if the type is not an arrow)
   pub fn args_ret(&self) -> (Vec<Type>, Type) {
@@ -113,13 +133,83 @@ pub fn mangle_sexp(sexp: &Sexp) -> Sexp {
 lazy_static! {
   pub static ref BOOL_TYPE: String = mangle_name("Bool");
   pub static ref ITE: String = mangle_name("ite");
+  /// `(match s arm_1 ... arm_n)` selects the arm for the constructor of `s`,
+  /// where the arms are in the order the constructors were declared;
+  /// the i-th arm is applied (using `$`) to the arguments of the i-th constructor,
+  /// so arms for nullary constructors are plain values
+  pub static ref MATCH: String = mangle_name("match");
+  /// `(let x e body)` stands for body with e substituted for x (see `expand_lets`)
+  pub static ref LET: String = mangle_name("let");
   pub static ref TRUE: String = mangle_name("True");
   pub static ref FALSE: String = mangle_name("False");
+  // Boolean connectives, which have built-in reductions
+  pub static ref AND: String = mangle_name("and");
+  pub static ref OR: String = mangle_name("or");
+  pub static ref NOT: String = mangle_name("not");
+  pub static ref IMPLIES: String = mangle_name("implies");
+  // Natural numbers and the arithmetic that is folded with --fold-nats
+  pub static ref ZERO: String = mangle_name("Z");
+  pub static ref SUCC: String = mangle_name("S");
+  pub static ref ADD: String = mangle_name("add");
+  pub static ref MUL: String = mangle_name("mul");
+  pub static ref EQ: String = mangle_name("eq");
+  // Integers are built in: their constructors are the integer literals
+  pub static ref INT_TYPE: String = mangle_name("Int");
+  /// Pairs are built in, unless a problem declares its own: `(Pair a b)` has the single constructor `Pair`
+  pub static ref PAIR: String = mangle_name("Pair");
 }
 pub const ARROW: &str = "->";
 pub const APPLY: &str = "$";
 pub const GUARD_PREFIX: &str = "g_";
+// Integer arithmetic and comparisons, which are evaluated on integer literals
+pub const INT_ADD: &str = "+";
+pub const INT_SUB: &str = "-";
+pub const INT_MUL: &str = "*";
+pub const INT_LT: &str = "<";
+pub const INT_LE: &str = "<=";
+pub const INT_GT: &str = ">";
+pub const INT_GE: &str = ">=";
+
+/// Is this one of the Boolean connectives?
+pub fn is_connective(s: &Symbol) -> bool {
+  [&*AND, &*OR, &*NOT, &*IMPLIES]
+    .iter()
+    .any(|c| s.as_str() == c.as_str())
+}
+
+/// Is this the name of an equality test? By convention these are `eq`, `==`,
+/// or `eq` followed by the name of a type (e.g. `eqList`)
+pub fn is_equality_name(s: &Symbol) -> bool {
+  let s = s.as_str();
+  s == EQ.as_str()
+    || s == "=="
+    || s
+      .strip_prefix(EQ.as_str())
+      .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_uppercase()))
+}
+
+/// Is this one of the integer operations?
+pub fn is_int_operation(s: &Symbol) -> bool {
+  [INT_ADD, INT_SUB, INT_MUL, INT_LT, INT_LE, INT_GT, INT_GE].contains(&s.as_str())
+}
+
+/// The value of an integer literal, such as `42` or `-7`
+pub fn int_literal(s: &str) -> Option<i64> {
+  let digits = s.strip_prefix('-').unwrap_or(s);
+  if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  s.parse().ok()
+}
 
+/// Does this symbol have reductions even if it is not declared in the program?
+pub fn is_builtin(s: &Symbol) -> bool {
+  s.as_str() == MATCH.as_str() || is_connective(s) || is_int_operation(s)
+}
+
+/// How many case splits it took to create this variable:
+/// every split appends `_` and a number to the name of the variable it splits
+/// (e.g. `xs` becomes `xs_3` and then `xs_3_7`)
 pub fn var_depth(var_name: &str) -> usize {
   var_name.matches('_').count()
 }
@@ -130,7 +220,7 @@ pub fn is_descendant(var_name: &str, ancestor_name: &str) -> bool {
     && var_name.chars().nth(ancestor_name.len()).unwrap() == '_'
 }
 
-#[derive(PartialEq, PartialOrd, Ord, Eq, Debug)]
+#[derive(PartialEq, PartialOrd, Ord, Eq, Debug, Clone, Copy)]
 pub enum StructuralComparison {
   /// Strictly less than
   LT,
@@ -142,20 +232,64 @@ pub enum StructuralComparison {
 
 /// Check if sub is a subterm of sup
 pub fn is_subterm(sub: &Expr, sup: &Expr) -> StructuralComparison {
-  // Convert both expressions to strings and check if one is a substring of the other
-  let sub_str = sub.to_string();
-  let sup_str = sup.to_string();
-  if sup_str.contains(&sub_str) {
-    if sub_str.len() < sup_str.len() {
-      StructuralComparison::LT
-    } else {
-      StructuralComparison::LE
+  // Compare the terms structurally rather than as strings,
+  // where e.g. `b` would look like a subterm of `(S ab_0)`
+  let sub_root = root_id(sub);
+  let sup_root = root_id(sup);
+  if same_term(sub, sub_root, sup, sup_root) {
+    return StructuralComparison::LE;
+  }
+  let mut worklist = sup[sup_root].children.clone();
+  while let Some(id) = worklist.pop() {
+    if same_term(sub, sub_root, sup, id) {
+      return StructuralComparison::LT;
+    }
+    worklist.extend(sup[id].children.iter().copied());
+  }
+  StructuralComparison::Incomparable
+}
+
+/// A slower but more obviously correct version of `is_subterm`, to cross-check it against:
+/// the terms are compared as s-expressions, against every subterm of sup in turn
+pub fn reference_subterm(sub: &Expr, sup: &Expr) -> StructuralComparison {
+  fn proper_subterms<'a>(sexp: &'a Sexp, res: &mut Vec<&'a Sexp>) {
+    if let Sexp::List(xs) = sexp {
+      // The head is a function or constructor name, not an argument
+      for x in xs.iter().skip(1) {
+        res.push(x);
+        proper_subterms(x, res);
+      }
     }
+  }
+  let to_sexp = |expr: &Expr| symbolic_expressions::parser::parse_str(&expr.to_string()).unwrap();
+  let (sub, sup) = (to_sexp(sub), to_sexp(sup));
+  let mut subterms = vec![];
+  proper_subterms(&sup, &mut subterms);
+  if sub == sup {
+    StructuralComparison::LE
+  } else if subterms.contains(&&sub) {
+    StructuralComparison::LT
   } else {
     StructuralComparison::Incomparable
   }
 }
 
+fn root_id(expr: &Expr) -> Id {
+  Id::from(expr.as_ref().len() - 1)
+}
+
+/// Are the subterm of a rooted at a_id and the subterm of b rooted at b_id the same term?
+fn same_term(a: &Expr, a_id: Id, b: &Expr, b_id: Id) -> bool {
+  let (a_node, b_node) = (&a[a_id], &b[b_id]);
+  a_node.op == b_node.op
+    && a_node.children.len() == b_node.children.len()
+    && a_node
+      .children
+      .iter()
+      .zip(b_node.children.iter())
+      .all(|(a_child, b_child)| same_term(a, *a_child, b, *b_child))
+}
+
 /// Replace one variable with another in a RecExpr;
 /// also returns whether the variable was found
 pub fn replace_var(expr: &Expr, var: Symbol, replacement: Symbol) -> (Expr, bool) {
@@ -183,6 +317,15 @@ where
   }
 }
 
+/// Does the symbol name occur anywhere in sexp?
+pub fn mentions(sexp: &Sexp, name: &str) -> bool {
+  match sexp {
+    Sexp::Empty => false,
+    Sexp::String(str) => str == name,
+    Sexp::List(list) => list.iter().any(|s| mentions(s, name)),
+  }
+}
+
 pub fn contains_function(sexp: &Sexp) -> bool {
   match sexp {
     Sexp::List(list) => {
@@ -267,6 +410,30 @@ pub fn resolve_sexp(sexp: &Sexp, instantiations: &SSubst) -> Sexp {
   )
 }
 
+/// Expand every `(let x e body)` in sexp by substituting e for x in body;
+/// inner lets are expanded first, so an inner binding of x shadows an outer one.
+/// The bound variable does not survive the expansion, so it is never a parameter to split on.
+/// Returns the first form that is not a valid let, i.e. not of the form `(let x e body)` for a variable x.
+pub fn expand_lets(sexp: &Sexp) -> Result<Sexp, Sexp> {
+  match sexp {
+    Sexp::List(list) if matches!(list.first(), Some(Sexp::String(head)) if *head == *LET) => {
+      match list.as_slice() {
+        [_, Sexp::String(var), value, body] if !is_constructor(var) => {
+          let value = expand_lets(value)?;
+          let body = expand_lets(body)?;
+          let subst: SSubst = [(var.clone(), value)].into_iter().collect();
+          Ok(resolve_sexp(&body, &subst))
+        }
+        _ => Err(sexp.clone()),
+      }
+    }
+    Sexp::List(list) => Ok(Sexp::List(
+      list.iter().map(expand_lets).collect::<Result<_, _>>()?,
+    )),
+    _ => Ok(sexp.clone()),
+  }
+}
+
 /// Recursively resolves a Sexp using instantiations.
 ///
 /// Ex: sexp:           (List a)
@@ -285,8 +452,10 @@ pub fn recursively_resolve_variable(var: &str, instantiations: &SSubst) -> Sexp
     .unwrap_or_else(|| Sexp::String(var.to_string()))
 }
 
+/// Constructors start with an uppercase letter, except for integer literals,
+/// which are the (infinitely many) constructors of `Int`
 pub fn is_constructor(var_name: &str) -> bool {
-  var_name.chars().next().unwrap().is_uppercase()
+  var_name.chars().next().unwrap().is_uppercase() || int_literal(var_name).is_some()
 }
 
 // Convert a symbol into a wildcard by prepending a '?' to it
@@ -327,15 +496,74 @@ pub fn lookup_vars<'a, I: Iterator<Item = &'a Symbol>, A: Analysis<SymbolLang>>(
   let mut subst = IndexMap::new();
   for var in vars {
     match egraph.lookup(SymbolLang::leaf(*var)) {
-      Some(id) => subst.insert(var.clone(), id),
+      Some(id) => subst.insert(*var, id),
       None => panic!("lookup_vars: variable {} not found in egraph", var),
     };
   }
   subst
 }
 
-// Environment: for now just a map from datatype names to constructor names
-pub type Env = HashMap<Symbol, (Vec<String>, Vec<Symbol>)>;
+// Environment: a map from datatype names to their type variables and constructor names,
+// together with the constructors that are declared symmetric and the functions that are declared AC
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+  datatypes: HashMap<Symbol, (Vec<String>, Vec<Symbol>)>,
+  /// Constructors whose arguments may be permuted freely, e.g. a set-like pair;
+  /// the prover trusts this annotation, so every function and goal over the datatype
+  /// must be invariant under permuting the arguments of these constructors.
+  /// Subgoals that differ only by argument order are closed as duplicates (see `ProofState::push_goal`),
+  /// and the emitted proofs of such subgoals do not justify the permutation.
+  pub symmetric: HashSet<Symbol>,
+  /// Binary functions that are associative and commutative, e.g. addition;
+  /// the prover trusts this annotation as well, and saturates with rewrites that reorder
+  /// and regroup their applications (see `parser::ac_rewrites`) instead of proving them by induction.
+  pub ac: HashSet<Symbol>,
+}
+
+impl Env {
+  pub fn is_symmetric(&self, con: &Symbol) -> bool {
+    self.symmetric.contains(con)
+  }
+
+  /// Sort the arguments of every application of a symmetric constructor in sexp;
+  /// arguments are ordered by their shape, with every variable (as decided by is_var) looking the same,
+  /// so that two terms that differ only by renaming and argument order end up differing only by renaming
+  pub fn sort_symmetric_args<F: Fn(&Symbol) -> bool>(&self, sexp: &Sexp, is_var: &F) -> Sexp {
+    match sexp {
+      Sexp::List(xs) if !xs.is_empty() => {
+        let mut xs: Vec<Sexp> = xs
+          .iter()
+          .map(|x| self.sort_symmetric_args(x, is_var))
+          .collect();
+        let symmetric = match &xs[0] {
+          Sexp::String(head) => self.is_symmetric(&Symbol::from(head)),
+          _ => false,
+        };
+        if symmetric {
+          let shape =
+            |s: &str| Sexp::String(if is_var(&Symbol::from(s)) { "?" } else { s }.to_string());
+          xs[1..].sort_by_cached_key(|arg| map_sexp(shape, arg).to_string());
+        }
+        Sexp::List(xs)
+      }
+      _ => sexp.clone(),
+    }
+  }
+}
+
+impl Deref for Env {
+  type Target = HashMap<Symbol, (Vec<String>, Vec<Symbol>)>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.datatypes
+  }
+}
+
+impl DerefMut for Env {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.datatypes
+  }
+}
 
 // Type context
 pub type Context = HashMap<Symbol, Type>;
@@ -1,20 +1,213 @@
-use std::{fs::create_dir_all, path::PathBuf, sync::Mutex};
+use std::{
+  collections::{HashMap, HashSet},
+  fs::create_dir_all,
+  path::PathBuf,
+};
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
+use egg::Symbol;
 use lazy_static::lazy_static;
-use log::Level;
+use tracing::Level;
+
+/// Which egg rewrite scheduler to use during saturation
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulerKind {
+  /// egg's default scheduler, which temporarily bans rules that match too often
+  Backoff,
+  /// Apply every rule in every iteration
+  Simple,
+}
+
+/// How to compare the tuple of lemma arguments against the tuple of original variables
+/// when checking that a lemma application terminates
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerminationOrder {
+  /// All components no larger, at least one strictly smaller
+  Pointwise,
+  /// Components compared left-to-right, the first strictly smaller one decides
+  Lexicographic,
+  /// Components compared as multisets, ignoring their positions
+  Multiset,
+}
+
+/// Order in which pending subgoals are explored
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchStrategy {
+  /// Treat pending subgoals as a stack
+  DepthFirst,
+  /// Treat pending subgoals as a queue, which finds shallow counterexamples sooner
+  BreadthFirst,
+}
+
+/// Which expressions of the goal's sides to build cyclic lemmas from
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LemmaExtraction {
+  /// Every pair of expressions in the e-classes of the two sides
+  Exhaustive,
+  /// Only the smallest expression of each side
+  Minimal,
+}
+
+/// Cost function used to pick expressions out of an e-graph
+/// (for lemmas, guards, and logging)
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtractionCost {
+  /// Fewest nodes
+  AstSize,
+  /// Least nesting
+  AstDepth,
+}
+
+/// File format of saved e-graph drawings
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+  Png,
+  Svg,
+  Pdf,
+  /// Graphviz source, which can be written without Graphviz installed
+  Dot,
+}
+
+impl GraphFormat {
+  /// File extension (and Graphviz output format)
+  pub fn extension(&self) -> &'static str {
+    match self {
+      GraphFormat::Png => "png",
+      GraphFormat::Svg => "svg",
+      GraphFormat::Pdf => "pdf",
+      GraphFormat::Dot => "dot",
+    }
+  }
+}
+
+/// A user-supplied extraction cost: the cost of an e-node given a function from its children to their costs
+pub type CustomCost = fn(&egg::SymbolLang, &mut dyn FnMut(egg::Id) -> usize) -> usize;
+
+/// A user-supplied termination check for lemma applications, used instead of a `TerminationOrder`:
+/// given, for every variable of the lemma, its value where the lemma was created and the actual argument
+/// (both constructor terms), whether the arguments are strictly smaller.
+/// Soundness of the whole prover rests on it: it must only accept arguments that decrease
+/// in some well-founded order, or cyclic proofs of false conjectures go through
+/// (see `examples/unsound-ih.ceg`).
+pub type CustomTermination = fn(
+  &[(
+    egg::Symbol,
+    egg::RecExpr<egg::SymbolLang>,
+    egg::RecExpr<egg::SymbolLang>,
+  )],
+) -> bool;
+
+/// Parse a per-datatype split depth of the form `Datatype=depth`
+fn parse_type_depth(s: &str) -> Result<(String, usize), String> {
+  let (datatype, depth) = s
+    .split_once('=')
+    .ok_or_else(|| format!("expected Datatype=depth, got {}", s))?;
+  let depth = depth.parse().map_err(|e| format!("{}: {}", depth, e))?;
+  Ok((datatype.to_string(), depth))
+}
+
+/// How to choose the next variable to case-split on
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrutineeHeuristic {
+  /// In the order the scrutinees were introduced
+  Fifo,
+  /// The scrutinee whose e-class occurs in the most matches of the reductions
+  MostMatches,
+}
 
 #[derive(Parser)]
 pub struct Args {
   pub filename: String,
   #[clap(short = 'd', long = "max-depth", default_value = "3")]
   pub max_split_depth: usize,
+  /// Maximum split depth for variables of a specific datatype, e.g. `Nat=4`
+  /// (can be given multiple times; other datatypes use --max-depth)
+  #[clap(long = "type-depth", parse(try_from_str = parse_type_depth))]
+  pub type_split_depths: Vec<(String, usize)>,
+  /// Do not case-split on variables of datatypes with more than this many constructors
+  /// while there are other variables to split on (0 means no limit)
+  #[clap(long = "max-constructor-fanout", default_value = "0")]
+  pub max_constructor_fanout: usize,
   #[clap(short = 's', long = "single-rhs")]
   pub single_rhs: bool,
   #[clap(short = 'i', long = "irreducible")]
   pub irreducible_only: bool,
   #[clap(short = 'c', long = "no-cond-split")]
   pub no_cond_split: bool,
+  /// How to choose the next variable to case-split on
+  /// (guards introduced by conditionals are always split first)
+  #[clap(arg_enum, long = "scrutinee-heuristic", default_value = "fifo")]
+  pub scrutinee_heuristic: ScrutineeHeuristic,
+  /// Order in which pending subgoals are explored
+  #[clap(arg_enum, long = "search", default_value = "depth-first")]
+  pub search_strategy: SearchStrategy,
+  /// Well-founded order used to check that lemma applications are smaller
+  #[clap(arg_enum, long = "termination-order", default_value = "pointwise")]
+  pub termination_order: TerminationOrder,
+  /// Which expressions of the goal's sides to build cyclic lemmas from
+  #[clap(arg_enum, long = "lemma-extraction", default_value = "exhaustive")]
+  pub lemma_extraction: LemmaExtraction,
+  /// Generalize subterms that occur on both sides of a goal before case-splitting
+  /// (a goal that fails after generalization is reported as unknown rather than invalid)
+  #[clap(long = "generalize")]
+  pub generalize: bool,
+  /// Before proving a goal that passes a constant accumulator to a function (e.g. `(revAcc xs Nil)`),
+  /// try to prove it for any accumulator, and use that as a lemma
+  #[clap(long = "generalize-accumulators")]
+  pub generalize_accumulators: bool,
+  /// Give up on a goal left with a function applied to constructor terms that no case of its definition matches
+  /// (the definition is partial), instead of refuting it
+  #[clap(long = "detect-stuck")]
+  pub detect_stuck: bool,
+  /// Split on these parameters of the goal simultaneously, so that the induction hypothesis
+  /// can decrease all of them together (can be given multiple times)
+  #[clap(long = "joint")]
+  pub joint_induction: Vec<String>,
+  /// Also use the definition of this function from right to left, in the cases where that makes terms smaller
+  /// (can be given multiple times)
+  #[clap(long = "bidirectional")]
+  pub bidirectional_defs: Vec<String>,
+  /// Never unfold the definition of this function during saturation, so that proofs about it
+  /// can only use the lemmas given with the goal (can be given multiple times)
+  #[clap(long = "opaque")]
+  pub opaque_functions: Vec<String>,
+  /// Evaluate `add` and `mul` on closed natural numbers while building e-graphs
+  /// (this assumes they are defined as the usual addition and multiplication on `Nat`)
+  #[clap(long = "fold-nats")]
+  pub fold_nats: bool,
+  /// Skip subgoals that are the same as an earlier subgoal up to renaming variables,
+  /// and close them by referring to it
+  #[clap(long = "prune-duplicates")]
+  pub prune_duplicates: bool,
+  /// Keep proving the other cases after a case turns out invalid or unknown,
+  /// and report every case that failed
+  #[clap(long = "exhaustive-failures")]
+  pub exhaustive_failures: bool,
+  /// Cross-check every application of a lemma against a slower reference implementation
+  /// of the termination check, and panic if they disagree (for testing the prover)
+  #[clap(long = "verify-lemmas")]
+  pub verify_lemmas: bool,
+  /// Do not use the lemmas created by case splits (induction hypotheses),
+  /// to find out whether a goal can be proven by case analysis alone
+  #[clap(long = "no-lemmas")]
+  pub no_lemmas: bool,
+  /// Also use the hypotheses of conditional goals as rewrites (from left to right)
+  #[clap(long = "hypothesis-rewrites")]
+  pub hypothesis_rewrites: bool,
+  /// When a conditional is stuck on an equality test (`eq`, `==`, or `eqT` for a type T) between variables,
+  /// split on the variables instead of introducing a Boolean for the test
+  #[clap(long = "split-equality-operands")]
+  pub split_equality_operands: bool,
+  /// Record explanations of why e-classes are merged, so that the debug log shows why each case holds;
+  /// costs time and memory, and is implied when proofs are emitted or printed
+  #[clap(long)]
+  pub explanations: bool,
+  /// Saturate all pending subgoals in parallel, one round at a time, instead of one after the other
+  #[clap(long = "parallel")]
+  pub parallel: bool,
+  /// Before proving a goal, try to refute it by evaluating it on this many random parameter values
+  #[clap(long = "quickcheck", default_value = "0")]
+  pub quickcheck_trials: usize,
   /// Mode
   #[clap(long = "cyclic")]
   pub cyclic: bool,
@@ -23,11 +216,58 @@ pub struct Args {
   /// Timeout
   #[clap(short = 't', long = "timeout", default_value = "0")]
   pub timeout: u64,
+  /// Maximum number of e-nodes in the e-graph of a single goal during saturation
+  #[clap(long = "max-egraph-nodes", default_value = "10000")]
+  pub max_egraph_nodes: usize,
+  /// Maximum number of saturation iterations for a single goal
+  #[clap(long = "max-iterations", default_value = "30")]
+  pub max_iterations: usize,
+  /// Maximum number of new lemmas created from a single goal (0 means no limit);
+  /// the lemmas from the smallest expressions are kept
+  #[clap(long = "max-lemmas", default_value = "0")]
+  pub max_lemmas_per_goal: usize,
+  /// Maximum size (number of nodes) of either side of a lemma (0 means no limit);
+  /// larger lemmas are not created
+  #[clap(long = "max-lemma-size", default_value = "0")]
+  pub max_lemma_size: usize,
+  /// Maximum number of distinct variables in a lemma (0 means no limit);
+  /// lemmas with more variables match too broadly, and are not created
+  #[clap(long = "max-lemma-vars", default_value = "0")]
+  pub max_lemma_vars: usize,
+  /// Maximum number of case splits in the whole proof of a goal (0 means no limit)
+  #[clap(long = "max-total-splits", default_value = "0")]
+  pub max_total_splits: usize,
+  /// Maximum number of new guards of conditionals that are split on before any other variable (0 means no limit);
+  /// the guards that occur in the most e-nodes are chosen, and the others are split after all other variables
+  #[clap(long = "max-ite-scrutinees", default_value = "0")]
+  pub max_ite_scrutinees: usize,
+  /// Cost function for extracting expressions from the e-graph
+  /// (this determines the shape of lemmas with --lemma-extraction minimal)
+  #[clap(arg_enum, long = "extraction-cost", default_value = "ast-size")]
+  pub extraction_cost: ExtractionCost,
+  /// Rewrite scheduler used during saturation
+  #[clap(arg_enum, long = "scheduler", default_value = "backoff")]
+  pub scheduler: SchedulerKind,
+  /// After a case split, first propagate the new equality through the part of the parent's saturated e-graph
+  /// it affects, before re-saturating the whole e-graph
+  #[clap(long = "incremental-saturation")]
+  pub incremental_saturation: bool,
   /// Logging
   #[clap(short = 'l', long = "log", default_value = "ERROR")]
   pub log_level: String,
+  /// Save a drawing of the e-graph of every subgoal to the graph directory,
+  /// with the classes of the goal's sides and the scrutinees left to split on highlighted
   #[clap(short = 'g', long = "save-graphs")]
   pub save_graphs: bool,
+  /// Format of the e-graphs saved with --save-graphs
+  #[clap(arg_enum, long = "graph-format", default_value = "png")]
+  pub graph_format: GraphFormat,
+  /// Where to save the e-graphs saved with --save-graphs
+  #[clap(long = "graph-dir", default_value = "target")]
+  pub graph_dir: PathBuf,
+  /// Export the e-graph of every subgoal as JSON to the output directory
+  #[clap(long = "export-json")]
+  pub export_json: bool,
   #[clap(short = 'r', long = "save-results")]
   pub save_results: bool,
   /// Emit proofs under the proofs directory in the output directory
@@ -37,6 +277,15 @@ pub struct Args {
   pub verbose: bool,
   #[clap(long = "verbose-proofs")]
   pub verbose_proofs: bool,
+  /// Print the structure of each proof (case splits and the lemmas closing each case)
+  #[clap(long = "proof-tree")]
+  pub record_proof: bool,
+  /// Save a replayable proof script for each goal to the output directory
+  #[clap(long = "proof-script")]
+  pub save_proof_script: bool,
+  /// Save a Coq proof skeleton for each goal proven to the output directory
+  #[clap(long = "coq")]
+  pub save_coq_proof: bool,
   /// Where to save outputs other than proofs
   #[clap(short = 'o', long = "output-directory", default_value = "target")]
   pub output_directory: PathBuf,
@@ -55,6 +304,9 @@ pub struct Args {
   /// Do not emit comments in proofs
   #[clap(long = "no-proof-comments")]
   pub no_proof_comments: bool,
+  /// Instead of proving goals, print the case splits the prover would make on them up to the depth bound
+  #[clap(long = "plan")]
+  pub plan: bool,
   /// Only prove the proposition with this name
   #[clap(long = "prop")]
   pub prop: Option<String>,
@@ -70,64 +322,248 @@ impl Args {
   }
 }
 
-pub struct Config {
+/// Settings for a single run of the prover.
+///
+/// The global `CONFIG` is built from the command line; library users can start
+/// from `ProverConfig::default()` and adjust it with the `with_*` methods.
+#[derive(Clone, Debug)]
+pub struct ProverConfig {
   pub prop: Option<String>,
-  // This is a mutex because we want to be able to change it
-  cyclic_mode: Mutex<bool>,
+  pub cyclic: bool,
   // proof search parameters
   pub max_split_depth: usize,
+  /// Per-datatype overrides of max_split_depth, keyed by the (mangled) datatype name
+  pub type_split_depths: HashMap<String, usize>,
+  /// Variables of datatypes with more constructors than this are split last
+  pub max_constructor_fanout: Option<usize>,
   pub split_conditionals: bool,
   pub single_rhs: bool,
   pub irreducible_only: bool,
-  // timeout
+  pub scrutinee_heuristic: ScrutineeHeuristic,
+  pub search_strategy: SearchStrategy,
+  pub termination_order: TerminationOrder,
+  /// If set, used instead of termination_order (only available through the library)
+  pub custom_termination: Option<CustomTermination>,
+  pub lemma_extraction: LemmaExtraction,
+  pub extraction_cost: ExtractionCost,
+  /// If set, used instead of extraction_cost (only available through the library)
+  pub custom_extraction_cost: Option<CustomCost>,
+  pub generalize: bool,
+  /// Whether goals are first proven with their accumulators generalized (see `Goal::generalize_accumulator`)
+  pub generalize_accumulators: bool,
+  /// Whether goals with stuck applications of partial definitions are unknown rather than invalid
+  /// (see `Goal::stuck_application`)
+  pub detect_stuck: bool,
+  /// Names of the (mangled) parameters to split on simultaneously
+  pub joint_induction: Vec<String>,
+  /// Names of the (mangled) functions whose definitions are also used from right to left
+  pub bidirectional_defs: Vec<String>,
+  /// Names of the (mangled) functions whose definitions are left out of every goal's rewrites
+  pub opaque_functions: HashSet<Symbol>,
+  /// Whether the lemmas created by case splits are used in saturation
+  pub use_lemmas: bool,
+  pub hypothesis_rewrites: bool,
+  pub split_equality_operands: bool,
+  pub fold_nats: bool,
+  pub prune_duplicates: bool,
+  /// Whether a failed case is recorded in `ProofState::failures` instead of ending the proof
+  pub exhaustive_failures: bool,
+  /// Whether lemma applications are cross-checked against a reference termination check
+  pub verify_lemmas: bool,
+  /// Number of random tests to run before proving a goal (0 disables testing)
+  pub quickcheck_trials: usize,
+  // resource limits
   pub timeout: Option<u64>,
+  pub max_egraph_nodes: usize,
+  pub max_iterations: usize,
+  pub max_lemmas_per_goal: Option<usize>,
+  pub max_lemma_size: Option<usize>,
+  pub max_lemma_vars: Option<usize>,
+  pub max_total_splits: Option<usize>,
+  pub max_ite_scrutinees: Option<usize>,
+  pub scheduler: SchedulerKind,
+  /// Priorities of rewrites during saturation, keyed by a prefix of the rewrite name
+  /// (only available through the library; see `rewrite_priority`)
+  pub rewrite_priorities: HashMap<String, i32>,
+  pub incremental_saturation: bool,
+  /// Whether saturation memoizes how variables compare with the values of lemma variables
+  /// when checking that lemmas apply to smaller arguments
+  /// (only available through the library, for benchmarking; on by default)
+  pub memoize_termination_checks: bool,
+  /// Whether the binary proves goals with `prove_parallel`
+  pub parallel: bool,
+  /// Whether e-graphs record why their classes are merged, so that discharged goals have explanations
+  /// (which proof emission, proof trees, and promoting lemmas into a `LemmaLibrary` rely on);
+  /// this slows down every union and keeps the whole history of the e-graph in memory
+  pub explanations: bool,
   // logging
   pub log_level: Level,
   pub save_graphs: bool,
+  pub graph_format: GraphFormat,
+  pub graph_dir: PathBuf,
+  pub export_json: bool,
   pub save_results: bool,
   pub emit_proofs: bool,
   pub verbose: bool,
   pub verbose_proofs: bool,
+  pub record_proof: bool,
+  pub save_proof_script: bool,
+  pub save_coq_proof: bool,
   pub output_directory: PathBuf,
   pub proofs_directory: PathBuf,
   pub mangle_names: bool,
   pub proof_comments: bool,
 }
 
-impl Config {
-  fn from_args(args: &Args) -> Self {
+impl ProverConfig {
+  pub fn from_args(args: &Args) -> Self {
     // Make the output directory if it doesn't exist.
     create_dir_all(&args.output_directory).unwrap();
+    if args.save_graphs {
+      create_dir_all(&args.graph_dir).unwrap();
+    }
     let emit_proofs = args.emit_proofs;
     if emit_proofs {
       // Make the proofs directory if it doesn't exist.
       create_dir_all(&args.proofs_directory).unwrap();
     }
     let mangle_names = !args.unmangled_names && emit_proofs;
+    // Why the +1? Because mangling the names prepends a Cyclegg_ to
+    // everything, which means that our depth check (which naively looks at
+    // how many underscores there are) will return 1 greater than it should.
+    let depth_offset = if mangle_names { 1 } else { 0 };
+    let type_split_depths = args
+      .type_split_depths
+      .iter()
+      .map(|(datatype, depth)| {
+        // Datatype names are mangled the same way as in the parser
+        let datatype = if mangle_names {
+          format!("Cyclegg_{}", datatype)
+        } else {
+          datatype.clone()
+        };
+        (datatype, depth + depth_offset)
+      })
+      .collect();
     Self {
-      cyclic_mode: Mutex::new(false),
-      max_split_depth: if mangle_names {
-        // Why the +1? Because mangling the names prepends a Cyclegg_ to
-        // everything, which means that our depth check (which naively looks at
-        // how many underscores there are) will return 1 greater than it should.
-        args.max_split_depth + 1
+      cyclic: false,
+      max_split_depth: args.max_split_depth + depth_offset,
+      type_split_depths,
+      max_constructor_fanout: if args.max_constructor_fanout == 0 {
+        None
       } else {
-        args.max_split_depth
+        Some(args.max_constructor_fanout)
       },
       split_conditionals: !args.no_cond_split,
       single_rhs: args.single_rhs,
       irreducible_only: args.irreducible_only,
+      scrutinee_heuristic: args.scrutinee_heuristic,
+      search_strategy: args.search_strategy,
+      termination_order: args.termination_order,
+      custom_termination: None,
+      lemma_extraction: args.lemma_extraction,
+      extraction_cost: args.extraction_cost,
+      custom_extraction_cost: None,
+      generalize: args.generalize,
+      generalize_accumulators: args.generalize_accumulators,
+      detect_stuck: args.detect_stuck,
+      joint_induction: args
+        .joint_induction
+        .iter()
+        .map(|var| {
+          // Variable names are mangled the same way as in the parser
+          if mangle_names {
+            format!("cyclegg_{}", var)
+          } else {
+            var.clone()
+          }
+        })
+        .collect(),
+      bidirectional_defs: args
+        .bidirectional_defs
+        .iter()
+        .map(|name| {
+          // Function names are mangled the same way as variable names
+          if mangle_names {
+            format!("cyclegg_{}", name)
+          } else {
+            name.clone()
+          }
+        })
+        .collect(),
+      opaque_functions: args
+        .opaque_functions
+        .iter()
+        .map(|name| {
+          if mangle_names {
+            Symbol::from(format!("cyclegg_{}", name))
+          } else {
+            Symbol::from(name)
+          }
+        })
+        .collect(),
+      use_lemmas: !args.no_lemmas,
+      hypothesis_rewrites: args.hypothesis_rewrites,
+      split_equality_operands: args.split_equality_operands,
+      fold_nats: args.fold_nats,
+      prune_duplicates: args.prune_duplicates,
+      exhaustive_failures: args.exhaustive_failures,
+      verify_lemmas: args.verify_lemmas,
+      quickcheck_trials: args.quickcheck_trials,
       timeout: if args.timeout == 0 {
         None
       } else {
         Some(args.timeout)
       },
+      max_egraph_nodes: args.max_egraph_nodes,
+      max_iterations: args.max_iterations,
+      max_lemmas_per_goal: if args.max_lemmas_per_goal == 0 {
+        None
+      } else {
+        Some(args.max_lemmas_per_goal)
+      },
+      max_lemma_size: if args.max_lemma_size == 0 {
+        None
+      } else {
+        Some(args.max_lemma_size)
+      },
+      max_lemma_vars: if args.max_lemma_vars == 0 {
+        None
+      } else {
+        Some(args.max_lemma_vars)
+      },
+      max_total_splits: if args.max_total_splits == 0 {
+        None
+      } else {
+        Some(args.max_total_splits)
+      },
+      max_ite_scrutinees: if args.max_ite_scrutinees == 0 {
+        None
+      } else {
+        Some(args.max_ite_scrutinees)
+      },
+      scheduler: args.scheduler,
+      rewrite_priorities: HashMap::new(),
+      incremental_saturation: args.incremental_saturation,
+      memoize_termination_checks: true,
+      parallel: args.parallel,
+      explanations: args.explanations
+        || emit_proofs
+        || args.record_proof
+        || args.save_proof_script
+        || args.save_coq_proof,
       log_level: args.log_level.parse().unwrap(),
       save_graphs: args.save_graphs,
+      graph_format: args.graph_format,
+      graph_dir: args.graph_dir.clone(),
+      export_json: args.export_json,
       save_results: args.save_results,
       emit_proofs,
       verbose: args.verbose,
       verbose_proofs: args.verbose_proofs,
+      record_proof: args.record_proof,
+      save_proof_script: args.save_proof_script,
+      save_coq_proof: args.save_coq_proof,
       output_directory: args.output_directory.clone(),
       proofs_directory: args.proofs_directory.clone(),
       mangle_names,
@@ -136,16 +572,264 @@ impl Config {
     }
   }
 
+  /// Maximum split depth for variables of the given datatype
+  pub fn max_split_depth_for(&self, datatype: &str) -> usize {
+    *self
+      .type_split_depths
+      .get(datatype)
+      .unwrap_or(&self.max_split_depth)
+  }
+
+  /// Priority of the rewrite with the given name: that of the longest key in `rewrite_priorities`
+  /// it starts with, or 0 if there is none (so `"lemma-"` covers all lemmas, and `"(add "` all equations of add)
+  pub fn rewrite_priority(&self, name: &str) -> i32 {
+    self
+      .rewrite_priorities
+      .iter()
+      .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+      .max_by_key(|(prefix, _)| prefix.len())
+      .map_or(0, |(_, priority)| *priority)
+  }
+
   pub fn is_cyclic(&self) -> bool {
-    *self.cyclic_mode.lock().unwrap()
+    self.cyclic
+  }
+
+  pub fn with_cyclic(mut self, cyclic: bool) -> Self {
+    self.cyclic = cyclic;
+    self
+  }
+
+  pub fn with_max_split_depth(mut self, max_split_depth: usize) -> Self {
+    self.max_split_depth = max_split_depth;
+    self
+  }
+
+  pub fn with_max_constructor_fanout(mut self, max_constructor_fanout: Option<usize>) -> Self {
+    self.max_constructor_fanout = max_constructor_fanout;
+    self
+  }
+
+  pub fn with_joint_induction(mut self, joint_induction: Vec<String>) -> Self {
+    self.joint_induction = joint_induction;
+    self
+  }
+
+  pub fn with_bidirectional_defs(mut self, bidirectional_defs: Vec<String>) -> Self {
+    self.bidirectional_defs = bidirectional_defs;
+    self
+  }
+
+  pub fn with_opaque_functions(mut self, opaque_functions: HashSet<Symbol>) -> Self {
+    self.opaque_functions = opaque_functions;
+    self
   }
 
-  pub fn set_cyclic(&self, cyclic: bool) {
-    *self.cyclic_mode.lock().unwrap() = cyclic;
+  pub fn with_use_lemmas(mut self, use_lemmas: bool) -> Self {
+    self.use_lemmas = use_lemmas;
+    self
+  }
+
+  pub fn with_extraction_cost(mut self, extraction_cost: ExtractionCost) -> Self {
+    self.extraction_cost = extraction_cost;
+    self
+  }
+
+  pub fn with_custom_extraction_cost(mut self, cost: CustomCost) -> Self {
+    self.custom_extraction_cost = Some(cost);
+    self
+  }
+
+  pub fn with_search_strategy(mut self, search_strategy: SearchStrategy) -> Self {
+    self.search_strategy = search_strategy;
+    self
+  }
+
+  pub fn with_termination_order(mut self, termination_order: TerminationOrder) -> Self {
+    self.termination_order = termination_order;
+    self
+  }
+
+  pub fn with_custom_termination(mut self, termination: CustomTermination) -> Self {
+    self.custom_termination = Some(termination);
+    self
+  }
+
+  pub fn with_timeout(mut self, timeout: Option<u64>) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  pub fn with_max_egraph_nodes(mut self, max_egraph_nodes: usize) -> Self {
+    self.max_egraph_nodes = max_egraph_nodes;
+    self
+  }
+
+  pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+    self.max_iterations = max_iterations;
+    self
+  }
+
+  pub fn with_incremental_saturation(mut self, incremental_saturation: bool) -> Self {
+    self.incremental_saturation = incremental_saturation;
+    self
+  }
+
+  pub fn with_memoize_termination_checks(mut self, memoize_termination_checks: bool) -> Self {
+    self.memoize_termination_checks = memoize_termination_checks;
+    self
+  }
+
+  pub fn with_parallel(mut self, parallel: bool) -> Self {
+    self.parallel = parallel;
+    self
+  }
+
+  pub fn with_explanations(mut self, explanations: bool) -> Self {
+    self.explanations = explanations;
+    self
+  }
+
+  pub fn with_exhaustive_failures(mut self, exhaustive_failures: bool) -> Self {
+    self.exhaustive_failures = exhaustive_failures;
+    self
+  }
+
+  pub fn with_generalize_accumulators(mut self, generalize_accumulators: bool) -> Self {
+    self.generalize_accumulators = generalize_accumulators;
+    self
+  }
+
+  pub fn with_detect_stuck(mut self, detect_stuck: bool) -> Self {
+    self.detect_stuck = detect_stuck;
+    self
+  }
+
+  pub fn with_verify_lemmas(mut self, verify_lemmas: bool) -> Self {
+    self.verify_lemmas = verify_lemmas;
+    self
+  }
+
+  pub fn with_quickcheck_trials(mut self, quickcheck_trials: usize) -> Self {
+    self.quickcheck_trials = quickcheck_trials;
+    self
+  }
+
+  pub fn with_max_lemma_size(mut self, max_lemma_size: Option<usize>) -> Self {
+    self.max_lemma_size = max_lemma_size;
+    self
+  }
+
+  pub fn with_max_lemma_vars(mut self, max_lemma_vars: Option<usize>) -> Self {
+    self.max_lemma_vars = max_lemma_vars;
+    self
+  }
+
+  pub fn with_max_total_splits(mut self, max_total_splits: Option<usize>) -> Self {
+    self.max_total_splits = max_total_splits;
+    self
+  }
+
+  pub fn with_max_ite_scrutinees(mut self, max_ite_scrutinees: Option<usize>) -> Self {
+    self.max_ite_scrutinees = max_ite_scrutinees;
+    self
+  }
+
+  /// Saturate the rewrites whose names start with `prefix` before those of lower priority
+  pub fn with_rewrite_priority(mut self, prefix: &str, priority: i32) -> Self {
+    self.rewrite_priorities.insert(prefix.to_string(), priority);
+    self
+  }
+
+  pub fn with_save_graphs(mut self, save_graphs: bool) -> Self {
+    self.save_graphs = save_graphs;
+    self
+  }
+
+  pub fn with_graph_format(mut self, graph_format: GraphFormat) -> Self {
+    self.graph_format = graph_format;
+    self
+  }
+
+  pub fn with_graph_dir(mut self, graph_dir: PathBuf) -> Self {
+    self.graph_dir = graph_dir;
+    self
+  }
+
+  pub fn with_log_level(mut self, log_level: Level) -> Self {
+    self.log_level = log_level;
+    self
+  }
+}
+
+impl Default for ProverConfig {
+  /// The same settings as running the binary with no flags
+  fn default() -> Self {
+    Self {
+      prop: None,
+      cyclic: false,
+      max_split_depth: 3,
+      type_split_depths: HashMap::new(),
+      max_constructor_fanout: None,
+      split_conditionals: true,
+      single_rhs: false,
+      irreducible_only: false,
+      scrutinee_heuristic: ScrutineeHeuristic::Fifo,
+      search_strategy: SearchStrategy::DepthFirst,
+      termination_order: TerminationOrder::Pointwise,
+      custom_termination: None,
+      lemma_extraction: LemmaExtraction::Exhaustive,
+      extraction_cost: ExtractionCost::AstSize,
+      custom_extraction_cost: None,
+      generalize: false,
+      generalize_accumulators: false,
+      detect_stuck: false,
+      joint_induction: vec![],
+      bidirectional_defs: vec![],
+      opaque_functions: HashSet::new(),
+      use_lemmas: true,
+      hypothesis_rewrites: false,
+      split_equality_operands: false,
+      fold_nats: false,
+      prune_duplicates: false,
+      exhaustive_failures: false,
+      verify_lemmas: false,
+      quickcheck_trials: 0,
+      timeout: None,
+      max_egraph_nodes: 10000,
+      max_iterations: 30,
+      max_lemmas_per_goal: None,
+      max_lemma_size: None,
+      max_lemma_vars: None,
+      max_total_splits: None,
+      max_ite_scrutinees: None,
+      scheduler: SchedulerKind::Backoff,
+      rewrite_priorities: HashMap::new(),
+      incremental_saturation: false,
+      memoize_termination_checks: true,
+      parallel: false,
+      explanations: false,
+      log_level: Level::ERROR,
+      save_graphs: false,
+      graph_format: GraphFormat::Png,
+      graph_dir: PathBuf::from("target"),
+      export_json: false,
+      save_results: false,
+      emit_proofs: false,
+      verbose: false,
+      verbose_proofs: false,
+      record_proof: false,
+      save_proof_script: false,
+      save_coq_proof: false,
+      output_directory: PathBuf::from("target"),
+      proofs_directory: PathBuf::from("target/proofs"),
+      mangle_names: false,
+      proof_comments: true,
+    }
   }
 }
 
 lazy_static! {
   pub static ref ARGS: Args = Args::parse();
-  pub static ref CONFIG: Config = Config::from_args(&ARGS);
+  pub static ref CONFIG: ProverConfig = ProverConfig::from_args(&ARGS);
 }
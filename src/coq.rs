@@ -0,0 +1,420 @@
+use egg::Symbol;
+use itertools::Itertools;
+use std::collections::HashSet;
+use symbolic_expressions::{parser, Sexp};
+
+use crate::ast::{Context, Defns, Env, Type, APPLY, ARROW};
+use crate::goal::{Goal, ProofTree};
+
+/// Identifiers of cyclegg programs that are keywords in Coq get a prime appended
+const COQ_KEYWORDS: &[&str] = &[
+  "as", "at", "cofix", "else", "end", "exists", "fix", "for", "forall", "fun", "if", "in", "let",
+  "match", "mod", "Prop", "return", "Set", "then", "Type", "using", "where", "with",
+];
+/// The name of the induction hypothesis, i.e. of the theorem itself in its `fix`
+const IH: &str = "IH";
+const TAB_WIDTH: usize = 2;
+
+/// Render a proof of goal as a Coq proof skeleton:
+/// the datatypes and functions of the goal, the theorem, and a proof by `fix`
+/// that destructs the same variables (and conditions) as the case splits in tree,
+/// closing every case by simplification, by rewriting with the induction hypothesis
+/// where the case used one of the lemmas created by the splits, and by reflexivity.
+///
+/// Saturation is far stronger than these tactics, so the skeleton is a starting point for a
+/// proof by hand rather than a proof: Coq may not accept the recursion of the functions or the proof
+/// as given, and the cases that reasoned about conditionals or premises typically need more work.
+/// Cases that are the same as another case, or were not proved, are admitted.
+pub fn to_coq(tree: &ProofTree, goal: &Goal) -> String {
+  let mut coq = String::new();
+  coq.push_str(&format!(
+    "(* {}: {} *)\n\n",
+    goal.name,
+    goal.eqs.iter().map(|eq| eq.to_string()).join(", ")
+  ));
+  coq.push_str(&datatype_definitions(goal.env, goal.global_context));
+  coq.push_str(&function_definitions(
+    goal.defns,
+    goal.env,
+    goal.global_context,
+  ));
+
+  // The statement: type variables, parameters, premises, and the equations
+  let mut type_vars = vec![];
+  for param in goal.params.iter() {
+    collect_type_vars(&goal.local_context[param].repr, goal.env, &mut type_vars);
+  }
+  let mut binders: Vec<String> = type_vars
+    .iter()
+    .map(|a| format!("{{{} : Type}}", a))
+    .collect();
+  binders.extend(goal.params.iter().map(|x| {
+    format!(
+      "({} : {})",
+      ident(x.as_str()),
+      ty(&goal.local_context[x].repr)
+    )
+  }));
+  let premises: Vec<String> = goal
+    .premises
+    .iter()
+    .map(|eq| format!("{} = {} -> ", term(&eq.lhs.sexp), term(&eq.rhs.sexp)))
+    .collect();
+  let conclusion = goal
+    .eqs
+    .iter()
+    .map(|eq| format!("{} = {}", term(&eq.lhs.sexp), term(&eq.rhs.sexp)))
+    .join(" /\\ ");
+  coq.push_str(&format!(
+    "Theorem {} : forall {}, {}{}.\nProof.\n",
+    ident(&goal.name),
+    binders.join(" "),
+    premises.concat(),
+    conclusion
+  ));
+
+  // The proof: recursion on the first variable split on, which is what the lemmas decrease
+  if let ProofTree::Split {
+    scrutinee,
+    guard: None,
+    ..
+  } = tree
+  {
+    if let Some(i) = goal.params.iter().position(|x| x.as_str() == scrutinee) {
+      push_line(
+        &mut coq,
+        1,
+        &format!("fix {} {}.", IH, type_vars.len() + i + 1),
+      );
+    }
+  }
+  // The type variables are bound first, as in the statement
+  let mut intros: Vec<String> = type_vars.iter().map(|a| a.to_string()).collect();
+  intros.extend(goal.params.iter().map(|x| ident(x.as_str())));
+  intros.extend((0..goal.premises.len()).map(|i| format!("H{}", i)));
+  if !intros.is_empty() {
+    push_line(&mut coq, 1, &format!("intros {}.", intros.join(" ")));
+  }
+  let mut admitted = false;
+  proof_script(tree, goal, 1, &mut coq, &mut admitted);
+  coq.push_str(if admitted { "Admitted.\n" } else { "Qed.\n" });
+  coq
+}
+
+/// The tactics for the proof of tree at the given indentation depth;
+/// admitted is set if any case is admitted
+fn proof_script(
+  tree: &ProofTree,
+  goal: &Goal,
+  depth: usize,
+  coq: &mut String,
+  admitted: &mut bool,
+) {
+  match tree {
+    ProofTree::Leaf { name, lemmas } => {
+      push_line(coq, depth, &format!("(* {} *)", name));
+      if goal.eqs.len() > 1 {
+        push_line(coq, depth, "repeat split.");
+      }
+      push_line(coq, depth, "simpl.");
+      for lemma in lemmas {
+        push_line(coq, depth, &format!("rewrite {}. (* {} *)", IH, lemma));
+      }
+      push_line(coq, depth, "reflexivity.");
+    }
+    ProofTree::Split {
+      name,
+      scrutinee,
+      guard,
+      branches,
+    } => {
+      push_line(coq, depth, &format!("(* {} *)", name));
+      // Coq expects the cases in the order the constructors are declared
+      let constructors = branches
+        .first()
+        .and_then(|(con_app, _)| datatype_constructors(con_app, goal.env, goal.global_context))
+        .unwrap_or_default();
+      let cases: Vec<(Vec<String>, Option<&ProofTree>)> = constructors
+        .iter()
+        .map(|con| {
+          let branch = branches
+            .iter()
+            .find(|(con_app, _)| constructor_and_fields(con_app).0 == con.as_str());
+          match branch {
+            Some((con_app, subproof)) => (constructor_and_fields(con_app).1, Some(subproof)),
+            None => {
+              let arity = goal.global_context[con].args_ret().0.len();
+              (vec!["_".to_string(); arity], None)
+            }
+          }
+        })
+        .collect();
+      let patterns = cases.iter().map(|(fields, _)| fields.join(" ")).join(" | ");
+      let destruct = match guard {
+        Some(guard) => match parser::parse_str(guard) {
+          Ok(condition) => format!(
+            "destruct {} as [{}] eqn:{}.",
+            term(&condition),
+            patterns,
+            ident(scrutinee)
+          ),
+          Err(_) => format!("destruct {} as [{}].", ident(scrutinee), patterns),
+        },
+        None => format!("destruct {} as [{}].", ident(scrutinee), patterns),
+      };
+      push_line(coq, depth, &destruct);
+      for (_, subproof) in cases.iter() {
+        push_line(coq, depth, "{");
+        match subproof {
+          Some(subproof) => proof_script(subproof, goal, depth + 1, coq, admitted),
+          None => {
+            push_line(coq, depth + 1, "admit.");
+            *admitted = true;
+          }
+        }
+        push_line(coq, depth, "}");
+      }
+    }
+    ProofTree::Duplicate { name, of } => {
+      push_line(coq, depth, &format!("(* {}: same as {} *)", name, of));
+      push_line(coq, depth, "admit.");
+      *admitted = true;
+    }
+    ProofTree::Open { name } => {
+      push_line(coq, depth, &format!("(* {}: not proved *)", name));
+      push_line(coq, depth, "admit.");
+      *admitted = true;
+    }
+  }
+}
+
+/// The constructor of a constructor application as recorded in a proof tree (e.g. `(S n_0)`),
+/// and the variables it is applied to
+fn constructor_and_fields(con_app: &str) -> (String, Vec<String>) {
+  let mut words = con_app
+    .trim_start_matches('(')
+    .trim_end_matches(')')
+    .split_whitespace()
+    .map(ident);
+  let con = words.next().unwrap_or_default();
+  (con, words.collect())
+}
+
+/// All constructors of the datatype of the constructor in con_app, in the order they are declared
+fn datatype_constructors(
+  con_app: &str,
+  env: &Env,
+  global_context: &Context,
+) -> Option<Vec<Symbol>> {
+  let (con, _) = constructor_and_fields(con_app);
+  let (_, ret) = global_context.get(&Symbol::from(con))?.args_ret();
+  let (_, cons) = env.get(&Symbol::from(ret.datatype().ok()?))?;
+  Some(cons.clone())
+}
+
+/// `Inductive` definitions of all datatypes, each after the datatypes its constructors use
+/// (mutually recursive datatypes are not supported)
+fn datatype_definitions(env: &Env, global_context: &Context) -> String {
+  let names: Vec<String> = env.keys().map(|dt| dt.to_string()).sorted().collect();
+  let order = dependency_order(&names, |dt| {
+    let mut used = vec![];
+    for con in env[&Symbol::from(dt)].1.iter() {
+      collect_names(&global_context[con].repr, &mut used);
+    }
+    used
+  });
+  let mut coq = String::new();
+  for dt in order {
+    let (type_vars, cons) = &env[&Symbol::from(&dt)];
+    let params: String = type_vars
+      .iter()
+      .map(|a| format!(" ({} : Type)", ident(a)))
+      .collect();
+    coq.push_str(&format!("Inductive {}{} : Type :=\n", ident(&dt), params));
+    for con in cons.iter() {
+      let con_ty = &global_context[con].repr;
+      push_line(
+        &mut coq,
+        1,
+        &format!("| {} : {}", ident(con.as_str()), ty(con_ty)),
+      );
+    }
+    coq.pop();
+    coq.push_str(".\n");
+    // The type parameters of constructors are inferred
+    if !type_vars.is_empty() {
+      let implicit = type_vars.iter().map(|a| ident(a)).join(" ");
+      for con in cons.iter() {
+        coq.push_str(&format!(
+          "Arguments {} {{{}}}.\n",
+          ident(con.as_str()),
+          implicit
+        ));
+      }
+    }
+    coq.push('\n');
+  }
+  coq
+}
+
+/// `Fixpoint` definitions of all functions by pattern matching on all of their arguments,
+/// each after the functions it calls (mutual recursion is not supported)
+fn function_definitions(defns: &Defns, env: &Env, global_context: &Context) -> String {
+  let names: Vec<String> = defns.keys().cloned().sorted().collect();
+  let order = dependency_order(&names, |name| {
+    let mut used = vec![];
+    for (_, value) in defns[name].iter() {
+      collect_names(value, &mut used);
+    }
+    used
+  });
+  let mut coq = String::new();
+  for name in order {
+    let fn_ty = match global_context.get(&Symbol::from(&name)) {
+      Some(fn_ty) => fn_ty,
+      None => continue,
+    };
+    let mut type_vars = vec![];
+    collect_type_vars(&fn_ty.repr, env, &mut type_vars);
+    let (arg_tys, ret_ty) = fn_ty.args_ret();
+    let args: Vec<String> = (0..arg_tys.len()).map(|i| format!("arg{}", i)).collect();
+    let binders: String = type_vars
+      .iter()
+      .map(|a| format!(" {{{} : Type}}", a))
+      .chain(
+        args
+          .iter()
+          .zip(arg_tys.iter())
+          .map(|(arg, arg_ty)| format!(" ({} : {})", arg, ty(&arg_ty.repr))),
+      )
+      .collect();
+    let cases = &defns[&name];
+    if args.is_empty() {
+      let value = cases
+        .first()
+        .map(|(_, value)| term(value))
+        .unwrap_or_default();
+      coq.push_str(&format!(
+        "Definition {} : {} :=\n",
+        ident(&name),
+        ty(&ret_ty.repr)
+      ));
+      push_line(&mut coq, 1, &format!("{}.", value));
+      coq.push('\n');
+      continue;
+    }
+    coq.push_str(&format!(
+      "Fixpoint {}{} : {} :=\n",
+      ident(&name),
+      binders,
+      ty(&ret_ty.repr)
+    ));
+    push_line(&mut coq, 1, &format!("match {} with", args.join(", ")));
+    for (patterns, value) in cases.iter() {
+      let patterns = match patterns {
+        Sexp::List(patterns) => patterns.iter().map(term).join(", "),
+        pattern => term(pattern),
+      };
+      push_line(&mut coq, 1, &format!("| {} => {}", patterns, term(value)));
+    }
+    push_line(&mut coq, 1, "end.");
+    coq.push('\n');
+  }
+  coq
+}
+
+/// Order names so that every name comes after the names it uses (as given by uses),
+/// except within cycles; otherwise names keep their order
+fn dependency_order<F: Fn(&str) -> Vec<String>>(names: &[String], uses: F) -> Vec<String> {
+  fn visit<F: Fn(&str) -> Vec<String>>(
+    name: &str,
+    names: &[String],
+    uses: &F,
+    visited: &mut HashSet<String>,
+    order: &mut Vec<String>,
+  ) {
+    if !visited.insert(name.to_string()) {
+      return;
+    }
+    for used in uses(name) {
+      if names.contains(&used) {
+        visit(&used, names, uses, visited, order);
+      }
+    }
+    order.push(name.to_string());
+  }
+  let mut visited = HashSet::new();
+  let mut order = vec![];
+  for name in names {
+    visit(name, names, &uses, &mut visited, &mut order);
+  }
+  order
+}
+
+/// All atoms of sexp
+fn collect_names(sexp: &Sexp, names: &mut Vec<String>) {
+  match sexp {
+    Sexp::String(s) => names.push(s.clone()),
+    Sexp::List(children) => children
+      .iter()
+      .for_each(|child| collect_names(child, names)),
+    Sexp::Empty => (),
+  }
+}
+
+/// The type variables of a type, i.e. the atoms that are not datatypes, in order of occurrence
+fn collect_type_vars(ty: &Sexp, env: &Env, type_vars: &mut Vec<String>) {
+  let mut names = vec![];
+  collect_names(ty, &mut names);
+  for name in names {
+    if name != ARROW && !env.contains_key(&Symbol::from(&name)) && !type_vars.contains(&name) {
+      type_vars.push(name);
+    }
+  }
+}
+
+/// A cyclegg term (or pattern) in Coq syntax
+fn term(sexp: &Sexp) -> String {
+  match sexp {
+    Sexp::String(s) => ident(s.trim_start_matches('?')),
+    Sexp::List(children) => {
+      // Partial applications are just applications in Coq
+      let children = match children.first() {
+        Some(Sexp::String(op)) if op == APPLY => &children[1..],
+        _ => &children[..],
+      };
+      format!("({})", children.iter().map(term).join(" "))
+    }
+    Sexp::Empty => String::new(),
+  }
+}
+
+/// A cyclegg type in Coq syntax
+fn ty(sexp: &Sexp) -> String {
+  let ty_repr = Type::new(sexp.clone());
+  match sexp {
+    Sexp::List(children) if children.first() == Some(&Sexp::String(ARROW.to_string())) => {
+      let (args, ret) = ty_repr.args_ret();
+      let mut tys: Vec<String> = args.iter().map(|arg| ty(&arg.repr)).collect();
+      tys.push(ty(&ret.repr));
+      format!("({})", tys.join(" -> "))
+    }
+    Sexp::List(children) => format!("({})", children.iter().map(ty).join(" ")),
+    Sexp::String(s) => ident(s),
+    Sexp::Empty => String::new(),
+  }
+}
+
+fn ident(name: &str) -> String {
+  if COQ_KEYWORDS.contains(&name) {
+    format!("{}'", name)
+  } else {
+    name.to_string()
+  }
+}
+
+fn push_line(coq: &mut String, depth: usize, line: &str) {
+  coq.push_str(&" ".repeat(depth * TAB_WIDTH));
+  coq.push_str(line);
+  coq.push('\n');
+}
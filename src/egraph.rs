@@ -9,18 +9,30 @@ type Denotation<L> = HashMap<Id, Vec<RecExpr<L>>>;
 pub fn get_all_expressions<L: Language, A: Analysis<L>>(
   egraph: &EGraph<L, A>,
   roots: Vec<Id>,
+) -> Denotation<L> {
+  get_expressions_up_to(egraph, roots, None)
+}
+
+/// Like `get_all_expressions`, but only with the expressions of at most max_size nodes (if any);
+/// since the subexpressions of such an expression are no larger, larger ones are dropped as soon as they are built,
+/// and never combined into expressions of the classes above
+pub fn get_expressions_up_to<L: Language, A: Analysis<L>>(
+  egraph: &EGraph<L, A>,
+  roots: Vec<Id>,
+  max_size: Option<usize>,
 ) -> Denotation<L> {
   let mut memo = HashMap::new();
   for root in roots {
-    collect_expressions(egraph, root, &mut memo);
+    collect_expressions(egraph, root, max_size, &mut memo);
   }
   memo
 }
 
-/// Compute the denotation of eclass ignoring cycles and store it in memo
+/// Compute the denotation of eclass ignoring cycles (and expressions larger than max_size) and store it in memo
 fn collect_expressions<L: Language, A: Analysis<L>>(
   egraph: &EGraph<L, A>,
   eclass: Id,
+  max_size: Option<usize>,
   memo: &mut Denotation<L>,
 ) {
   if memo.get(&eclass).is_some() {
@@ -43,7 +55,7 @@ fn collect_expressions<L: Language, A: Analysis<L>>(
         // Each products[i] stores the product of denotation sizes of all nodes from i+1 onwards
         let mut products: HashMap<Id, usize> = HashMap::new();
         for (i, c) in node.children().iter().enumerate() {
-          collect_expressions(egraph, *c, memo);
+          collect_expressions(egraph, *c, max_size, memo);
           products.insert(*c, 1);
           for j in 0..i {
             products
@@ -60,7 +72,9 @@ fn collect_expressions<L: Language, A: Analysis<L>>(
           // The formula is: k / (the product of all following denotation sizes) % this denotation size
           let lookup_id = |id: Id| k / products[&id] % memo[&id].len();
           let expr = node.join_recexprs(|id| memo.get(&id).unwrap()[lookup_id(id)].clone());
-          denotations.push(expr);
+          if max_size.is_none_or(|max| expr.as_ref().len() <= max) {
+            denotations.push(expr);
+          }
         }
       }
     }
@@ -68,10 +82,16 @@ fn collect_expressions<L: Language, A: Analysis<L>>(
   }
 }
 
-/// Remove node from egraph
+/// Remove node from egraph;
+/// the node must not be the only one in its class, since other nodes may still refer to the class
 pub fn remove_node<L: Language, A: Analysis<L>>(egraph: &mut EGraph<L, A>, node: &L) {
   for c in egraph.classes_mut() {
     c.nodes.retain(|n| n != node);
+    debug_assert!(
+      !c.nodes.is_empty(),
+      "removed the last node of e-class {}",
+      c.id
+    );
   }
 }
 
@@ -127,7 +147,7 @@ where
     egraph: &EGraph<L, N>,
     eclass: Id,
     limit: usize,
-  ) -> Option<SearchMatches<L>> {
+  ) -> Option<SearchMatches<'_, L>> {
     // Use the underlying searcher first
     let matches = self
       .searcher
@@ -154,4 +174,8 @@ where
   fn vars(&self) -> Vec<Var> {
     self.searcher.vars()
   }
+
+  fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
+    self.searcher.get_pattern_ast()
+  }
 }
@@ -66,7 +66,6 @@ struct LemmaInfo {
 pub fn goal_name_to_filename(goal_name: &str) -> String {
   goal_name
     .split('_')
-    .into_iter()
     .map(|chunk| {
       let mut chars_iter = chunk.chars();
       let mut new_string = String::new();
@@ -79,6 +78,7 @@ pub fn goal_name_to_filename(goal_name: &str) -> String {
     .collect()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn explain_top(
   filename: &str,
   goal: &str,
@@ -197,7 +197,7 @@ fn add_proof_types_and_stub(
   goal: &str,
   lhs: &Sexp,
   rhs: &Sexp,
-  args: &Vec<(String, String)>,
+  args: &[(String, String)],
 ) -> String {
   let mut str_explanation = String::new();
 
@@ -419,16 +419,35 @@ fn explain_proof(
       }
       str_explanation
     }
+    ProofTerm::Duplicate(orig_goal, renaming) => {
+      // Bind the variables of the original goal to ours and reuse its proof
+      for (orig_var, var) in renaming {
+        add_indentation(&mut str_explanation, proof_depth);
+        str_explanation.push_str(&format!("let {} = {} in", orig_var, var));
+        str_explanation.push('\n');
+      }
+      str_explanation.push_str(&explain_proof(
+        proof_depth + 1,
+        orig_goal,
+        state,
+        top_goal_name,
+        lemma_map,
+      ));
+      str_explanation
+    }
   }
 }
 
 fn explain_goal(
   depth: usize,
-  (explanation, local_context): &mut (Explanation<SymbolLang>, Context),
+  (explanation, local_context): &mut (Option<Explanation<SymbolLang>>, Context),
   top_goal_name: &str,
   lemma_map: &mut HashMap<String, LemmaInfo>,
 ) -> String {
   let mut str_explanation: String = String::new();
+  let explanation = explanation
+    .as_mut()
+    .expect("emitting proofs requires explanations");
   let flat_terms = explanation.make_flat_explanation();
   let next_flat_term_iter = flat_terms.iter().skip(1);
   let flat_term_and_next = flat_terms.iter().zip_longest(next_flat_term_iter);
@@ -789,10 +808,9 @@ fn extract_ih_arguments(rule_name: &str) -> Vec<String> {
     .strip_prefix(IH_EQUALITY_PREFIX)
     .unwrap()
     .split(',')
-    .into_iter()
     .map(|pair| {
       // println!("{}", pair);
-      let args: Vec<&str> = pair.split('=').into_iter().collect();
+      let args: Vec<&str> = pair.split('=').collect();
       // This should just be x=(Constructor c1 c2 c3)
       assert_eq!(args.len(), 2);
       args[1].to_string()
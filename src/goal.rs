@@ -1,11 +1,21 @@
 use colored::Colorize;
 use egg::*;
-use log::warn;
+use itertools::Itertools;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::collections::{hash_map::Entry, HashMap, VecDeque};
 use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use symbolic_expressions::{parser, Sexp};
+use tracing::{debug, info, info_span, trace, warn, Level};
 
 use crate::ast::*;
 use crate::config::*;
@@ -16,25 +26,81 @@ use crate::parser::RawEquation;
 pub type Eg = EGraph<SymbolLang, CanonicalFormAnalysis>;
 pub type Rw = Rewrite<SymbolLang, CanonicalFormAnalysis>;
 
-/// A special scrutinee name used to signal that case split bound has been exceeded
-const BOUND_EXCEEDED: &str = "__";
+/// How deeply nested the random values used to test a goal can be
+const QUICKCHECK_DEPTH: usize = 4;
 pub const LEMMA_PREFIX: &str = "lemma-";
+pub const LIBRARY_PREFIX: &str = "library-";
+pub const HYPOTHESIS_PREFIX: &str = "hypothesis-";
+pub const REVERSED_PREFIX: &str = "reversed-";
 pub const IH_EQUALITY_PREFIX: &str = "ih-equality-"; // TODO: remove
 
-/// Condition that checks whether it is sound to apply a lemma
+/// Compares a term with another in the subterm order (see `is_subterm`)
+type Compare = fn(&Expr, &Expr) -> StructuralComparison;
+
+/// Compares the argument at one position of a lemma application
+/// with the original value at another position (see `Soundness::smaller_tuple_by`)
+type CompareAt<'c> = dyn FnMut(usize, usize) -> StructuralComparison + 'c;
+
+/// Memoized parts of the termination checks of lemmas (see `Soundness::compare_arg`),
+/// together with the e-class of the original value each was computed for
+#[derive(Default)]
+struct TerminationMemo {
+  /// The original value of each lemma variable (None if it is not canonical)
+  values: HashMap<Symbol, (Id, Option<Expr>)>,
+  /// How each variable compares with the original value of each lemma variable,
+  /// keyed on the lemma variable and the variable
+  var_comparisons: HashMap<(Symbol, Symbol), (Id, Option<StructuralComparison>)>,
+}
+
+thread_local! {
+  /// The termination memo of the runner searching on this thread, if any.
+  /// The e-graph does not change while a runner searches it, but anything added to it,
+  /// such as the fresh variables of a split, can change the values of lemma variables,
+  /// so the memo is emptied before every iteration of the runner (see `Saturation::run`),
+  /// and is not used (None) outside of it.
+  static TERMINATION_MEMO: RefCell<Option<TerminationMemo>> = const { RefCell::new(None) };
+}
+
+/// Condition that checks whether it is sound to apply a lemma.
+///
+/// A lemma is created from a goal G with variables xs and is only used in the descendants of G.
+/// Applying it with arguments as in a descendant D uses G at as, so it is sound
+/// if as is strictly smaller than the values that D assigns to xs,
+/// because then the cycle through G descends along a well-founded order.
+/// Those values are the canonical forms of the e-classes xs were in *when the lemma was created*:
+/// the e-graph of D extends that of G, so these classes still exist in D
+/// and contain the constructor applications xs have been split into since.
+/// (Comparing with the variables D currently splits on instead would be unsound,
+/// since they are already smaller than xs; `examples/unsound-ih.ceg` has goals
+/// that such a comparison would prove.)
+/// A lemma can match its own right-hand side within the same saturation,
+/// but every application is compared with these fixed values, not with the previous application,
+/// so it still only applies to strictly smaller instances of G.
 #[derive(Clone)]
 pub struct Soundness {
   /// A substitution from lemma's free variables
-  /// to the original e-classes these variables came from
+  /// to the original e-classes these variables came from,
+  /// captured when the lemma is created
+  /// (only variables of datatypes: these are the ones that decrease along a cycle)
   pub free_vars: IdSubst,
+  /// The lemma's free variables of function type or of type `Int`, which are never case-split,
+  /// so they do not take part in the termination check
+  /// and the lemma holds for any functions (or integers) passed in for them
+  pub higher_order_vars: IdSubst,
   /// All premises that must hold for this lemma to apply,
   /// expressed in terms of the free variables
   pub premises: Vec<Equation>,
+  /// The order in which the lemma's arguments must be smaller
+  pub order: TerminationOrder,
+  /// A check used instead of order, if any (see `CustomTermination`)
+  pub custom_order: Option<CustomTermination>,
+  /// Whether every application is cross-checked against `reference_subterm` (see `verify_lemmas`)
+  pub verify: bool,
 }
 
 impl Soundness {
   /// Substitution as a string, for debugging purposes
-  fn _pretty_subst(subst: &Vec<(Symbol, Expr, Expr)>) -> String {
+  fn pretty_subst(subst: &[(Symbol, Expr, Expr)]) -> String {
     let strings: Vec<String> = subst
       .iter()
       .map(|(x, orig, new)| {
@@ -50,12 +116,36 @@ impl Soundness {
   }
 
   /// Are the canonical forms of the e-classes in new_subst strictly smaller than those in orig_subst?
-  /// For now implements a sound but incomplete measure,
+  fn smaller_tuple(&self, triples: &[(Symbol, Expr, Expr)]) -> bool {
+    if let Some(custom) = self.custom_order {
+      return custom(triples);
+    }
+    self.smaller_tuple_by(triples, is_subterm)
+  }
+
+  /// `smaller_tuple` with the forms compared by compare
+  fn smaller_tuple_by(&self, triples: &[(Symbol, Expr, Expr)], compare: Compare) -> bool {
+    self.smaller_by(triples.len(), &mut |i, j| {
+      compare(&triples[j].2, &triples[i].1)
+    })
+  }
+
+  /// Are n arguments smaller than the original values in this condition's order,
+  /// where compare(i, j) compares the j-th argument with the i-th original value?
+  fn smaller_by(&self, n: usize, compare: &mut CompareAt) -> bool {
+    match self.order {
+      TerminationOrder::Pointwise => Soundness::smaller_pointwise(n, compare),
+      TerminationOrder::Lexicographic => Soundness::smaller_lexicographic(n, compare),
+      TerminationOrder::Multiset => Soundness::smaller_multiset(n, compare),
+    }
+  }
+
+  /// A sound but incomplete measure,
   /// where all forms need to be no larger, and at least one has to be strictly smaller.
-  fn smaller_tuple(&self, triples: &Vec<(Symbol, Expr, Expr)>) -> bool {
+  fn smaller_pointwise(n: usize, compare: &mut CompareAt) -> bool {
     let mut has_strictly_smaller = false;
-    for (_, orig, new) in triples {
-      match is_subterm(&new, &orig) {
+    for i in 0..n {
+      match compare(i, i) {
         StructuralComparison::LT => {
           has_strictly_smaller = true;
         }
@@ -68,9 +158,149 @@ impl Soundness {
     has_strictly_smaller
   }
 
+  /// Lexicographic extension of the subterm order:
+  /// the first form that is not equal to the original has to be strictly smaller,
+  /// and later forms are unconstrained.
+  fn smaller_lexicographic(n: usize, compare: &mut CompareAt) -> bool {
+    for i in 0..n {
+      match compare(i, i) {
+        StructuralComparison::LT => return true,
+        StructuralComparison::Incomparable => return false,
+        StructuralComparison::LE => (),
+      }
+    }
+    false
+  }
+
+  /// Multiset extension of the subterm order:
+  /// after cancelling forms that occur in both the original and the new multiset,
+  /// something must remain of the original, and every remaining new form
+  /// must be strictly smaller than some remaining original form.
+  /// Unlike the other orders, this allows arguments to swap positions.
+  fn smaller_multiset(n: usize, compare: &mut CompareAt) -> bool {
+    let mut origs: Vec<usize> = (0..n).collect();
+    let mut news: Vec<usize> = vec![];
+    for j in 0..n {
+      // (a form is equal to another when it is not larger and not strictly smaller)
+      match origs
+        .iter()
+        .position(|&i| compare(i, j) == StructuralComparison::LE)
+      {
+        Some(k) => {
+          origs.remove(k);
+        }
+        None => news.push(j),
+      }
+    }
+    !origs.is_empty()
+      && news.iter().all(|&j| {
+        origs
+          .iter()
+          .any(|&i| compare(i, j) == StructuralComparison::LT)
+      })
+  }
+
+  /// How the argument in e-class new_id compares with the original value of lemma variable x,
+  /// in e-class orig_id, or None if either is not canonical;
+  /// while a runner is searching, the values and the comparisons of variables are memoized (see `TERMINATION_MEMO`)
+  fn compare_arg(egraph: &Eg, x: Symbol, orig_id: Id, new_id: Id) -> Option<StructuralComparison> {
+    let orig_id = egraph.find(orig_id);
+    let var = match &egraph[new_id].data {
+      CanonicalForm::Var(node) => Some(node.op),
+      CanonicalForm::Const(_) => None,
+      // Not canonical, whatever the value
+      _ => return None,
+    };
+    let compare = |orig: Option<&Expr>| {
+      let new = CanonicalFormAnalysis::extract_canonical(egraph, new_id)?;
+      Some(is_subterm(&new, orig?))
+    };
+    TERMINATION_MEMO.with(|memo| {
+      let mut memo = memo.borrow_mut();
+      let TerminationMemo {
+        values,
+        var_comparisons,
+      } = match memo.as_mut() {
+        Some(memo) => memo,
+        None => return compare(CanonicalFormAnalysis::extract_canonical(egraph, orig_id).as_ref()),
+      };
+      if let Some((id, res)) = var.and_then(|var| var_comparisons.get(&(x, var))) {
+        if *id == orig_id {
+          return *res;
+        }
+      }
+      if values.get(&x).is_none_or(|(id, _)| *id != orig_id) {
+        let value = CanonicalFormAnalysis::extract_canonical(egraph, orig_id);
+        values.insert(x, (orig_id, value));
+      }
+      let res = compare(values[&x].1.as_ref());
+      if let Some(var) = var {
+        var_comparisons.insert((x, var), (orig_id, res));
+      }
+      res
+    })
+  }
+
+  /// Are the arguments of the lemma under subst smaller than the original values of its variables?
+  /// (Like `smaller_tuple` on `triples`, but comparing the e-classes with `compare_arg`,
+  /// so that only a custom order needs the forms themselves.)
+  fn smaller_args(&self, egraph: &Eg, subst: &Subst) -> bool {
+    let args: Vec<(Symbol, Id, Id)> = self
+      .free_vars
+      .iter()
+      .map(|(x, orig_id)| (*x, *orig_id, *subst.get(to_wildcard(x)).unwrap()))
+      .collect();
+    // All actual arguments must be canonical in order to be comparable to the formals,
+    // and so must the formals
+    let diagonal: Option<Vec<StructuralComparison>> = args
+      .iter()
+      .map(|(x, orig_id, new_id)| Soundness::compare_arg(egraph, *x, *orig_id, *new_id))
+      .collect();
+    let diagonal = match diagonal {
+      Some(diagonal) => diagonal,
+      None => return false,
+    };
+    self.smaller_by(args.len(), &mut |i, j| {
+      if i == j {
+        diagonal[i]
+      } else {
+        let (x, orig_id, _) = args[i];
+        Soundness::compare_arg(egraph, x, orig_id, args[j].2).unwrap()
+      }
+    })
+  }
+
+  /// The triples (variable, old canonical form, new canonical form) of the lemma's free variables under subst,
+  /// or None if some form is not canonical
+  fn triples(&self, egraph: &Eg, subst: &Subst) -> Option<Vec<(Symbol, Expr, Expr)>> {
+    self
+      .free_vars
+      .iter()
+      .map(|(x, orig_id)| {
+        let v = to_wildcard(x);
+        // Subst must have all lemma variables defined
+        // because we did the filtering when creating the lemma
+        let new_id = subst.get(v).unwrap();
+        // If the actual argument of the lemma is not canonical, give up
+        let new_canonical = CanonicalFormAnalysis::extract_canonical(egraph, *new_id)?;
+        // Same for the original argument, i.e. the value of the variable in this e-graph
+        // (which is the constructor application it was split into if it has been split since);
+        // it might not be canonical if it's inconsistent, in which case there's no point applying any lemmas
+        let orig_canonical = CanonicalFormAnalysis::extract_canonical(egraph, *orig_id)?;
+        Some((*x, orig_canonical, new_canonical))
+      })
+      .collect()
+  }
+
+  /// `smaller_tuple` with the forms compared by `reference_subterm` instead;
+  /// a custom order is trusted, so it is not cross-checked
+  fn reference_smaller_tuple(&self, triples: &[(Symbol, Expr, Expr)]) -> bool {
+    self.custom_order.is_some() || self.smaller_tuple_by(triples, reference_subterm)
+  }
+
   /// Apply subst to self.premise (if any)
   /// and check whether the resulting terms are equal in the egraph
-  fn check_premise(premise: &Equation, triples: &Vec<(Symbol, Expr, Expr)>, egraph: &Eg) -> bool {
+  fn check_premise(premise: &Equation, triples: &[(Symbol, Expr, Expr)], egraph: &Eg) -> bool {
     // let info = SmallerVar::pretty_subst(triples);
     // println!("checking premise {} = {} for {}", premise.lhs.sexp, premise.rhs.sexp, info);
 
@@ -112,7 +342,7 @@ impl Soundness {
   }
 
   /// Check all of the premises of this condition
-  fn check_premises(&self, triples: &Vec<(Symbol, Expr, Expr)>, egraph: &Eg) -> bool {
+  fn check_premises(&self, triples: &[(Symbol, Expr, Expr)], egraph: &Eg) -> bool {
     self
       .premises
       .iter()
@@ -123,32 +353,39 @@ impl Soundness {
 impl SearchCondition<SymbolLang, CanonicalFormAnalysis> for Soundness {
   /// Returns true if the substitution is into a smaller tuple of variables
   fn check(&self, egraph: &Eg, _eclass: Id, subst: &Subst) -> bool {
-    // Create an iterator over triples: (variable, old canonical form, new canonical form)
-    let triples = self
-      .free_vars
-      .iter()
-      .map(|(x, orig_id)| {
-        let v = to_wildcard(x);
-        // Subst must have all lemma variables defined
-        // because we did the filtering when creating SmallerVars
-        let new_id = subst.get(v).unwrap();
-        // If the actual argument of the lemma is not canonical, give up
-        let new_canonical = CanonicalFormAnalysis::extract_canonical(egraph, *new_id)?;
-        // Same for the original argument:
-        // it might not be canonical if it's inconsistent, in which case there's no point applying any lemmas
-        let orig_canonical = CanonicalFormAnalysis::extract_canonical(egraph, *orig_id)?;
-        Some((x.clone(), orig_canonical, new_canonical))
-      })
-      .collect::<Option<Vec<(Symbol, Expr, Expr)>>>();
-
-    match triples {
+    if self.custom_order.is_none() && self.premises.is_empty() && !self.verify {
+      // Only the order of the arguments matters, which does not need their forms
+      return self.smaller_args(egraph, subst);
+    }
+    match self.triples(egraph, subst) {
       None => false, // All actual arguments must be canonical in order to be comparable to the formals
-      Some(triples) => {
+      Some(mut triples) => {
         // Check that the actuals are smaller than the formals
         // and that the actual premise holds
         let terminates = self.smaller_tuple(&triples);
+        if terminates && !self.premises.is_empty() && !self.higher_order_vars.is_empty() {
+          // The premises may also mention the functions, so we need those as expressions too
+          // (they need not be canonical, since they are only substituted into the premises)
+          let extractor = Extractor::new(egraph, AstSize);
+          for (x, orig_id) in self.higher_order_vars.iter() {
+            let new_id = subst.get(to_wildcard(x)).unwrap();
+            triples.push((
+              *x,
+              extractor.find_best(*orig_id).1,
+              extractor.find_best(*new_id).1,
+            ));
+          }
+        }
         // Let's not check the premises if the termination check doesn't hold:
         let sound = terminates && self.check_premises(&triples, egraph);
+        // (the functions pushed for the premises were never part of the termination check)
+        if sound && self.verify {
+          assert!(
+            self.reference_smaller_tuple(&triples[..self.free_vars.len()]),
+            "termination check accepted {}, but the reference check rejects it",
+            Soundness::pretty_subst(&triples)
+          );
+        }
         // println!("trying IH with subst {}; checks: {} {}", SmallerVar::pretty_subst(&triples), terminates, sound);
         sound
       }
@@ -156,6 +393,64 @@ impl SearchCondition<SymbolLang, CanonicalFormAnalysis> for Soundness {
   }
 }
 
+/// How many matches of a lemma `verify_lemma` checks
+const VERIFIED_MATCHES: usize = 100;
+
+/// Cross-check the termination check of a lemma of goal against `reference_subterm`:
+/// for (up to `VERIFIED_MATCHES` of) the matches of the lemma's lhs in the goal's e-graph
+/// under which the lemma applies, are its arguments really smaller?
+/// A debugging aid: returns false if the termination check accepted an argument the reference rejects;
+/// rewrites that are not lemmas of goal have nothing to check.
+pub fn verify_lemma(lemma: &Rw, goal: &Goal) -> bool {
+  let lemma = match goal.lemma_rewrites.get(lemma.name.as_str()) {
+    Some(lemma) => lemma,
+    None => return true,
+  };
+  let condition = &lemma.condition;
+  lemma
+    .lhs
+    .search(&goal.egraph)
+    .iter()
+    .flat_map(|m| m.substs.iter().map(move |subst| (m.eclass, subst)))
+    .take(VERIFIED_MATCHES)
+    .filter(|(eclass, subst)| condition.check(&goal.egraph, *eclass, subst))
+    .all(|(_, subst)| {
+      condition
+        .triples(&goal.egraph, subst)
+        .is_some_and(|triples| condition.reference_smaller_tuple(&triples))
+    })
+}
+
+/// A lemma created from a goal, together with what it was created from
+#[derive(Clone)]
+struct Lemma {
+  lhs: Pat,
+  rhs: Pat,
+  condition: Soundness,
+  /// The rewrite `lhs => rhs` that applies under condition
+  rewrite: Rw,
+}
+
+impl Lemma {
+  fn new(name: String, lhs: Pat, rhs: Pat, condition: Soundness) -> Self {
+    let rewrite = Rewrite::new(
+      name,
+      ConditionalSearcher {
+        condition: condition.clone(),
+        searcher: lhs.clone(),
+      },
+      rhs.clone(),
+    )
+    .unwrap();
+    Self {
+      lhs,
+      rhs,
+      condition,
+      rewrite,
+    }
+  }
+}
+
 /// The set of constructors in an e-class.
 /// The order of variants is important: since we use the derived order during the merge.
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone)]
@@ -175,7 +470,13 @@ pub enum CanonicalForm {
 }
 
 #[derive(Default, Clone)]
-pub struct CanonicalFormAnalysis {}
+pub struct CanonicalFormAnalysis {
+  /// Whether to evaluate `add` and `mul` on closed natural numbers
+  pub fold_nats: bool,
+  /// Whether constructors are injective and distinct, as they are under definitional equality
+  /// (but not necessarily under the equivalences of a goal, see `Goal::with_equivalences`)
+  pub free_constructors: bool,
+}
 
 impl CanonicalFormAnalysis {
   /// Extract the canonical form of an e-class if it exists.
@@ -202,6 +503,136 @@ impl CanonicalFormAnalysis {
     }
   }
 
+  /// The value of e-class id if its canonical form is a closed natural number `(S ... (S Z))`
+  fn nat_value(egraph: &Eg, id: Id) -> Option<usize> {
+    let mut value = 0;
+    let mut id = egraph.find(id);
+    // The bound guards against cycles of successors that have not been detected yet
+    while value <= egraph.total_number_of_nodes() {
+      match &egraph[id].data {
+        CanonicalForm::Const(n) if n.op.as_str() == *ZERO => return Some(value),
+        CanonicalForm::Const(n) if n.op.as_str() == *SUCC && n.children.len() == 1 => {
+          value += 1;
+          id = egraph.find(n.children[0]);
+        }
+        _ => return None,
+      }
+    }
+    None
+  }
+
+  /// The closed natural number with the given value
+  fn nat_expr(value: usize) -> Expr {
+    let mut expr: Expr = RecExpr::default();
+    let mut id = expr.add(SymbolLang::leaf(&*ZERO));
+    for _ in 0..value {
+      id = expr.add(SymbolLang::new(&*SUCC, vec![id]));
+    }
+    expr
+  }
+
+  /// Evaluate `add` and `mul` applications in eclass id whose arguments are closed natural numbers.
+  /// Since e-nodes are added bottom-up, nested closed arithmetic is folded as it is added;
+  /// we don't revisit applications whose arguments become closed later (e.g. by a case split),
+  /// because the reductions take care of those.
+  fn fold_nats(egraph: &mut Eg, id: Id) {
+    let values: Vec<(SymbolLang, usize)> = egraph[id]
+      .nodes
+      .iter()
+      .filter_map(|n| {
+        if n.children.len() != 2 {
+          return None;
+        }
+        let x = Self::nat_value(egraph, n.children[0])?;
+        let y = Self::nat_value(egraph, n.children[1])?;
+        if n.op.as_str() == *ADD {
+          Some((n.clone(), x + y))
+        } else if n.op.as_str() == *MUL {
+          Some((n.clone(), x * y))
+        } else {
+          None
+        }
+      })
+      .collect();
+    for (n, value) in values {
+      let literal = Self::nat_expr(value);
+      let literal_id = egraph.add_expr(&literal);
+      if egraph.find(literal_id) != egraph.find(id) {
+        egraph.union_trusted(id, literal_id, format!("fold-nats {} = {}", n.op, literal));
+      }
+    }
+  }
+
+  /// The value of e-class id if its canonical form is an integer literal
+  fn int_value(egraph: &Eg, id: Id) -> Option<i64> {
+    match &egraph[id].data {
+      CanonicalForm::Const(n) if n.children.is_empty() => int_literal(n.op.as_str()),
+      _ => None,
+    }
+  }
+
+  /// The literal that integer operation n evaluates to, if its arguments are integer literals
+  /// (and the result does not overflow); comparisons evaluate to `True` or `False`
+  fn eval_int_operation(egraph: &Eg, n: &SymbolLang) -> Option<String> {
+    if n.children.len() != 2 {
+      return None;
+    }
+    let x = Self::int_value(egraph, n.children[0])?;
+    let y = Self::int_value(egraph, n.children[1])?;
+    let boolean = |b: bool| {
+      if b {
+        TRUE.to_string()
+      } else {
+        FALSE.to_string()
+      }
+    };
+    match n.op.as_str() {
+      INT_ADD => x.checked_add(y).map(|v| v.to_string()),
+      INT_SUB => x.checked_sub(y).map(|v| v.to_string()),
+      INT_MUL => x.checked_mul(y).map(|v| v.to_string()),
+      INT_LT => Some(boolean(x < y)),
+      INT_LE => Some(boolean(x <= y)),
+      INT_GT => Some(boolean(x > y)),
+      INT_GE => Some(boolean(x >= y)),
+      _ => None,
+    }
+  }
+
+  /// Evaluate integer operations on integer literals:
+  /// the one in e-class id if it has just been added, and, if id is a literal, those that take it as an argument.
+  /// Unlike `fold_nats`, this is always on, since there are no reductions for integer literals;
+  /// this is also why we have to revisit the applications whose arguments become literals later.
+  /// (Scanning every e-node of every class that changes would make large saturations quadratic.)
+  fn fold_ints(egraph: &mut Eg, id: Id) {
+    let mut candidates: Vec<(SymbolLang, Id)> = vec![];
+    if let [n] = egraph[id].nodes.as_slice() {
+      if is_int_operation(&n.op) {
+        candidates.push((n.clone(), id));
+      }
+    }
+    if Self::int_value(egraph, id).is_some() {
+      candidates.extend(
+        egraph[id]
+          .parents()
+          .filter(|(n, _)| is_int_operation(&n.op))
+          .map(|(n, parent)| (n.clone(), parent)),
+      );
+    }
+    let values: Vec<(SymbolLang, Id, String)> = candidates
+      .into_iter()
+      .filter_map(|(n, class)| {
+        let value = Self::eval_int_operation(egraph, &n)?;
+        Some((n, class, value))
+      })
+      .collect();
+    for (n, class, value) in values {
+      let literal_id = egraph.add(SymbolLang::leaf(&value));
+      if egraph.find(literal_id) != egraph.find(class) {
+        egraph.union_trusted(class, literal_id, format!("fold-ints {} = {}", n.op, value));
+      }
+    }
+  }
+
   /// Check if the canonical form of eclass id (whose constructor node is n)
   /// has a cycle back to itself made up of only constructors.
   /// This means that the eclass represents an infinite term.
@@ -243,7 +674,7 @@ impl Analysis<SymbolLang> for CanonicalFormAnalysis {
     // (and remember both constructors, we'll need them to build an explanation)
     if let CanonicalForm::Const(n1) = to {
       if let CanonicalForm::Const(ref n2) = from {
-        if n1.op != n2.op {
+        if n1.op != n2.op && self.free_constructors {
           *to = CanonicalForm::Inconsistent(n1.clone(), n2.clone());
           return DidMerge(true, true);
         }
@@ -264,7 +695,9 @@ impl Analysis<SymbolLang> for CanonicalFormAnalysis {
   }
 
   fn modify(egraph: &mut EGraph<SymbolLang, Self>, id: Id) {
-    if let CanonicalForm::Const(ref n1) = egraph[id].data {
+    if let (CanonicalForm::Const(ref n1), true) =
+      (&egraph[id].data, egraph.analysis.free_constructors)
+    {
       let n1 = n1.clone();
       // We have just merged something into a constructor.
       // 1) Check if there are any other constructors in this class with the same head and union their children
@@ -310,6 +743,39 @@ impl Analysis<SymbolLang> for CanonicalFormAnalysis {
         egraph[id].data = CanonicalForm::Inconsistent(n1.clone(), n2.clone());
       }
     }
+    if egraph.analysis.fold_nats {
+      Self::fold_nats(egraph, id);
+    }
+    Self::fold_ints(egraph, id);
+  }
+}
+
+/// Holds for e-classes that are not integer literals (see `integer_rewrites`)
+pub struct NotIntLiteral;
+
+impl SearchCondition<SymbolLang, CanonicalFormAnalysis> for NotIntLiteral {
+  fn check(&self, egraph: &Eg, eclass: Id, _subst: &Subst) -> bool {
+    CanonicalFormAnalysis::int_value(egraph, eclass).is_none()
+  }
+}
+
+/// The extraction cost function selected by a configuration
+pub struct ConfiguredCost<'a>(&'a ProverConfig);
+
+impl<'a> CostFunction<SymbolLang> for ConfiguredCost<'a> {
+  type Cost = usize;
+
+  fn cost<C>(&mut self, enode: &SymbolLang, mut costs: C) -> Self::Cost
+  where
+    C: FnMut(Id) -> Self::Cost,
+  {
+    if let Some(custom) = self.0.custom_extraction_cost {
+      return custom(enode, &mut costs);
+    }
+    match self.0.extraction_cost {
+      ExtractionCost::AstSize => AstSize.cost(enode, costs),
+      ExtractionCost::AstDepth => AstDepth.cost(enode, costs),
+    }
   }
 }
 
@@ -339,6 +805,18 @@ impl ETerm {
     }
   }
 
+  /// Like `new`, but returns None if sexp is not a valid term
+  fn try_new(sexp: &Sexp, egraph: &mut Eg) -> Option<ETerm> {
+    let expr: Expr = sexp.to_string().parse().ok()?;
+    egraph.add_expr(&expr);
+    let id = egraph.lookup_expr(&expr)?;
+    Some(Self {
+      sexp: sexp.clone(),
+      id,
+      expr,
+    })
+  }
+
   fn new_from_expr(expr: &Expr, egraph: &mut Eg) -> ETerm {
     let sexp = parser::parse_str(&expr.to_string()).unwrap();
     egraph.add_expr(expr);
@@ -401,15 +879,17 @@ impl Equation {
   /// Add both sides of a raw equation to the egraph,
   /// producing an equation;
   /// if assume is true, also union the the two sides
-  fn new(eq: &RawEquation, egraph: &mut Eg, assume: bool) -> Self {
-    let lhs = ETerm::new(&eq.lhs, egraph);
-    let rhs = ETerm::new(&eq.rhs, egraph);
+  fn new(eq: &RawEquation, egraph: &mut Eg, assume: bool) -> Result<Self, GoalError> {
+    let lhs =
+      ETerm::try_new(&eq.lhs, egraph).ok_or_else(|| GoalError::LhsNotFound(eq.lhs.clone()))?;
+    let rhs =
+      ETerm::try_new(&eq.rhs, egraph).ok_or_else(|| GoalError::RhsNotFound(eq.rhs.clone()))?;
     if assume {
       // Assume the premise
       egraph.union_trusted(lhs.id, rhs.id, format!("premise {}={}", lhs.sexp, rhs.sexp));
       egraph.rebuild();
     }
-    Self { lhs, rhs }
+    Ok(Self { lhs, rhs })
   }
 
   /// Update variables in my expressions with their canonical forms
@@ -421,15 +901,42 @@ impl Equation {
   }
 }
 
+/// Statistics about a single saturation run
+#[derive(Debug, Clone)]
+pub struct SaturationStats {
+  /// Number of iterations the runner performed
+  pub iterations: usize,
+  /// Number of e-nodes at the end of saturation
+  pub egraph_nodes: usize,
+  /// Number of e-classes at the end of saturation
+  pub egraph_classes: usize,
+  /// Why saturation stopped
+  pub stop_reason: Option<StopReason>,
+  /// Number of rounds of incremental propagation before the full saturation
+  pub propagation_rounds: usize,
+  /// Number of times each rewrite (by name) changed the e-graph, in propagation and saturation
+  pub applied: HashMap<String, usize>,
+}
+
 /// Proof goal
 pub struct Goal<'a> {
   /// Goal name
   pub name: String,
   /// Equivalences we already proved
   pub egraph: Eg,
-  /// Rewrites are split into reductions (invertible rules) and lemmas (non-invertible rules)
-  reductions: &'a Vec<Rw>,
-  lemmas: HashMap<String, Rw>,
+  /// Rewrites are split into definitions (invertible rules, shared by all goals)
+  /// and lemmas (non-invertible rules created by case splits, which `use_lemmas` can disable);
+  /// the definitions are only owned by the goal if a rewrite filter dropped some of them
+  def_rewrites: Cow<'a, [Rw]>,
+  lemma_rewrites: HashMap<String, Lemma>,
+  /// The lemmas created by the case split that produced this goal,
+  /// i.e. its induction hypotheses (a subset of lemma_rewrites)
+  induction_hypotheses: Vec<Rw>,
+  /// Lemmas that are assumed to hold (e.g. previously proven conjectures);
+  /// unlike induction hypotheses, they apply unconditionally
+  external_lemmas: Vec<Rw>,
+  /// Rewrites generating the equivalence the goal is proven up to (see `with_equivalences`)
+  equivalence_rewrites: Vec<Rw>,
   /// Mapping from all universally-quantified variables of the goal to their types
   /// (note this includes both current and old variables, which have been case-split away)
   pub local_context: Context,
@@ -441,10 +948,17 @@ pub struct Goal<'a> {
   /// Variables we can case-split
   /// (i.e. the subset of local_context that have datatype types)
   scrutinees: VecDeque<Symbol>,
+  /// Whether some variable could have been case-split on, but was not added to scrutinees
+  /// because it exceeded the depth bound for its datatype
+  depth_exceeded: bool,
+  /// Groups of scrutinees that are split together (simultaneous induction)
+  joint_groups: Vec<Vec<Symbol>>,
   /// Instantiations of the induction hypothesis that are in the egraph
   grounding_instantiations: Vec<IdSubst>,
-  /// The equation we are trying to prove
-  pub eq: Equation,
+  /// The equations we are trying to prove;
+  /// when there are several, they are proven simultaneously,
+  /// so that the induction hypothesis includes all of them
+  pub eqs: Vec<Equation>,
   /// If this is a conditional prop, the premises
   pub premises: Vec<Equation>,
   /// Environment
@@ -452,32 +966,116 @@ pub struct Goal<'a> {
   /// Global context (i.e. constructors and top-level bindings)
   pub global_context: &'a Context,
 
-  /// If the goal is discharged, an explanation of the proof
-  pub explanation: Option<Explanation<SymbolLang>>,
+  /// If the goal is discharged, two terms whose equivalence discharges it:
+  /// the sides of its first equation, or two different constructor applications
+  /// (if this case is unreachable); see `explanation`
+  discharged_by: Option<(Expr, Expr)>,
   /// Definitions in a form amenable to proof emission
   pub defns: &'a Defns,
   /// Stores the expression each guard variable maps to
   guard_exprs: HashMap<String, Expr>,
+  /// Configuration of the proof this goal belongs to
+  pub config: &'a ProverConfig,
+  /// Statistics of the last saturation of this goal
+  pub saturation_stats: Option<SaturationStats>,
+  /// E-classes changed since the e-graph was last saturated,
+  /// or None if it has never been (completely) saturated
+  dirty_classes: Option<Vec<Id>>,
+  /// The case splits that led to this goal, in order:
+  /// each scrutinee together with the constructor application it was instantiated to
+  case_splits: Vec<(Symbol, Expr)>,
+  /// Whether common subterms of this goal (or its ancestor) have been generalized,
+  /// which makes it stronger than the original conjecture
+  generalized: bool,
+  /// Accumulators generalized to variables (see `generalize_accumulator`):
+  /// the induction is on the other variables, so these are never split on
+  /// and are not part of the termination check of lemmas
+  accumulators: Vec<Symbol>,
+  /// The cases of the split of this goal that are still to be taken (see `ProofState::next_goal`):
+  /// until then they all share this goal, which the last of them takes over
+  pending_cases: Vec<PendingCase>,
+}
+
+/// A case of a split (see `Goal::case_split`) before it becomes a subgoal:
+/// the name of the subgoal, and every variable split on with its type,
+/// the constructor it is instantiated to and the number of the first fresh variable of that constructor
+#[derive(Clone)]
+struct PendingCase {
+  name: String,
+  instantiations: Vec<(Symbol, Type, Symbol, usize)>,
+}
+
+/// The n-th fresh variable of a split on var (see `ast::var_depth`)
+fn fresh_var(var: Symbol, n: usize) -> Symbol {
+  Symbol::from(format!("{}_{}", var, n))
+}
+
+/// The application of constructor con to fresh_vars, as written in the proof
+fn con_app_string(con: Symbol, fresh_vars: &[Symbol]) -> String {
+  format!("({} {})", con, fresh_vars.iter().join(" "))
 }
 
+/// Selects, by name, which of the rewrites passed to `Goal::top` the goal saturates with
+pub type RewriteFilter = fn(&str) -> bool;
+
 impl<'a> Goal<'a> {
-  /// Create top-level goal
+  /// Create top-level goal;
+  /// if a rewrite filter is given, only the reductions whose names it accepts are used for this goal
+  /// (a goal that is only stuck because of a rule the filter dropped is unknown rather than invalid)
+  #[allow(clippy::too_many_arguments)]
   pub fn top(
     name: &str,
-    eq: &RawEquation,
-    premise: &Option<RawEquation>,
+    eqs: &[RawEquation],
+    hypotheses: &[RawEquation],
     params: Vec<(Symbol, Type)>,
     env: &'a Env,
     global_context: &'a Context,
     reductions: &'a Vec<Rw>,
+    rewrite_filter: Option<RewriteFilter>,
     defns: &'a Defns,
-  ) -> Self {
-    let mut egraph: Eg = EGraph::default().with_explanations_enabled();
-    let eq = Equation::new(eq, &mut egraph, false);
-    let premise = premise
-      .as_ref()
-      .map(|eq| Equation::new(eq, &mut egraph, true));
+    config: &'a ProverConfig,
+  ) -> Result<Self, GoalError> {
+    for (_, ty) in params.iter() {
+      check_type(ty, env)?;
+    }
+    let eqs = expand_equation_lets(eqs)?;
+    let hypotheses = expand_equation_lets(hypotheses)?;
+    check_symbols(eqs.iter().chain(&hypotheses), &params, env, global_context)?;
+    let mut egraph = new_egraph(config);
+    let eqs = eqs
+      .iter()
+      .map(|eq| Equation::new(eq, &mut egraph, false))
+      .collect::<Result<Vec<Equation>, GoalError>>()?;
+    let premises = hypotheses
+      .iter()
+      .map(|eq| Equation::new(eq, &mut egraph, true))
+      .collect::<Result<Vec<Equation>, GoalError>>()?;
+    // A parameter that does not occur in the goal gets a class of its own,
+    // which is not reachable from the equations, so it is never split on
+    // (see `defer_irrelevant_scrutinees`)
+    for (x, _) in params.iter() {
+      if egraph.lookup(SymbolLang::leaf(*x)).is_none() {
+        egraph.add(SymbolLang::leaf(*x));
+      }
+    }
     let var_classes = lookup_vars(&egraph, params.iter().map(|(x, _)| x));
+    // Hypotheses are assumed outright, so their rewrites are not subject to the termination check
+    let external_lemmas = if config.hypothesis_rewrites {
+      premises.iter().map(hypothesis_rewrite).collect()
+    } else {
+      vec![]
+    };
+
+    let def_rewrites = match rewrite_filter {
+      Some(filter) if !reductions.iter().all(|rw| filter(rw.name.as_str())) => Cow::Owned(
+        reductions
+          .iter()
+          .filter(|rw| filter(rw.name.as_str()))
+          .cloned()
+          .collect(),
+      ),
+      _ => Cow::Borrowed(reductions.as_slice()),
+    };
 
     let mut res = Self {
       name: name.to_string(),
@@ -485,16 +1083,27 @@ impl<'a> Goal<'a> {
       var_classes: var_classes.clone(),
       grounding_instantiations: vec![var_classes],
       egraph,
-      explanation: None,
-      reductions,
-      lemmas: HashMap::new(),
+      discharged_by: None,
+      def_rewrites,
+      lemma_rewrites: HashMap::new(),
+      induction_hypotheses: vec![],
+      external_lemmas,
+      equivalence_rewrites: vec![],
       local_context: Context::new(),
-      params: params.iter().map(|(x, _)| x.clone()).collect(),
+      params: params.iter().map(|(x, _)| *x).collect(),
       guard_exprs: HashMap::new(),
+      config,
+      saturation_stats: None,
+      dirty_classes: None,
+      case_splits: vec![],
+      generalized: false,
+      accumulators: vec![],
+      pending_cases: vec![],
       scrutinees: VecDeque::new(),
-      eq,
-      // Convert to a singleton list if the Option is Some, else the empty list
-      premises: premise.into_iter().collect(),
+      depth_exceeded: false,
+      joint_groups: vec![],
+      eqs,
+      premises,
       env,
       global_context,
       defns,
@@ -503,702 +1112,4859 @@ impl<'a> Goal<'a> {
       res.add_scrutinee(name, &ty, 0);
       res.local_context.insert(name, ty);
     }
-    res
+    let joint_group: Vec<Symbol> = res
+      .scrutinees
+      .iter()
+      .filter(|x| config.joint_induction.iter().any(|y| x.as_str() == y))
+      .cloned()
+      .collect();
+    if joint_group.len() > 1 {
+      res.joint_groups.push(joint_group);
+    }
+    Ok(res)
   }
 
   pub fn copy(&self) -> Self {
     Goal {
       name: self.name.clone(),
       egraph: self.egraph.clone(),
-      reductions: self.reductions,
-      lemmas: HashMap::new(), // the lemmas will be re-generated immediately anyway
+      def_rewrites: self.def_rewrites.clone(),
+      lemma_rewrites: HashMap::new(), // the lemmas will be re-generated immediately anyway
+      induction_hypotheses: self.induction_hypotheses.clone(),
+      external_lemmas: self.external_lemmas.clone(),
+      equivalence_rewrites: self.equivalence_rewrites.clone(),
       local_context: self.local_context.clone(),
       var_classes: self.var_classes.clone(),
       params: self.params.clone(),
       scrutinees: self.scrutinees.clone(),
+      depth_exceeded: self.depth_exceeded,
+      joint_groups: self.joint_groups.clone(),
       grounding_instantiations: self.grounding_instantiations.clone(),
-      eq: self.eq.clone(),
+      eqs: self.eqs.clone(),
       premises: self.premises.clone(),
       env: self.env,
       global_context: self.global_context,
       // NOTE: We don't really need to clone this.
       defns: self.defns,
       // If we reach this point, I think we won't have an explanation
-      explanation: None,
+      discharged_by: None,
       guard_exprs: self.guard_exprs.clone(),
+      config: self.config,
+      saturation_stats: None,
+      dirty_classes: self.dirty_classes.clone(),
+      case_splits: self.case_splits.clone(),
+      generalized: self.generalized,
+      accumulators: self.accumulators.clone(),
+      pending_cases: vec![],
     }
   }
 
-  /// Saturate the goal by applying all available rewrites
-  pub fn saturate(mut self) -> Self {
-    let rewrites = self.reductions.iter().chain(self.lemmas.values());
-    let runner = Runner::default()
-      .with_explanations_enabled()
-      .with_egraph(self.egraph)
-      .run(rewrites);
-    self.egraph = runner.egraph;
+  /// Saturate the goal by applying all available rewrites;
+  /// if a time limit is given, saturation stops once it has been reached
+  pub fn saturate(self, time_limit: Option<Duration>) -> Self {
+    self.saturate_cancellable(time_limit, &Arc::new(AtomicBool::new(false)))
+  }
+
+  /// Like `saturate`, but saturation also stops as soon as cancel is set
+  pub fn saturate_cancellable(
+    mut self,
+    time_limit: Option<Duration>,
+    cancel: &Arc<AtomicBool>,
+  ) -> Self {
+    let saturation = self.take_saturation().run(time_limit, cancel);
+    self.put_saturation(saturation);
     self
   }
 
-  /// Check if the goal has been discharged,
-  /// and if so, create an explanation.
+  /// Take what saturation works on out of this goal, leaving an empty e-graph in its place
+  /// until `put_saturation` puts the result back
+  fn take_saturation(&mut self) -> Saturation<'a> {
+    let lemma_rewrites = if self.config.use_lemmas {
+      Some(self.lemma_rewrites.values().map(|lemma| &lemma.rewrite))
+    } else {
+      None
+    };
+    let rewrites: Vec<Rw> = self
+      .def_rewrites
+      .iter()
+      .chain(lemma_rewrites.into_iter().flatten())
+      .chain(self.external_lemmas.iter())
+      .chain(self.equivalence_rewrites.iter())
+      .cloned()
+      .collect();
+    let empty = EGraph::new(CanonicalFormAnalysis {
+      fold_nats: self.config.fold_nats,
+      free_constructors: true,
+    });
+    Saturation {
+      egraph: std::mem::replace(&mut self.egraph, empty),
+      rewrites,
+      dirty_classes: self.dirty_classes.take(),
+      config: self.config,
+      stats: None,
+    }
+  }
+
+  fn put_saturation(&mut self, saturation: Saturation<'a>) {
+    self.egraph = saturation.egraph;
+    self.dirty_classes = saturation.dirty_classes;
+    self.saturation_stats = saturation.stats;
+    self.canonicalize_roots();
+  }
+
+  /// Point the cached e-class ids of the sides of the equations and premises
+  /// to the canonical representatives of their classes;
+  /// called whenever the e-graph has been rebuilt.
+  /// (Which of two merged classes becomes the representative is up to egg,
+  /// so the ids should only ever be used through `find` or after this.)
+  fn canonicalize_roots(&mut self) {
+    for eq in self.eqs.iter_mut().chain(self.premises.iter_mut()) {
+      eq.lhs.id = self.egraph.find(eq.lhs.id);
+      eq.rhs.id = self.egraph.find(eq.rhs.id);
+    }
+  }
+
+  /// The best expression in e-class id according to the extraction cost,
+  /// breaking ties between equally good nodes by how the expressions print:
+  /// egg orders the nodes of a class by their symbols, whose order depends on when they were interned,
+  /// which can differ from run to run.
+  pub fn extract_stable(&self, id: Id) -> Expr {
+    let extractor = self.extractor();
+    let mut memo = HashMap::new();
+    self.extract_stable_memo(&extractor, self.egraph.find(id), &mut memo)
+  }
+
+  fn extract_stable_memo(
+    &self,
+    extractor: &Extractor<ConfiguredCost, SymbolLang, CanonicalFormAnalysis>,
+    id: Id,
+    memo: &mut HashMap<Id, Expr>,
+  ) -> Expr {
+    if let Some(expr) = memo.get(&id) {
+      return expr.clone();
+    }
+    let best_cost = extractor.find_best_cost(id);
+    let child_cost = |child: &Id| extractor.find_best_cost(*child);
+    // Only nodes whose children are strictly cheaper can be extracted without going around a cycle
+    // (which is every optimal node for the built-in costs)
+    let candidates: Vec<&SymbolLang> = self.egraph[id]
+      .nodes
+      .iter()
+      .filter(|node| {
+        node
+          .children
+          .iter()
+          .all(|child| child_cost(child) < best_cost)
+          && ConfiguredCost(self.config).cost(node, |child| extractor.find_best_cost(child))
+            == best_cost
+      })
+      .collect();
+    let best = candidates
+      .into_iter()
+      .map(|node| {
+        node
+          .join_recexprs(|child| self.extract_stable_memo(extractor, self.egraph.find(child), memo))
+      })
+      .min_by_key(|expr| expr.to_string())
+      .unwrap_or_else(|| extractor.find_best(id).1);
+    memo.insert(id, best.clone());
+    best
+  }
+
+  /// Record that e-class id changed since the last saturation
+  fn mark_dirty(&mut self, id: Id) {
+    if let Some(dirty) = &mut self.dirty_classes {
+      dirty.push(id);
+    }
+  }
+
+  /// An extractor for this goal's e-graph using the configured cost function
+  pub fn extractor(&self) -> Extractor<'_, ConfiguredCost<'_>, SymbolLang, CanonicalFormAnalysis> {
+    Extractor::new(&self.egraph, ConfiguredCost(self.config))
+  }
+
+  /// The variables this goal can still case-split on, in the order they are queued
+  /// (guards introduced by conditionals come first)
+  pub fn scrutinees(&self) -> impl Iterator<Item = &Symbol> {
+    self.scrutinees.iter()
+  }
+
+  /// The induction hypotheses introduced by the case split that produced this goal
+  /// (empty for the top-level goal, and in non-cyclic mode for goals whose ancestors already created them);
+  /// unlike the definitional rewrites, these only apply to smaller instances of the goal
+  pub fn induction_hypotheses(&self) -> &[Rw] {
+    &self.induction_hypotheses
+  }
+
+  /// Types of all universally-quantified variables of the goal
+  pub fn local_context(&self) -> &Context {
+    &self.local_context
+  }
+
+  /// Every expression the e-graph knows to be equal to the lhs and to the rhs of the (first) equation,
+  /// smallest first; these are the variants cyclic mode makes lemmas out of.
+  /// Cycles in the e-graph are not followed, but the number of expressions can still be exponential
+  /// in the size of the e-graph, so this is meant for debugging small goals.
+  pub fn equivalent_expressions(&self) -> (Vec<Expr>, Vec<Expr>) {
+    let lhs_id = self.egraph.find(self.eqs[0].lhs.id);
+    let rhs_id = self.egraph.find(self.eqs[0].rhs.id);
+    let mut exprs = get_all_expressions(&self.egraph, vec![lhs_id, rhs_id]);
+    let mut sorted = |id: Id| {
+      exprs
+        .remove(&id)
+        .unwrap_or_default()
+        .into_iter()
+        .sorted_by_key(|expr| (expr.as_ref().len(), expr.to_string()))
+        .collect()
+    };
+    // (if both sides are in the same class, the second lookup finds nothing left to remove)
+    let lhs_exprs: Vec<Expr> = sorted(lhs_id);
+    let rhs_exprs = if lhs_id == rhs_id {
+      lhs_exprs.clone()
+    } else {
+      sorted(rhs_id)
+    };
+    (lhs_exprs, rhs_exprs)
+  }
+
+  /// Number of rewrites available to saturate this goal: definitions, lemmas, and external lemmas
+  pub fn num_rewrites(&self) -> usize {
+    self.def_rewrites.len()
+      + self.lemma_rewrites.len()
+      + self.external_lemmas.len()
+      + self.equivalence_rewrites.len()
+  }
+
+  /// Was scrutinee s introduced for the condition of an `ite` or the scrutinee of a `match`
+  /// (as opposed to being a variable of the goal)?
+  pub fn is_guard_scrutinee(&self, s: &Symbol) -> bool {
+    self.guard_exprs.contains_key(s.as_str())
+  }
+
+  /// Did the last saturation stop because it reached the iteration limit?
+  pub fn iteration_limit_reached(&self) -> bool {
+    matches!(
+      self.saturation_stats,
+      Some(SaturationStats {
+        stop_reason: Some(StopReason::IterationLimit(_)),
+        ..
+      })
+    )
+  }
+
+  /// Did the last saturation stop because the e-graph grew too large?
+  /// (in which case the e-graph is rebuilt but not saturated)
+  pub fn node_limit_reached(&self) -> bool {
+    matches!(
+      self.saturation_stats,
+      Some(SaturationStats {
+        stop_reason: Some(StopReason::NodeLimit(_)),
+        ..
+      })
+    )
+  }
+
+  /// Are the sides of every equation already in the same e-class, without any rewriting?
+  /// (e.g. because the goal is `x = x`, or a case split made them equal)
+  pub fn is_trivially_valid(&self) -> bool {
+    self.eqs.iter().all(|eq| self.sides_equal(eq))
+  }
+
+  /// Are the two sides of `eq` in the same e-class?
+  /// The cached ids of the sides remain valid across case splits:
+  /// egg never retires a class id, and `remove_node` only drops the split variable's leaf
+  /// from a class that also contains the constructor application it was unioned with.
+  fn sides_equal(&self, eq: &Equation) -> bool {
+    self.egraph.find(eq.lhs.id) == self.egraph.find(eq.rhs.id)
+  }
+
   pub fn check_validity(&mut self) {
     // for eclass in self.egraph.classes() {
     //   println!("{}: {:?} CANONICAL {}", eclass.id, eclass.nodes, ConstructorFolding::extract_canonical(&self.egraph, eclass.id).unwrap_or(vec![].into()));
     // }
 
-    if self.egraph.find(self.eq.lhs.id) == self.egraph.find(self.eq.rhs.id) {
-      // We have shown that LHS == RHS
-      self.explanation = Some(
-        self
-          .egraph
-          .explain_equivalence(&self.eq.lhs.expr, &self.eq.rhs.expr),
-      );
+    if self.is_trivially_valid() {
+      // We have shown that LHS == RHS (for every equation);
+      // the explanation only covers the first one
+      let eq = &self.eqs[0];
+      self.discharged_by = Some((eq.lhs.expr.clone(), eq.rhs.expr.clone()));
     } else {
       // Check if this case in unreachable (i.e. if there are any inconsistent e-classes in the e-graph)
       let res = self.egraph.classes().find_map(|eclass| {
         if let CanonicalForm::Inconsistent(n1, n2) = &eclass.data {
           // This is here only for the purpose of proof generation:
-          let extractor = Extractor::new(&self.egraph, AstSize);
+          let extractor = self.extractor();
           let expr1 = extract_with_node(n1, &extractor);
           let expr2 = extract_with_node(n2, &extractor);
-          if CONFIG.verbose {
-            println!("{}: {} = {}", "UNREACHABLE".bright_red(), expr1, expr2);
-          }
+          info!("unreachable: {} = {}", expr1, expr2);
           Some((expr1, expr2))
         } else {
           None
         }
       });
       if let Some((expr1, expr2)) = res {
-        self.explanation = Some(self.egraph.explain_equivalence(&expr1, &expr2));
+        self.discharged_by = Some((expr1, expr2));
       }
     }
   }
 
-  /// Check whether an expression is reducible using this goal's reductions
-  pub fn is_reducible(&self, expr: &Expr) -> bool {
-    let mut local_graph: Eg = Default::default();
-    local_graph.add_expr(expr);
-    local_graph.rebuild();
-    for reduction in self.reductions {
-      if !reduction.search(&local_graph).is_empty() {
-        return true;
-      }
-    }
-    false
+  /// Does this goal have variables of type `Int`?
+  /// These are never case-split, so running out of scrutinees does not make such a goal invalid.
+  fn has_int_vars(&self) -> bool {
+    self.local_context.values().any(Type::is_int)
   }
 
-  /// Create a rewrite `lhs => rhs` which will serve as the lemma ("induction hypothesis") for a cycle in the proof;
-  /// here lhs and rhs are patterns, created by replacing all scrutinees with wildcards;
-  /// soundness requires that the pattern only apply to variable tuples smaller than the current scrutinee tuple.
-  fn add_lemma_rewrites(&mut self, state: &ProofState) -> HashMap<String, Rw> {
-    let lhs_id = self.egraph.find(self.eq.lhs.id);
-    let rhs_id = self.egraph.find(self.eq.rhs.id);
-    let is_var = |v| self.local_context.contains_key(v);
-    let is_cyclic = CONFIG.is_cyclic();
+  /// Does this goal have variables of function type?
+  /// Saturation treats their applications as opaque, so running out of scrutinees does not make such a goal invalid.
+  fn has_function_vars(&self) -> bool {
+    self.local_context.values().any(Type::is_arrow)
+  }
 
-    let exprs = if is_cyclic {
-      // If we are doing cyclic proofs: make lemmas out of all LHS and RHS variants
-      get_all_expressions(&self.egraph, vec![lhs_id, rhs_id])
-    } else {
-      // In the non-cyclic case, only use the original LHS and RHS
-      // and only if no other lemmas have been added yet
-      let mut exprs: HashMap<Id, Vec<Expr>> = vec![(lhs_id, vec![]), (rhs_id, vec![])]
-        .into_iter()
-        .collect();
-      if self.lemmas.is_empty() {
-        exprs
-          .get_mut(&lhs_id)
-          .unwrap()
-          .push(self.eq.lhs.expr.clone());
-        exprs
-          .get_mut(&rhs_id)
-          .unwrap()
-          .push(self.eq.rhs.expr.clone());
-      }
-      exprs
-    };
+  /// Does this goal use a function whose definition was left out of its rewrites
+  /// (by the configuration, or by the rewrite filter of the goal)?
+  /// Then running out of scrutinees does not make it invalid either.
+  fn uses_opaque_functions(&self) -> bool {
+    matches!(self.def_rewrites, Cow::Owned(_))
+      || self
+        .defns
+        .keys()
+        .any(|name| self.config.opaque_functions.contains(&Symbol::from(name)))
+  }
 
-    // Before creating a cyclic lemma with premises,
-    // we need to update the variables in the premises 
-    // with their canonical forms in terms of the current goal variables
-    let premises: Vec<Equation> = self
-      .premises
+  /// Has `check_validity` found this goal to hold?
+  pub fn is_discharged(&self) -> bool {
+    self.discharged_by.is_some()
+  }
+
+  /// If the goal is discharged, the chain of rewrites in its e-graph that proves it
+  /// (for a conjunction, only its first equation);
+  /// None if explanations are disabled in the configuration
+  pub fn explanation(&mut self) -> Option<Explanation<SymbolLang>> {
+    if !self.config.explanations {
+      return None;
+    }
+    let (expr1, expr2) = self.discharged_by.clone()?;
+    Some(self.egraph.explain_equivalence(&expr1, &expr2))
+  }
+
+  /// The values of the top-level parameters in this goal,
+  /// obtained by composing the case splits that led to it;
+  /// parameters that were never split on map to themselves.
+  pub fn counterexample(&self) -> Counterexample {
+    let splits: SSubst = self
+      .case_splits
       .iter()
-      .map(|eq| eq.update_variables(&self.var_classes, &self.egraph))
+      .map(|(var, con_app)| {
+        (
+          var.to_string(),
+          parser::parse_str(&con_app.to_string()).unwrap(),
+        )
+      })
+      .collect();
+    let assignment = self
+      .params
+      .iter()
+      .map(|param| {
+        let value = recursively_resolve_variable(&param.to_string(), &splits);
+        (*param, value.to_string().parse().unwrap())
+      })
       .collect();
+    Counterexample { assignment }
+  }
 
-    let mut rewrites = self.lemmas.clone();
-    for lhs_expr in exprs.get(&lhs_id).unwrap() {
-      let lhs: Pattern<SymbolLang> = to_pattern(lhs_expr, is_var);
-      if (CONFIG.irreducible_only && self.is_reducible(lhs_expr)) || has_guard_wildcards(&lhs) {
-        continue;
+  /// Try to refute the goal by evaluating it on random values of its parameters:
+  /// each trial instantiates every parameter with a random constructor term of bounded depth
+  /// (the bound grows with the first few trials) and checks the goal with `fails_on`.
+  /// Gives up if a parameter has a type whose values cannot be generated,
+  /// such as a type variable or a function type,
+  /// or if the goal is proven up to equivalences, which evaluation does not take into account.
+  pub fn quickcheck_goal(&self, trials: usize) -> Option<Counterexample> {
+    if self.has_equivalences() {
+      return None;
+    }
+    // A fixed seed makes the outcome of a run reproducible
+    let mut rng = StdRng::seed_from_u64(0);
+    for trial in 0..trials {
+      // Start with small values, which make for more readable counterexamples
+      let depth = trial.min(QUICKCHECK_DEPTH);
+      let mut assignment = vec![];
+      for param in self.params.iter() {
+        let value = self.random_value(&self.local_context[param], depth, &mut rng)?;
+        assignment.push((*param, value));
       }
-      for rhs_expr in exprs.get(&rhs_id).unwrap() {
-        if state.timeout() {
-          return rewrites;
-        }
-
-        let rhs: Pattern<SymbolLang> = to_pattern(rhs_expr, is_var);
-        if (CONFIG.irreducible_only && self.is_reducible(rhs_expr)) || has_guard_wildcards(&rhs) {
-          continue;
-        }
-
-        let lhs_vars = var_set(&lhs);
-        let rhs_vars = var_set(&rhs);
-        let lemma_vars = lhs_vars.union(&rhs_vars).cloned().collect();
-
-        // If any of my premises contain variables that are not present in lhs or rhs,
-        // skip because we don't know how to check such a premise
-        if !premises.iter().all(|eq| {
-          let premise_lhs_vars = var_set(&to_pattern(&eq.lhs.expr, is_var));
-          let premise_rhs_vars = var_set(&to_pattern(&eq.rhs.expr, is_var));
-          let premise_vars: HashSet<Var> =
-            premise_lhs_vars.union(&premise_rhs_vars).cloned().collect();
-          premise_vars.is_subset(&lemma_vars)
-        }) {
-          continue;
-        }
-
-        // Pick out those variables that occur in the lemma
-        let lemma_var_classes: IdSubst = self
-          .var_classes
-          .iter()
-          .filter(|(x, _)| lemma_vars.contains(&to_wildcard(x)))
-          .map(|(x, id)| (x.clone(), *id))
-          .collect();
-
-        let condition = Soundness {
-          free_vars: lemma_var_classes,
-          premises: premises.clone(),
-        };
-        let mut added_lemma = false;
-        if rhs_vars.is_subset(&lhs_vars) {
-          // if rhs has no extra wildcards, create a lemma lhs => rhs
-          Goal::add_lemma(lhs.clone(), rhs.clone(), condition.clone(), &mut rewrites);
-          added_lemma = true;
-          if CONFIG.single_rhs {
-            continue;
-          };
-        }
-        if (is_cyclic || !added_lemma) && lhs_vars.is_subset(&rhs_vars) {
-          // if lhs has no extra wildcards, create a lemma rhs => lhs;
-          // in non-cyclic mode, a single direction of IH is always sufficient
-          // (because grounding adds all instantiations we could possibly care about).
-          Goal::add_lemma(rhs.clone(), lhs.clone(), condition, &mut rewrites);
-          added_lemma = true;
-          if CONFIG.single_rhs {
-            continue;
-          };
-        }
-        if !added_lemma {
-          warn!("cannot create a lemma from {} and {}", lhs, rhs);
-        }
+      if self.fails_on(&assignment) {
+        return Some(Counterexample::new(assignment));
       }
     }
-    rewrites
+    None
   }
 
-  /// Add a rewrite `lhs => rhs` to `rewrites` if not already present
-  fn add_lemma(lhs: Pat, rhs: Pat, cond: Soundness, rewrites: &mut HashMap<String, Rw>) {
-    let name = format!("{}{}={}", LEMMA_PREFIX, lhs, rhs);
-    // Insert the lemma into the rewrites map if it's not already there
-    match rewrites.entry(name.clone()) {
-      Entry::Occupied(_) => (),
-      Entry::Vacant(entry) => {
-        warn!("creating lemma: {} => {}", lhs, rhs);
-        let rw = Rewrite::new(
-          name,
-          ConditionalSearcher {
-            condition: cond,
-            searcher: lhs,
-          },
-          rhs,
-        )
+  /// Does the goal fail when its parameters are instantiated with the given closed constructor terms?
+  /// Both sides of every equation are evaluated using the reductions,
+  /// and the goal fails if the sides of some equation evaluate to different constructor terms
+  /// while every premise evaluates to equal ones.
+  fn fails_on(&self, assignment: &[(Symbol, Sexp)]) -> bool {
+    let subst: SSubst = assignment
+      .iter()
+      .map(|(x, value)| (x.to_string(), value.clone()))
+      .collect();
+    let mut egraph: Eg = EGraph::new(CanonicalFormAnalysis {
+      fold_nats: self.config.fold_nats,
+      free_constructors: true,
+    });
+    let mut add_sides = |eq: &Equation| {
+      let lhs: Expr = resolve_sexp(&eq.lhs.sexp, &subst)
+        .to_string()
+        .parse()
+        .unwrap();
+      let rhs: Expr = resolve_sexp(&eq.rhs.sexp, &subst)
+        .to_string()
+        .parse()
         .unwrap();
-        entry.insert(rw);
+      (egraph.add_expr(&lhs), egraph.add_expr(&rhs))
+    };
+    let eqs: Vec<(Id, Id)> = self.eqs.iter().map(&mut add_sides).collect();
+    let premises: Vec<(Id, Id)> = self.premises.iter().map(&mut add_sides).collect();
+    let runner = Runner::default()
+      .with_node_limit(self.config.max_egraph_nodes)
+      .with_iter_limit(self.config.max_iterations)
+      .with_egraph(egraph)
+      .run(self.def_rewrites.iter());
+    let egraph = runner.egraph;
+    // Evaluation might be cut short, so only fully evaluated sides are compared
+    let differ = |(lhs, rhs): &(Id, Id)| {
+      egraph.find(*lhs) != egraph.find(*rhs)
+        && CanonicalFormAnalysis::extract_canonical(&egraph, *lhs).is_some()
+        && CanonicalFormAnalysis::extract_canonical(&egraph, *rhs).is_some()
+    };
+    let premises_hold = premises
+      .iter()
+      .all(|(lhs, rhs)| egraph.find(*lhs) == egraph.find(*rhs));
+    premises_hold && eqs.iter().any(differ)
+  }
+
+  /// The normal form of expr under rewrites (usually the reductions of the definitions):
+  /// expr is added to an e-graph of its own, which is saturated with rewrites,
+  /// and the result is its constructor form if it has one,
+  /// otherwise the best form according to the configured extraction cost.
+  /// Symbols that are neither functions nor constructors are treated as variables.
+  /// Saturation respects the node and iteration limits of config,
+  /// so if the rewrites do not terminate (e.g. because some definitions are used in both directions),
+  /// the result is the best form found when saturation stopped, which need not be a normal form.
+  pub fn simplify(
+    expr: &Expr,
+    env: &Env,
+    global_context: &Context,
+    rewrites: &[Rw],
+    config: &ProverConfig,
+  ) -> Expr {
+    for node in expr.as_ref() {
+      let known = node.op.as_str() == APPLY
+        || is_builtin(&node.op)
+        || int_literal(node.op.as_str()).is_some()
+        || global_context.contains_key(&node.op)
+        || env.values().any(|(_, cons)| cons.contains(&node.op));
+      if !known && !node.is_leaf() {
+        warn!(
+          "simplify: unknown function {} is treated as uninterpreted",
+          node.op
+        );
       }
     }
+    let mut egraph: Eg = EGraph::new(CanonicalFormAnalysis {
+      fold_nats: config.fold_nats,
+      free_constructors: true,
+    });
+    let id = egraph.add_expr(expr);
+    let runner = Runner::default()
+      .with_node_limit(config.max_egraph_nodes)
+      .with_iter_limit(config.max_iterations)
+      .with_egraph(egraph)
+      .run(rewrites);
+    if let Some(StopReason::NodeLimit(_) | StopReason::IterationLimit(_)) = runner.stop_reason {
+      warn!("simplify: saturation of {} stopped early", expr);
+    }
+    let egraph = runner.egraph;
+    let id = egraph.find(id);
+    CanonicalFormAnalysis::extract_canonical(&egraph, id).unwrap_or_else(|| {
+      Extractor::new(&egraph, ConfiguredCost(config))
+        .find_best(id)
+        .1
+    })
   }
 
-  /// Add var as a scrutinee if its type `ty` is a datatype;
-  /// if depth bound is exceeded, add a sentinel symbol instead
-  fn add_scrutinee(&mut self, var: Symbol, ty: &Type, depth: usize) {
-    if let Ok(dt) = ty.datatype() {
-      if self.env.contains_key(&Symbol::from(dt)) {
-        // Only add new variable to scrutinees if its depth doesn't exceed the bound
-        if depth < CONFIG.max_split_depth {
-          self.scrutinees.push_back(var);
-        } else {
-          self.scrutinees.push_back(Symbol::from(BOUND_EXCEEDED));
-        }
+  /// A random constructor term of type ty, nested at most depth constructors deep
+  /// (beyond that, only constructors that do not recurse into the same datatype are used)
+  fn random_value(&self, ty: &Type, depth: usize, rng: &mut StdRng) -> Option<Sexp> {
+    if ty.is_int() {
+      // Integers get larger with the depth, like the other values
+      let bound: i64 = 1 << depth;
+      return Some(Sexp::String(rng.gen_range(-bound..=bound).to_string()));
+    }
+    let candidates = self.value_constructors(ty, depth)?;
+    let (con, args) = candidates.choose(rng)?;
+    if args.is_empty() {
+      return Some(Sexp::String(con.to_string()));
+    }
+    let mut app = vec![Sexp::String(con.to_string())];
+    for arg in args {
+      app.push(self.random_value(arg, depth.saturating_sub(1), rng)?);
+    }
+    Some(Sexp::List(app))
+  }
+
+  /// All constructor terms of type ty that `random_value` can generate with the same depth
+  fn all_values(&self, ty: &Type, depth: usize) -> Option<Vec<Sexp>> {
+    if ty.is_int() {
+      let bound: i64 = 1 << depth;
+      return Some(
+        (-bound..=bound)
+          .map(|n| Sexp::String(n.to_string()))
+          .collect(),
+      );
+    }
+    let mut values = vec![];
+    for (con, args) in self.value_constructors(ty, depth)? {
+      if args.is_empty() {
+        values.push(Sexp::String(con.to_string()));
+        continue;
+      }
+      let arg_values = args
+        .iter()
+        .map(|arg| self.all_values(arg, depth.saturating_sub(1)))
+        .collect::<Option<Vec<_>>>()?;
+      for fields in arg_values.into_iter().multi_cartesian_product() {
+        let mut app = vec![Sexp::String(con.to_string())];
+        app.extend(fields);
+        values.push(Sexp::List(app));
       }
     }
+    Some(values)
   }
 
-  /// If the egraph contains ITEs whose condition is "irreducible"
-  /// (i.e. not equivalent to a constant or a scrutinee variable),
-  /// add a fresh scrutinee to its eclass, so that we can match on it.
-  fn split_ite(&mut self) {
-    let guard_var = "?g".parse().unwrap();
-    // Pattern "(ite ?g ?x ?y)"
-    let searcher: Pattern<SymbolLang> = format!("({} {} ?x ?y)", *ITE, guard_var).parse().unwrap();
-    let matches = searcher.search(&self.egraph);
-    // Collects class IDs of all stuck guards;
-    // it's a map because the same guard can match more than once, but we only want to add a new scrutinee once
-    let mut stuck_guards = HashMap::new();
-    for m in matches {
-      for subst in m.substs {
-        let guard_id = *subst.get(guard_var).unwrap();
-        if let CanonicalForm::Stuck = self.egraph[guard_id].data {
-          stuck_guards.insert(guard_id, subst);
+  /// Values of type ty that are smaller than value, a closed constructor term of that type,
+  /// for shrinking counterexamples (see `shrink_counterexample`), most drastic first:
+  /// the nullary constructors of ty in place of value, its arguments of type ty,
+  /// and value with one of its arguments shrunk in turn.
+  /// Integers shrink by halving towards zero.
+  fn shrinks(&self, value: &Sexp, ty: &Type) -> Vec<Sexp> {
+    match value {
+      Sexp::String(atom) => match int_literal(atom) {
+        Some(n) if n != 0 => vec![Sexp::String((n / 2).to_string())],
+        _ => vec![],
+      },
+      Sexp::List(app) => {
+        let mut shrinks: Vec<Sexp> = self
+          .value_constructors(ty, 0)
+          .unwrap_or_default()
+          .into_iter()
+          .filter(|(_, args)| args.is_empty())
+          .map(|(con, _)| Sexp::String(con.to_string()))
+          .collect();
+        let con = Symbol::from(app[0].string().unwrap());
+        let arg_types = Goal::instantiate_constructor(&self.global_context[&con], ty);
+        for (arg, arg_ty) in app[1..].iter().zip(arg_types.iter()) {
+          if arg_ty == ty {
+            shrinks.push(arg.clone());
+          }
+        }
+        for (i, arg_ty) in arg_types.iter().enumerate() {
+          for arg in self.shrinks(&app[i + 1], arg_ty) {
+            let mut shrunk = app.clone();
+            shrunk[i + 1] = arg;
+            shrinks.push(Sexp::List(shrunk));
+          }
         }
+        shrinks
       }
+      Sexp::Empty => vec![],
     }
-    // Iterate over all stuck guard eclasses and add a new scrutinee to each
-    for (guard_id, subst) in stuck_guards {
-      let fresh_var = Symbol::from(format!("{}{}", GUARD_PREFIX, guard_id));
-      // This is here only for logging purposes
-      let expr = Extractor::new(&self.egraph, AstSize).find_best(guard_id).1;
-      let add_scrutinee_message =
-        format!("adding scrutinee {} to split condition {}", fresh_var, expr);
-      warn!("{}", add_scrutinee_message);
-      self
-        .local_context
-        .insert(fresh_var, BOOL_TYPE.parse().unwrap());
-      // We are adding the new scrutinee to the front of the deque,
-      // because we want to split conditions first, since they don't introduce new variables
-      self.scrutinees.push_front(fresh_var);
-      let new_node = SymbolLang::leaf(fresh_var);
-      let new_pattern_ast = vec![ENodeOrVar::ENode(new_node.clone())].into();
-      let guard_var_pattern_ast = vec![ENodeOrVar::Var(guard_var)].into();
-      self.guard_exprs.insert(fresh_var.to_string(), expr);
-      self.egraph.union_instantiations(
-        &guard_var_pattern_ast,
-        &new_pattern_ast,
-        &subst,
-        add_scrutinee_message,
-      );
+  }
+
+  /// The constructors of datatype ty that can head a value nested at most depth constructors deep,
+  /// each with the types of its arguments
+  fn value_constructors(&self, ty: &Type, depth: usize) -> Option<Vec<(Symbol, Vec<Type>)>> {
+    let dt = ty.datatype().ok()?;
+    let (_, cons) = self.env.get(&Symbol::from(dt))?;
+    let mut candidates: Vec<(Symbol, Vec<Type>)> = cons
+      .iter()
+      .map(|con| {
+        let con_ty = &self.global_context[con];
+        (*con, Goal::instantiate_constructor(con_ty, ty))
+      })
+      .collect();
+    if depth == 0 {
+      let base_cases = |(_, args): &(Symbol, Vec<Type>)| {
+        args
+          .iter()
+          .all(|arg| arg.datatype().map_or(true, |arg_dt| arg_dt != dt))
+      };
+      if candidates.iter().any(|(_, args)| args.is_empty()) {
+        candidates.retain(|(_, args)| args.is_empty());
+      } else {
+        candidates.retain(base_cases);
+      }
     }
-    self.egraph.rebuild();
+    Some(candidates)
   }
 
-  /// Consume this goal and add its case splits to the proof state
-  fn case_split(mut self, state: &mut ProofState<'a>) {
-    let new_lemmas = self.add_lemma_rewrites(state);
+  /// Make lemmas available to this goal (and all of its subgoals) as unconditional rewrites.
+  ///
+  /// The lemmas are trusted: they are not checked, and unlike induction hypotheses
+  /// they are not restricted to smaller instances of the goal, so an invalid lemma
+  /// (including the goal itself) makes any proof that uses it unsound.
+  pub fn add_lemmas<I: IntoIterator<Item = Rw>>(&mut self, lemmas: I) {
+    self.external_lemmas.extend(lemmas);
+  }
 
-    // Get the next variable to case-split on
-    let var = self.scrutinees.pop_front().unwrap();
-    let var_str = var.to_string();
-    warn!("case-split on {}", var);
-    let var_node = SymbolLang::leaf(var);
-    let var_pattern_ast: RecExpr<ENodeOrVar<SymbolLang>> = vec![ENodeOrVar::ENode(var_node)].into();
-    // Get the type of the variable, and then remove the variable
-    let ty = match self.local_context.get(&var) {
-      Some(ty) => ty,
-      None => panic!("{} not in local context", var),
+  /// Prove the equations of this goal up to the congruence generated by rewrites
+  /// (e.g. bag equality, generated by swapping adjacent list elements) instead of definitional equality:
+  /// the rewrites are applied in saturation like the definitions, without the termination check of lemmas,
+  /// and the goal is done once the sides are merged under them.
+  /// Every function the goal uses must respect the congruence
+  /// (bag equality is respected by `len` but not by `head`), or the proof is unsound.
+  /// Since the congruence may relate different constructor terms,
+  /// constructors are no longer taken to be injective or distinct;
+  /// as a consequence, unreachable cases are not detected, and the goal is never found invalid.
+  /// Only a goal fresh from `Goal::top` can be given equivalences.
+  pub fn with_equivalences(mut self, rewrites: Vec<Rw>) -> Self {
+    if rewrites.is_empty() {
+      return self;
+    }
+    debug_assert!(
+      self.case_splits.is_empty(),
+      "{} is not a top-level goal",
+      self.name
+    );
+    // Rebuild the e-graph, so that not even the premises are subject to injectivity
+    let mut egraph = new_egraph(self.config);
+    egraph.analysis.free_constructors = false;
+    let mut rebuild = |eqs: &[Equation], assume: bool| -> Vec<Equation> {
+      eqs
+        .iter()
+        .map(|eq| {
+          let raw = RawEquation {
+            lhs: eq.lhs.sexp.clone(),
+            rhs: eq.rhs.sexp.clone(),
+          };
+          // Both sides were valid terms in the old e-graph already
+          Equation::new(&raw, &mut egraph, assume).unwrap()
+        })
+        .collect()
     };
-    // Convert to datatype name
-    let dt = Symbol::from(ty.datatype().unwrap());
-    // Get the constructors of the datatype
-    let (_, cons) = self.env.get(&dt).unwrap();
-    // We will add this to state.proof to describe the case split.
-    let mut instantiated_cons_and_goals: Vec<(String, String)> = vec![];
-    // For each constructor, create a new goal and push it onto the proof state
-    // (we process constructors in reverse order so that base case ends up at the top of the stack)
-    for &con in cons.iter().rev() {
-      let mut new_goal = self.copy();
-      new_goal.name = format!("{}:", self.name);
-      new_goal.lemmas = new_lemmas.clone();
-
-      // Get the types of constructor arguments
-      let con_ty = self.global_context.get(&con).unwrap();
-      let con_args = Goal::instantiate_constructor(con_ty, ty);
-      // For each argument: create a fresh variable and add it to the context and to scrutinees
-      let mut fresh_vars = vec![];
-
-      for (i, arg_type) in con_args.iter().enumerate() {
-        let fresh_var_name = format!("{}_{}{}", var, self.egraph.total_size(), i);
-        let depth = var_depth(&fresh_var_name[..]);
-        let fresh_var = Symbol::from(fresh_var_name.clone());
-        fresh_vars.push(fresh_var);
-        // Add new variable to context
-        new_goal.local_context.insert(fresh_var, arg_type.clone());
-        new_goal.add_scrutinee(fresh_var, arg_type, depth);
-        let id = new_goal.egraph.add(SymbolLang::leaf(fresh_var));
-        new_goal.var_classes.insert(fresh_var, id);
-
-        if !CONFIG.is_cyclic() && ty == arg_type {
-          // This is a recursive constructor parameter:
-          // add new grounding instantiations replacing var with fresh_var
-          new_goal.add_grounding(var, fresh_var);
-        }
+    self.eqs = rebuild(&self.eqs, false);
+    self.premises = rebuild(&self.premises, true);
+    self.var_classes = lookup_vars(&egraph, self.params.iter());
+    self.grounding_instantiations = vec![self.var_classes.clone()];
+    self.egraph = egraph;
+    self.equivalence_rewrites = rewrites;
+    self
+  }
+
+  /// Is this goal proven up to equivalences (see `with_equivalences`)?
+  pub fn has_equivalences(&self) -> bool {
+    !self.equivalence_rewrites.is_empty()
+  }
+
+  /// Rewrites stating the equations of this goal, with its parameters turned into wildcards,
+  /// in every direction that does not introduce new variables;
+  /// only unconditional goals can be turned into rewrites,
+  /// and goals proven up to equivalences cannot either, since their equations only hold up to those.
+  /// It is up to the caller to make sure the goal has actually been proven.
+  pub fn to_lemmas(&self) -> Vec<Rw> {
+    if !self.premises.is_empty() || self.has_equivalences() {
+      return vec![];
+    }
+    let is_var = |v| self.params.contains(v);
+    let mut rewrites = vec![];
+    for eq in self.eqs.iter() {
+      let lhs = to_pattern(&eq.lhs.expr, is_var);
+      let rhs = to_pattern(&eq.rhs.expr, is_var);
+      let lhs_vars = var_set(&lhs);
+      let rhs_vars = var_set(&rhs);
+      if rhs_vars.is_subset(&lhs_vars) {
+        let name = format!("{}-{}={}", self.name, lhs, rhs);
+        rewrites.push(Rewrite::new(name, lhs.clone(), rhs.clone()).unwrap());
       }
+      if lhs_vars.is_subset(&rhs_vars) {
+        let name = format!("{}-{}={}", self.name, rhs, lhs);
+        rewrites.push(Rewrite::new(name, rhs, lhs).unwrap());
+      }
+    }
+    rewrites
+  }
 
-      // Create an application of the constructor to the fresh vars
-      let fresh_var_strings_iter = fresh_vars.iter().map(|x| x.to_string());
-      let con_app_string = format!(
-        "({} {})",
-        con,
-        fresh_var_strings_iter
-          .clone()
-          .collect::<Vec<String>>()
-          .join(" ")
-      );
-      let con_app: Expr = con_app_string.parse().unwrap();
+  /// The functions applied in the equations and premises of this goal
+  fn functions(&self) -> HashSet<Symbol> {
+    self
+      .eqs
+      .iter()
+      .chain(self.premises.iter())
+      .flat_map(|eq| [&eq.lhs.expr, &eq.rhs.expr])
+      .flat_map(|expr| expr.as_ref().iter())
+      .map(|node| node.op)
+      .filter(|op| self.global_context.contains_key(op) && !is_constructor(op.as_str()))
+      .collect()
+  }
 
-      new_goal.name = format!("{}{}={}", new_goal.name, var, con_app);
+  /// Total number of nodes in the equations and premises of this goal
+  fn size(&self) -> usize {
+    self
+      .eqs
+      .iter()
+      .chain(self.premises.iter())
+      .map(|eq| eq.lhs.expr.as_ref().len() + eq.rhs.expr.as_ref().len())
+      .sum()
+  }
 
-      instantiated_cons_and_goals.push((con_app_string, new_goal.name.clone()));
+  /// Could `lemma` be useful in proving this goal?
+  /// Only if every function it mentions can show up while proving this goal,
+  /// i.e. it is mentioned here or in a definition this goal (transitively) uses.
+  fn may_use(&self, lemma: &Goal) -> bool {
+    let functions = self.functions();
+    lemma
+      .functions()
+      .iter()
+      .all(|f| functions.contains(f) || self.defns.contains_key(f.as_str()))
+  }
 
-      // Add con_app to the new goal's egraph and union it with var
-      new_goal.egraph.add_expr(&con_app);
-      let _con_app_id = new_goal.egraph.lookup_expr(&con_app).unwrap();
-      // Not sure if it's proper to use new_goal.name here
-      new_goal.egraph.union_instantiations(
-        &var_pattern_ast,
-        &rec_expr_to_pattern_ast(con_app.clone()),
-        &Subst::default(),
-        new_goal.name.clone(),
-      );
-      new_goal.egraph.rebuild();
-
-      // Remove old variable from the egraph and context
-      remove_node(&mut new_goal.egraph, &SymbolLang::leaf(var));
-      // warn!("removing var {}", var);
-      new_goal.egraph.rebuild();
-
-      // In cyclic mode: add the guard to premises,
-      if CONFIG.is_cyclic() && var_str.starts_with(GUARD_PREFIX) {
-        let lhs = ETerm::from_expr(self.guard_exprs[&var_str].clone(), &new_goal.egraph);
-        let rhs = ETerm::from_expr(con_app, &new_goal.egraph);
-        let eq = Equation { lhs, rhs };
-        new_goal.premises.push(eq);
-      }
-
-      // Add the subgoal to the proof state
-      state.goals.push(new_goal);
-    }
-    // We split on var into the various instantiated constructors and subgoals.
-    //
-    // If the var begins with the guard prefix, it is an ITE split and we will
-    // add the condition that was split on to our proof term. This is necessary
-    // because for ITE splits we introduce a new variable that we bind an
-    // expression to.
-    if var_str.starts_with(GUARD_PREFIX) {
-      // There should only be two cases.
-      assert_eq!(instantiated_cons_and_goals.len(), 2);
-      state.proof.insert(
-        self.name,
-        ProofTerm::ITESplit(
-          var_str.clone(),
-          self.guard_exprs[&var_str].to_string(),
-          instantiated_cons_and_goals,
-        ),
-      );
-    // Otherwise, we are doing a case split on a variable.
-    } else {
-      state.proof.insert(
-        self.name,
-        ProofTerm::CaseSplit(var_str, instantiated_cons_and_goals),
-      );
+  /// Check whether an expression is reducible using this goal's reductions
+  pub fn is_reducible(&self, expr: &Expr) -> bool {
+    let mut local_graph: Eg = Default::default();
+    local_graph.add_expr(expr);
+    local_graph.rebuild();
+    for reduction in self.def_rewrites.iter() {
+      if !reduction.search(&local_graph).is_empty() {
+        return true;
+      }
     }
+    false
   }
 
-  /// Save e-graph to file
-  fn save_egraph(&self) {
-    let filename = CONFIG.output_directory.join(format!("{}.png", self.name));
-    let verbosity = format!("-q{}", CONFIG.log_level as usize);
-    let dot = self.egraph.dot();
-    dot
-      .run_dot([
-        "-Tpng",
-        verbosity.as_str(),
-        "-o",
-        &filename.to_string_lossy(),
-      ])
-      .unwrap();
+  /// Replace the maximal subterms that occur on both sides of the equation with fresh variables.
+  /// This only applies to single-equation goals that have not been case-split and have no premises,
+  /// since only then the sides of the equation are the terms we need to prove equal.
+  /// Returns whether anything was generalized (in which case the e-graph is rebuilt from scratch).
+  pub fn generalize(&mut self) -> bool {
+    if self.generalized
+      || !self.case_splits.is_empty()
+      || !self.premises.is_empty()
+      || self.eqs.len() != 1
+    {
+      return false;
+    }
+    let lhs = self.eqs[0].lhs.sexp.clone();
+    let rhs = self.eqs[0].rhs.sexp.clone();
+    let mut rhs_subterms = vec![];
+    collect_subterms(&rhs, &mut rhs_subterms);
+    let mut common = vec![];
+    self.collect_common_subterms(&lhs, &rhs_subterms, &mut common);
+    // Generalizing a whole side would lose the connection between the two sides
+    common.retain(|(t, _)| *t != lhs && *t != rhs);
+    if common.is_empty() {
+      return false;
+    }
+    let (mut new_lhs, mut new_rhs) = (lhs, rhs);
+    let mut fresh_vars = vec![];
+    for (subterm, ty) in common {
+      let fresh_var = (0..)
+        .map(|i| Symbol::from(mangle_name(&format!("gen{}", i))))
+        .find(|x| !self.local_context.contains_key(x) && !fresh_vars.iter().any(|(y, _)| y == x))
+        .unwrap();
+      info!("generalizing {} to {}", subterm, fresh_var);
+      let var_sexp = Sexp::String(fresh_var.to_string());
+      new_lhs = replace_subterm(&new_lhs, &subterm, &var_sexp);
+      new_rhs = replace_subterm(&new_rhs, &subterm, &var_sexp);
+      fresh_vars.push((fresh_var, ty));
+    }
+    self.rebuild_equation(new_lhs, new_rhs, &fresh_vars);
+    self.generalized = true;
+    true
   }
 
-  /// Given a polymorphic constructor type and a concrete instantiation of a datatype,
-  /// return the concrete types of constructor arguments.
-  fn instantiate_constructor(con_ty: &Type, actual: &Type) -> Vec<Type> {
-    let (args, ret) = con_ty.args_ret();
-    let instantiations = find_instantiations(&ret, actual);
-    let ret = args
+  /// Replace the goal's equation with lhs = rhs, where fresh_vars are new variables,
+  /// and start the goal over with a new e-graph
+  fn rebuild_equation(&mut self, new_lhs: Sexp, new_rhs: Sexp, fresh_vars: &[(Symbol, Type)]) {
+    let mut egraph = new_egraph(self.config);
+    self.eqs = vec![Equation::new(
+      &RawEquation {
+        lhs: new_lhs,
+        rhs: new_rhs,
+      },
+      &mut egraph,
+      false,
+    )
+    // Both sides were just built from the original sides, which are valid terms
+    .unwrap()];
+    for (var, ty) in fresh_vars.iter() {
+      self.local_context.insert(*var, ty.clone());
+    }
+    // Parameters that only occurred in the generalized subterms are gone
+    let params: Vec<Symbol> = self
+      .params
       .iter()
-      .map(|arg| Type::new(resolve_sexp(&arg.repr, &instantiations)))
+      .chain(fresh_vars.iter().map(|(x, _)| x))
+      .filter(|x| egraph.lookup(SymbolLang::leaf(**x)).is_some())
+      .cloned()
       .collect();
-    ret
+    self.var_classes = lookup_vars(&egraph, params.iter());
+    self.grounding_instantiations = vec![self.var_classes.clone()];
+    self.egraph = egraph;
+    self.dirty_classes = None;
+    self.lemma_rewrites.clear();
+    self.induction_hypotheses.clear();
+    self.scrutinees.clear();
+    self.depth_exceeded = false;
+    for param in params.iter() {
+      if !self.accumulators.contains(param) {
+        let ty = self.local_context[param].clone();
+        self.add_scrutinee(*param, &ty, 0);
+      }
+    }
+    self.params = params;
   }
 
-  /// Add new grounding instantiations
-  /// that replace parent with child in previous instantiations
-  fn add_grounding(&mut self, parent: Symbol, child: Symbol) {
-    // First gather all the terms we want to instantiate:
-    // take both sides of the equation and all the premises
-    let mut sides = vec![&self.eq.lhs, &self.eq.rhs];
-    for premise in self.premises.iter() {
-      sides.push(&premise.lhs);
-      sides.push(&premise.rhs);
+  /// The goal with an accumulator generalized, if it has one:
+  /// if a side of the equation applies a function f to a nullary constructor c as an accumulator
+  /// (see `accumulator_positions`), and some function g has c as a left identity (`(g c ?y) => ?y`),
+  /// the generalized goal replaces c with a fresh variable acc and the other side with `(g other acc)`.
+  /// E.g. `(rev xs) = (revAcc xs Nil)` generalizes to `(append (rev xs) acc) = (revAcc xs acc)`.
+  /// The goal is the instance of the generalization at acc = c, once g is known to have c as a right identity too.
+  /// Only applies to single-equation goals that have not been case-split and have no premises.
+  pub fn generalize_accumulator(&self) -> Option<Goal<'a>> {
+    if !self.case_splits.is_empty() || !self.premises.is_empty() || self.eqs.len() != 1 {
+      return None;
+    }
+    let sides = [&self.eqs[0].lhs.sexp, &self.eqs[0].rhs.sexp];
+    for (k, side) in sides.iter().enumerate() {
+      let xs = match side {
+        Sexp::List(xs) => xs,
+        _ => continue,
+      };
+      let f = match xs[0].string() {
+        Ok(f) => f,
+        Err(_) => continue,
+      };
+      for i in self.accumulator_positions(f) {
+        let c = match &xs[i + 1] {
+          Sexp::String(c) if is_constructor(c) => c,
+          _ => continue,
+        };
+        let g = match self.left_identity_function(c) {
+          Some(g) => g,
+          None => continue,
+        };
+        let acc = (0..)
+          .map(|n| Symbol::from(mangle_name(&format!("acc{}", n))))
+          .find(|x| !self.local_context.contains_key(x))
+          .unwrap();
+        let ty = self.global_context[&Symbol::from(f)].args_ret().0[i].clone();
+        let mut accumulating = xs.clone();
+        accumulating[i + 1] = Sexp::String(acc.to_string());
+        let accumulated = Sexp::List(vec![
+          Sexp::String(g),
+          sides[1 - k].clone(),
+          Sexp::String(acc.to_string()),
+        ]);
+        let (lhs, rhs) = if k == 0 {
+          (Sexp::List(accumulating), accumulated)
+        } else {
+          (accumulated, Sexp::List(accumulating))
+        };
+        info!("generalizing accumulator: {} = {}", lhs, rhs);
+        let mut goal = self.copy();
+        goal.name = format!("{}_acc", self.name);
+        goal.accumulators.push(acc);
+        goal.rebuild_equation(lhs, rhs, &[(acc, ty)]);
+        return Some(goal);
+      }
     }
+    None
+  }
 
-    // Now create new instantiations from existing ones
-    let mut new_instantiations = vec![];
-    for inst in self.grounding_instantiations.iter() {
-      let replaced_canonicals: Vec<(Symbol, ETerm, bool)> = self
-        .params
-        .iter()
-        .map(|x| {
-          // Which class was this param instantiated to?
-          let id = inst.get(x).unwrap();
-          // Parameters must be canonical (at least in a clean state)
-          let canonical = CanonicalFormAnalysis::extract_canonical(&self.egraph, *id).unwrap();
-          // Try replacing the case-split variable with its child
-          let (new_expr, replaced) = replace_var(&canonical, parent, child);
-          let eterm = if replaced {
-            ETerm::new_from_expr(&new_expr, &mut self.egraph)
-          } else {
-            ETerm::from_expr(new_expr, &self.egraph)
+  /// The positions of the arguments of f that are accumulators:
+  /// every equation of f binds them to a variable,
+  /// and some recursive call of f passes a term built from that variable in their place
+  fn accumulator_positions(&self, f: &str) -> Vec<usize> {
+    let cases = match self.defns.get(f) {
+      Some(cases) => cases,
+      None => return vec![],
+    };
+    let num_args = self
+      .global_context
+      .get(&Symbol::from(f))
+      .map_or(0, |ty| ty.args_ret().0.len());
+    (0..num_args)
+      .filter(|&i| {
+        let mut grows = false;
+        for (args, value) in cases {
+          let acc = match args.list().ok().and_then(|args| args.get(i)) {
+            Some(acc @ Sexp::String(var)) if var.starts_with('?') => acc,
+            _ => return false,
           };
-          (x.clone(), eterm, replaced)
-        })
-        .collect();
-      // If any of the canonical forms had a replacement, add a new instantiation:
-      if replaced_canonicals.iter().any(|(_, _, b)| *b) {
-        let new_instantiation = replaced_canonicals
-          .iter()
-          .map(|(x, e, _)| (x.to_string(), e.sexp.clone()))
-          .collect();
-        // For each new instantiation, instantiate all the sides and add them to the egraph
-        for term in sides.iter() {
-          let new_term = resolve_sexp(&term.sexp, &new_instantiation);
-          ETerm::new(&new_term, &mut self.egraph);
+          let mut subterms = vec![];
+          collect_subterms(value, &mut subterms);
+          grows |= subterms.iter().any(|t| match t {
+            Sexp::List(ys) if ys[0] == Sexp::String(f.to_string()) => {
+              ys.get(i + 1).is_some_and(|arg| {
+                let mut leaves = vec![];
+                collect_subterms(arg, &mut leaves);
+                arg != acc && leaves.contains(acc)
+              })
+            }
+            _ => false,
+          });
         }
-        // Add the new instantiation to the list of grounding instantiations
-        let new_subst = replaced_canonicals
+        grows
+      })
+      .collect()
+  }
+
+  /// A function among the goal's definitions that has c as a left identity, i.e. an equation `(g c ?y) => ?y`
+  fn left_identity_function(&self, c: &str) -> Option<String> {
+    self
+      .defns
+      .iter()
+      .sorted_by_key(|(g, _)| *g)
+      .find_map(|(g, cases)| {
+        cases
           .iter()
-          .map(|(x, e, _)| (x.clone(), e.id))
-          .collect();
-        new_instantiations.push(new_subst);
+          .any(|(args, value)| match args.list() {
+            Ok(args) => {
+              args.len() == 2
+                && args[0] == Sexp::String(c.to_string())
+                && matches!(&args[1], Sexp::String(y) if y.starts_with('?'))
+                && *value == args[1]
+            }
+            Err(_) => false,
+          })
+          .then(|| g.clone())
+      })
+  }
+
+  /// Prove the accumulator generalization of the goal (see `generalize_accumulator`), if it has one,
+  /// and if it holds, add it to the goal's lemmas;
+  /// the generalization is proven separately, but within the time left to the proof started at start_time
+  fn add_accumulator_lemma(&mut self, start_time: Instant) {
+    if let Some(generalized) = self.generalize_accumulator() {
+      let lemmas = generalized.to_lemmas();
+      let name = generalized.name.clone();
+      let (outcome, _) = ProofSearch::started_at(generalized, start_time).finish();
+      info!("accumulator generalization {}: {}", name, outcome);
+      if outcome == Outcome::Valid {
+        self.add_lemmas(lemmas);
       }
     }
+  }
 
-    // Add the new instantiations to the list of grounding instantiations
-    self.grounding_instantiations.extend(new_instantiations);
+  /// Collect the maximal subterms of sexp that occur in subterms and can be generalized,
+  /// together with their types
+  fn collect_common_subterms(&self, sexp: &Sexp, subterms: &[Sexp], res: &mut Vec<(Sexp, Type)>) {
+    if let Sexp::List(xs) = sexp {
+      if subterms.contains(sexp) {
+        if let Some(ty) = self.generalizable_type(sexp) {
+          if !res.iter().any(|(t, _)| t == sexp) {
+            res.push((sexp.clone(), ty));
+          }
+          return;
+        }
+      }
+      for x in xs.iter().skip(1) {
+        self.collect_common_subterms(x, subterms, res);
+      }
+    }
   }
-}
 
-impl<'a> Display for Goal<'a> {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    if !self.premises.is_empty() {
-      let premises_string = self
-        .premises
+  /// The type of an application subterm, if it can be generalized:
+  /// its head must be a function (not a constructor) returning a monomorphic datatype,
+  /// and it must mention a variable of the goal (otherwise it would be better to evaluate it)
+  fn generalizable_type(&self, sexp: &Sexp) -> Option<Type> {
+    let xs = sexp.list().ok()?;
+    let head = xs[0].string().ok()?;
+    if is_constructor(head) {
+      return None;
+    }
+    let (_, ret) = self.global_context.get(&Symbol::from(head))?.args_ret();
+    let dt = ret.datatype().ok()?;
+    let monomorphic = match &ret.repr {
+      Sexp::String(_) => true,
+      Sexp::List(args) => args
         .iter()
-        .map(|premise| format!("{}", premise))
-        .collect::<Vec<String>>()
-        .join(", ");
-      write!(f, "{} ==> ", premises_string)?;
+        .skip(1)
+        .all(|a| a.string().is_ok_and(|s| is_constructor(s))),
+      Sexp::Empty => false,
+    };
+    let mut leaves = vec![];
+    collect_subterms(sexp, &mut leaves);
+    let mentions_var = leaves
+      .iter()
+      .any(|t| matches!(t, Sexp::String(s) if self.local_context.contains_key(&Symbol::from(s))));
+    if monomorphic && mentions_var && self.env.contains_key(&Symbol::from(dt)) {
+      Some(ret)
+    } else {
+      None
     }
-    write!(f, "{}", self.eq)
+  }
+
+  /// Create a rewrite `lhs => rhs` which will serve as the lemma ("induction hypothesis") for a cycle in the proof;
+  /// here lhs and rhs are patterns, created by replacing all scrutinees with wildcards;
+  /// soundness requires that the pattern only apply to variable tuples smaller than the current scrutinee tuple.
+  fn add_lemma_rewrites(&mut self, state: &mut ProofState) -> HashMap<String, Lemma> {
+    // Equations that already hold in this goal need no induction hypothesis
+    let pairs: Vec<(Id, Id)> = self
+      .eqs
+      .iter()
+      .map(|eq| (self.egraph.find(eq.lhs.id), self.egraph.find(eq.rhs.id)))
+      .filter(|(lhs_id, rhs_id)| lhs_id != rhs_id)
+      .collect();
+    let ids: Vec<Id> = pairs.iter().flat_map(|(l, r)| [*l, *r]).collect();
+    let is_var = |v| self.local_context.contains_key(v);
+    let is_cyclic = self.config.is_cyclic();
+
+    let exprs: HashMap<Id, Vec<Expr>> =
+      if is_cyclic && self.config.lemma_extraction == LemmaExtraction::Minimal {
+        // Only make a lemma out of the smallest LHS and RHS variants
+        let extractor = self.extractor();
+        ids
+          .iter()
+          .map(|id| (*id, vec![extractor.find_best(*id).1]))
+          .collect()
+      } else if is_cyclic {
+        // If we are doing cyclic proofs: make lemmas out of all LHS and RHS variants
+        // (except those too large to make a lemma out of, which are not even enumerated)
+        get_expressions_up_to(&self.egraph, ids, self.config.max_lemma_size)
+      } else {
+        HashMap::new()
+      };
+
+    // Before creating a cyclic lemma with premises,
+    // we need to update the variables in the premises
+    // with their canonical forms in terms of the current goal variables
+    let premises: Vec<Equation> = self
+      .premises
+      .iter()
+      .map(|eq| eq.update_variables(&self.var_classes, &self.egraph))
+      .collect();
+
+    let mut rewrites = self.lemma_rewrites.clone();
+    let mut candidates: Vec<(&Expr, &Expr)> = if is_cyclic {
+      pairs
+        .iter()
+        .flat_map(|(lhs_id, rhs_id)| exprs[lhs_id].iter().cartesian_product(exprs[rhs_id].iter()))
+        .collect()
+    } else if self.lemma_rewrites.is_empty() {
+      // In the non-cyclic case, only use the original LHS and RHS
+      // and only if no other lemmas have been added yet
+      self
+        .eqs
+        .iter()
+        .map(|eq| (&eq.lhs.expr, &eq.rhs.expr))
+        .collect()
+    } else {
+      vec![]
+    };
+    // Consider the smallest pairs of expressions first,
+    // so that they are the ones that survive the cap on the number of new lemmas
+    candidates
+      .sort_by_key(|(lhs_expr, rhs_expr)| lhs_expr.as_ref().len() + rhs_expr.as_ref().len());
+    let mut num_new_lemmas = 0;
+    let mut num_duplicates = 0;
+    for (lhs_expr, rhs_expr) in candidates {
+      if state.timeout() {
+        break;
+      }
+      if let Some(max) = self.config.max_lemmas_per_goal {
+        if num_new_lemmas >= max {
+          break;
+        }
+      }
+      if let Some(max) = self.config.max_lemma_size {
+        let (lhs_size, rhs_size) = (AstSize.cost_rec(lhs_expr), AstSize.cost_rec(rhs_expr));
+        if lhs_size > max || rhs_size > max {
+          debug!(
+            "skipping lemma from {} and {}: sizes {} and {} exceed {}",
+            lhs_expr, rhs_expr, lhs_size, rhs_size, max
+          );
+          continue;
+        }
+      }
+      let lhs: Pattern<SymbolLang> = to_pattern(lhs_expr, is_var);
+      if (self.config.irreducible_only && self.is_reducible(lhs_expr)) || has_guard_wildcards(&lhs)
+      {
+        continue;
+      }
+      let rhs: Pattern<SymbolLang> = to_pattern(rhs_expr, is_var);
+      if (self.config.irreducible_only && self.is_reducible(rhs_expr)) || has_guard_wildcards(&rhs)
+      {
+        continue;
+      }
+
+      let lhs_vars = var_set(&lhs);
+      let rhs_vars = var_set(&rhs);
+      let lemma_vars: HashSet<Var> = lhs_vars.union(&rhs_vars).cloned().collect();
+      if let Some(max) = self.config.max_lemma_vars {
+        if lemma_vars.len() > max {
+          debug!(
+            "skipping lemma from {} and {}: {} variables exceed {}",
+            lhs,
+            rhs,
+            lemma_vars.len(),
+            max
+          );
+          continue;
+        }
+      }
+
+      // If any of my premises contain variables that are not present in lhs or rhs,
+      // skip because we don't know how to check such a premise
+      if !premises.iter().all(|eq| {
+        let premise_lhs_vars = var_set(&to_pattern(&eq.lhs.expr, is_var));
+        let premise_rhs_vars = var_set(&to_pattern(&eq.rhs.expr, is_var));
+        let premise_vars: HashSet<Var> =
+          premise_lhs_vars.union(&premise_rhs_vars).cloned().collect();
+        premise_vars.is_subset(&lemma_vars)
+      }) {
+        continue;
+      }
+
+      // Pick out those variables that occur in the lemma
+      let lemma_var_classes: IdSubst = self
+        .var_classes
+        .iter()
+        .filter(|(x, _)| lemma_vars.contains(&to_wildcard(x)))
+        .map(|(x, id)| (*x, *id))
+        .collect();
+
+      // Functions, integers, and generalized accumulators are generalized over like any other variable,
+      // but are not checked for termination
+      let (higher_order_vars, lemma_var_classes): (IdSubst, IdSubst) =
+        lemma_var_classes.into_iter().partition(|(x, _)| {
+          self.accumulators.contains(x)
+            || self
+              .local_context
+              .get(x)
+              .is_some_and(|ty| ty.is_arrow() || ty.is_int())
+        });
+      let condition = Soundness {
+        free_vars: lemma_var_classes,
+        higher_order_vars,
+        premises: premises.clone(),
+        order: self.config.termination_order,
+        custom_order: self.config.custom_termination,
+        verify: self.config.verify_lemmas,
+      };
+      let mut added_lemma = false;
+      if rhs_vars.is_subset(&lhs_vars) {
+        // if rhs has no extra wildcards, create a lemma lhs => rhs
+        if Goal::add_lemma(lhs.clone(), rhs.clone(), condition.clone(), &mut rewrites) {
+          num_new_lemmas += 1;
+          if premises.is_empty() && !self.has_equivalences() {
+            state.record_unconditional_lemma(&lhs, &rhs);
+          }
+        } else {
+          num_duplicates += 1;
+        }
+        added_lemma = true;
+        if self.config.single_rhs {
+          continue;
+        };
+      }
+      if (is_cyclic || !added_lemma) && lhs_vars.is_subset(&rhs_vars) {
+        // if lhs has no extra wildcards, create a lemma rhs => lhs;
+        // in non-cyclic mode, a single direction of IH is always sufficient
+        // (because grounding adds all instantiations we could possibly care about).
+        if Goal::add_lemma(rhs.clone(), lhs.clone(), condition, &mut rewrites) {
+          num_new_lemmas += 1;
+          if premises.is_empty() && !self.has_equivalences() {
+            state.record_unconditional_lemma(&rhs, &lhs);
+          }
+        } else {
+          num_duplicates += 1;
+        }
+        added_lemma = true;
+        if self.config.single_rhs {
+          continue;
+        };
+      }
+      if !added_lemma {
+        debug!("cannot create a lemma from {} and {}", lhs, rhs);
+      }
+    }
+    state.report.lemmas_created += num_new_lemmas;
+    state.report.duplicate_lemmas += num_duplicates;
+    rewrites
+  }
+
+  /// Add a rewrite `lhs => rhs` to `rewrites` if not already present;
+  /// returns whether it was added
+  fn add_lemma(lhs: Pat, rhs: Pat, cond: Soundness, rewrites: &mut HashMap<String, Lemma>) -> bool {
+    let name = lemma_name(&lhs, &rhs);
+    // Insert the lemma into the rewrites map if it's not already there
+    match rewrites.entry(name.clone()) {
+      Entry::Occupied(_) => false,
+      Entry::Vacant(entry) => {
+        debug!("creating lemma: {} => {}", lhs, rhs);
+        entry.insert(Lemma::new(name, lhs, rhs, cond));
+        true
+      }
+    }
+  }
+
+  /// Add var as a scrutinee if its type `ty` is a datatype (and it is not a scrutinee already,
+  /// e.g. because a parameter name is repeated, which would split it twice);
+  /// if depth bound is exceeded, only record that in `depth_exceeded`.
+  /// Variables of record types (see `record_constructor`) are added too,
+  /// but `destructure_records` takes them out again before the goal is worked on.
+  fn add_scrutinee(&mut self, var: Symbol, ty: &Type, depth: usize) {
+    match within_split_depth(ty, depth, self.env, self.config) {
+      Some(true) if !self.scrutinees.contains(&var) => self.scrutinees.push_back(var),
+      Some(false) => self.depth_exceeded = true,
+      _ => {}
+    }
+  }
+
+  /// Instantiate every scrutinee of a record type with its only constructor right away,
+  /// as `case_split` would, but without saturating the goal first or counting towards `max_total_splits`:
+  /// the split has a single case, which just names the fields.
+  /// Fields that are records themselves are destructured in turn (within the split depth).
+  /// Each split is recorded in the proof, so the goal is renamed like the child of a split.
+  fn destructure_records(&mut self, state: &mut ProofState<'a>) {
+    while let Some(pos) = self.scrutinees.iter().position(|var| {
+      record_constructor(&self.local_context[var], self.env, self.global_context).is_some()
+    }) {
+      let var = self.scrutinees.remove(pos).unwrap();
+      let ty = self.local_context[&var].clone();
+      let con = record_constructor(&ty, self.env, self.global_context).unwrap();
+      info!("destructuring record {}", var);
+      let first_fresh_var = state.num_fresh_vars;
+      state.num_fresh_vars += self.global_context[&con].args_ret().0.len();
+      let (con_app_string, _) = self.instantiate_scrutinee(var, &ty, con, first_fresh_var, &None);
+      let con_app: Expr = con_app_string.parse().unwrap();
+      let child_name = format!("{}:{}={}", self.name, var, con_app);
+      state.record_split(&self.name, var, &None, con_app_string, &child_name);
+      self.case_splits.push((var, con_app));
+      self.name = child_name;
+    }
+  }
+
+  /// Move the scrutinees of datatypes with more constructors than `max_constructor_fanout`
+  /// to the back of the queue, keeping the order of the others (so guards are still split first);
+  /// returns false if all scrutinees are of such datatypes
+  fn defer_wide_scrutinees(&mut self) -> bool {
+    defer_wide_scrutinees(
+      &mut self.scrutinees,
+      &self.local_context,
+      self.env,
+      self.config,
+    )
+  }
+
+  /// The datatype of a stuck e-class, if it contains an application of a known function
+  fn stuck_class_type(&self, id: Id) -> Option<Type> {
+    self.egraph[id].nodes.iter().find_map(|node| {
+      let (_, ret) = self.global_context.get(&node.op)?.args_ret();
+      let dt = ret.datatype().ok()?;
+      if self.env.contains_key(&Symbol::from(dt)) {
+        Some(ret)
+      } else {
+        None
+      }
+    })
+  }
+
+  /// Collect the atomic conditions of a stuck Boolean e-class into guards:
+  /// if the class is a connective application, we split on its stuck operands instead,
+  /// since the connective reduces once they are known;
+  /// if the class is an equality test between variables we can still split on
+  /// (and the configuration asks for it), we collect those variables into operands instead,
+  /// since a fresh Boolean for the test would lose the connection to its operands.
+  fn collect_atomic_guards(
+    &self,
+    id: Id,
+    guards: &mut HashMap<Id, Type>,
+    operands: &mut Vec<Symbol>,
+    visited: &mut HashSet<Id>,
+  ) {
+    let id = self.egraph.find(id);
+    if !visited.insert(id) {
+      return;
+    }
+    if let CanonicalForm::Stuck = self.egraph[id].data {
+      let connective = self.egraph[id].nodes.iter().find(|n| is_connective(&n.op));
+      match connective {
+        Some(node) => {
+          for child in node.children.iter() {
+            self.collect_atomic_guards(*child, guards, operands, visited);
+          }
+        }
+        None => {
+          let vars = if self.config.split_equality_operands {
+            self.equality_operand_scrutinees(id)
+          } else {
+            vec![]
+          };
+          if vars.is_empty() {
+            guards.insert(id, BOOL_TYPE.parse().unwrap());
+          }
+          for var in vars {
+            if !operands.contains(&var) {
+              operands.push(var);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// If class id contains an equality test on a datatype, the operands of the test
+  /// that are variables we can still split on
+  fn equality_operand_scrutinees(&self, id: Id) -> Vec<Symbol> {
+    let mut vars = vec![];
+    for node in self.egraph[id].nodes.iter() {
+      if !is_equality_name(&node.op) || !self.is_datatype_equality(&node.op) {
+        continue;
+      }
+      for child in node.children.iter() {
+        if let CanonicalForm::Var(var) = &self.egraph[*child].data {
+          if self.scrutinees.contains(&var.op) && !self.is_guard_scrutinee(&var.op) {
+            vars.push(var.op);
+          }
+        }
+      }
+    }
+    vars
+  }
+
+  /// Whether the function f has type `T -> T -> Bool` for a datatype T,
+  /// so that splitting its operands eventually decides it
+  fn is_datatype_equality(&self, f: &Symbol) -> bool {
+    let Some(ty) = self.global_context.get(f) else {
+      return false;
+    };
+    let (args, ret) = ty.args_ret();
+    args.len() == 2
+      && args[0] == args[1]
+      && ret.repr == Sexp::String(BOOL_TYPE.to_string())
+      && args[0]
+        .datatype()
+        .is_ok_and(|dt| self.env.contains_key(&Symbol::from(dt)))
+  }
+
+  /// Number of e-nodes that have each e-class as a child (counting an e-node once per class)
+  fn num_occurrences(&self) -> HashMap<Id, usize> {
+    let mut occurrences = HashMap::new();
+    for node in self.egraph.classes().flat_map(|class| class.iter()) {
+      for child in node
+        .children()
+        .iter()
+        .map(|c| self.egraph.find(*c))
+        .unique()
+      {
+        *occurrences.entry(child).or_insert(0) += 1;
+      }
+    }
+    occurrences
+  }
+
+  /// The e-classes reachable from the sides of the goal's equations and premises
+  fn reachable_classes(&self) -> HashSet<Id> {
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<Id> = self
+      .eqs
+      .iter()
+      .chain(self.premises.iter())
+      .flat_map(|eq| [eq.lhs.id, eq.rhs.id])
+      .collect();
+    while let Some(id) = stack.pop() {
+      let id = self.egraph.find(id);
+      if reachable.insert(id) {
+        stack.extend(
+          self.egraph[id]
+            .iter()
+            .flat_map(|node| node.children().iter().copied()),
+        );
+      }
+    }
+    reachable
+  }
+
+  /// An application in the goal of a defined function to closed constructor terms
+  /// that saturation did not rewrite to a constructor term, if there is one:
+  /// no case of the function's definition matches those arguments (the definition is partial),
+  /// so the application is stuck however the goal is split, and the goal cannot be refuted by evaluation.
+  /// (Only meaningful once the goal has been saturated completely, with no opaque functions.)
+  pub fn stuck_application(&self) -> Option<Expr> {
+    let mut classes: Vec<Id> = self.reachable_classes().into_iter().collect();
+    // (in a fixed order, so that the same application is reported every time)
+    classes.sort();
+    classes.into_iter().find_map(|id| {
+      if !matches!(self.egraph[id].data, CanonicalForm::Stuck) {
+        return None;
+      }
+      self.egraph[id].iter().find_map(|node| {
+        let arity = self
+          .global_context
+          .get(&node.op)
+          .filter(|_| self.defns.contains_key(node.op.as_str()))
+          .map(|ty| ty.args_ret())
+          .filter(|(_, ret)| !ret.is_arrow())
+          .map(|(args, _)| args.len())?;
+        let closed = node.children.len() == arity
+          && node
+            .children
+            .iter()
+            .all(|child| is_closed_constructor_term(&self.egraph, *child));
+        closed.then(|| {
+          node.join_recexprs(|child| {
+            CanonicalFormAnalysis::extract_canonical(&self.egraph, child).unwrap()
+          })
+        })
+      })
+    })
+  }
+
+  /// Move the scrutinees that occur nowhere in the goal's equations and premises
+  /// (e.g. unused parameters) to the back of the queue, keeping the order of the others,
+  /// since splitting them cannot change the goal;
+  /// returns false if all scrutinees are such
+  fn defer_irrelevant_scrutinees(&mut self) -> bool {
+    let reachable = self.reachable_classes();
+    let (relevant, irrelevant): (VecDeque<Symbol>, VecDeque<Symbol>) =
+      self.scrutinees.iter().partition(|var| {
+        self
+          .egraph
+          .lookup(SymbolLang::leaf(**var))
+          .is_some_and(|id| reachable.contains(&self.egraph.find(id)))
+      });
+    if relevant.is_empty() {
+      return false;
+    }
+    if !irrelevant.is_empty() {
+      debug!("deferring irrelevant scrutinees {:?}", irrelevant);
+    }
+    self.scrutinees = relevant;
+    self.scrutinees.extend(irrelevant);
+    true
+  }
+
+  /// If the egraph contains ITEs or matches whose condition is "irreducible"
+  /// (i.e. not equivalent to a constant or a scrutinee variable),
+  /// add a fresh scrutinee to its eclass, so that we can match on it;
+  /// the scrutinees are numbered by the same counter as the fresh variables of case splits.
+  /// The new scrutinees are split on first, except that beyond `max_ite_scrutinees` of them
+  /// (those of the guards that occur in the fewest e-nodes) are only split on after all other scrutinees.
+  fn split_ite(&mut self, state: &mut ProofState) {
+    let guard_var = "?g".parse().unwrap();
+    // Collects class IDs of all stuck guards together with their types;
+    // it's a map because the same guard can match more than once, but we only want to add a new scrutinee once
+    let mut stuck_guards = HashMap::new();
+    // Pattern "(ite ?g ?x ?y)"
+    let searcher: Pattern<SymbolLang> = format!("({} {} ?x ?y)", *ITE, guard_var).parse().unwrap();
+    // Variables compared by stuck equality tests, which we split on instead of the tests
+    let mut operands = vec![];
+    let mut visited = HashSet::new();
+    for m in searcher.search(&self.egraph) {
+      for subst in m.substs {
+        let guard_id = *subst.get(guard_var).unwrap();
+        self.collect_atomic_guards(guard_id, &mut stuck_guards, &mut operands, &mut visited);
+      }
+    }
+    // Move the operands to the front of the queue
+    // (the guards added below still go in front of them)
+    for var in operands.iter().rev() {
+      self.scrutinees.retain(|s| s != var);
+      self.scrutinees.push_front(*var);
+    }
+    // Patterns "(match ?g ?a0 ... ?an)" for every number of constructors n a datatype can have;
+    // here the type of the guard is not fixed, so we infer it from the function applications in its class
+    let num_arms: HashSet<usize> = self.env.values().map(|(_, cons)| cons.len()).collect();
+    for n in num_arms {
+      let arms: Vec<String> = (0..n).map(|i| format!("?a{}", i)).collect();
+      let searcher: Pattern<SymbolLang> = format!("({} {} {})", *MATCH, guard_var, arms.join(" "))
+        .parse()
+        .unwrap();
+      for m in searcher.search(&self.egraph) {
+        for subst in m.substs {
+          let guard_id = *subst.get(guard_var).unwrap();
+          if let CanonicalForm::Stuck = self.egraph[guard_id].data {
+            if let Some(ty) = self.stuck_class_type(guard_id) {
+              let (_, cons) = &self.env[&Symbol::from(ty.datatype().unwrap())];
+              if cons.len() == n {
+                stuck_guards.insert(guard_id, ty);
+              }
+            }
+          }
+        }
+      }
+    }
+    let deferred = match self.config.max_ite_scrutinees {
+      Some(max) if stuck_guards.len() > max => {
+        let occurrences = self.num_occurrences();
+        stuck_guards
+          .keys()
+          .sorted_by_key(|id| {
+            (
+              std::cmp::Reverse(occurrences.get(*id).copied().unwrap_or(0)),
+              **id,
+            )
+          })
+          .skip(max)
+          .copied()
+          .collect()
+      }
+      _ => HashSet::new(),
+    };
+    // Iterate over all stuck guard eclasses and add a new scrutinee to each
+    // (in a fixed order, so that the names do not depend on how the map is hashed)
+    for (guard_id, ty) in stuck_guards.into_iter().sorted_by_key(|(id, _)| *id) {
+      let fresh_var = Symbol::from(format!("{}{}", GUARD_PREFIX, state.num_fresh_vars));
+      state.num_fresh_vars += 1;
+      // This is here only for logging purposes
+      let expr = self.extractor().find_best(guard_id).1;
+      let add_scrutinee_message =
+        format!("adding scrutinee {} to split condition {}", fresh_var, expr);
+      debug!("{}", add_scrutinee_message);
+      self.local_context.insert(fresh_var, ty);
+      // We are adding the new scrutinee to the front of the deque (unless it is deferred),
+      // because we want to split conditions first, since they don't introduce new variables
+      if deferred.contains(&guard_id) {
+        debug!("deferring split on {}", fresh_var);
+        self.scrutinees.push_back(fresh_var);
+      } else {
+        self.scrutinees.push_front(fresh_var);
+      }
+      let new_node = SymbolLang::leaf(fresh_var);
+      let new_pattern_ast = vec![ENodeOrVar::ENode(new_node.clone())].into();
+      let guard_var_pattern_ast = vec![ENodeOrVar::Var(guard_var)].into();
+      let mut subst = Subst::default();
+      subst.insert(guard_var, guard_id);
+      self.guard_exprs.insert(fresh_var.to_string(), expr);
+      self.egraph.union_instantiations(
+        &guard_var_pattern_ast,
+        &new_pattern_ast,
+        &subst,
+        add_scrutinee_message,
+      );
+      self.mark_dirty(guard_id);
+    }
+    self.egraph.rebuild();
+    self.canonicalize_roots();
+  }
+
+  /// Choose the next variable to case-split on according to the scrutinee heuristic;
+  /// guards are always chosen first, since `split_ite` puts them at the front of the queue.
+  fn select_scrutinee(&self) -> Symbol {
+    let front = *self.scrutinees.front().unwrap();
+    if self.is_guard_scrutinee(&front) {
+      return front;
+    }
+    match self.config.scrutinee_heuristic {
+      ScrutineeHeuristic::Fifo => front,
+      ScrutineeHeuristic::MostMatches => {
+        // Count how many times each e-class is bound by a match of a reduction
+        let mut match_counts: HashMap<Id, usize> = HashMap::new();
+        for reduction in self.def_rewrites.iter() {
+          for m in reduction.search(&self.egraph) {
+            for subst in m.substs {
+              for v in reduction.searcher.vars() {
+                if let Some(id) = subst.get(v) {
+                  *match_counts.entry(self.egraph.find(*id)).or_default() += 1;
+                }
+              }
+            }
+          }
+        }
+        let count = |var: &Symbol| {
+          self
+            .egraph
+            .lookup(SymbolLang::leaf(*var))
+            .and_then(|id| match_counts.get(&self.egraph.find(id)))
+            .copied()
+            .unwrap_or(0)
+        };
+        // Pick the scrutinee with the most matches, preferring earlier ones on ties
+        let mut best = front;
+        let mut best_count = count(&front);
+        for var in self.scrutinees.iter().skip(1) {
+          let var_count = count(var);
+          if var_count > best_count {
+            best = *var;
+            best_count = var_count;
+          }
+        }
+        best
+      }
+    }
+  }
+
+  /// Consume this goal and add its case splits to the proof state;
+  /// if the chosen scrutinee belongs to a group marked for joint induction,
+  /// all scrutinees of the group are split at once, and the subgoals cover their constructor cross-product;
+  /// returns the scrutinee that was chosen
+  fn case_split(mut self, state: &mut ProofState<'a>) -> Symbol {
+    // Without lemmas, the subgoals have to be proven by the definitions alone,
+    // so there is no point creating induction hypotheses
+    let new_lemmas = if self.config.use_lemmas {
+      self.add_lemma_rewrites(state)
+    } else {
+      self.lemma_rewrites.clone()
+    };
+    // The lemmas created here are the induction hypotheses of all the subgoals
+    let induction_hypotheses: Vec<Rw> = new_lemmas
+      .iter()
+      .filter(|(name, _)| !self.lemma_rewrites.contains_key(*name))
+      .sorted_by(|(name1, _), (name2, _)| name1.cmp(name2))
+      .map(|(_, lemma)| lemma.rewrite.clone())
+      .collect();
+
+    // Get the next variable to case-split on, together with the rest of its joint group
+    let var = self.select_scrutinee();
+    let vars: Vec<Symbol> = match self.joint_groups.iter().position(|g| g.contains(&var)) {
+      Some(i) => {
+        let group = self.joint_groups.remove(i);
+        // Members of the group that have hit the depth bound are no longer split
+        let mut vars = vec![var];
+        vars.extend(
+          self
+            .scrutinees
+            .iter()
+            .filter(|s| **s != var && group.contains(s))
+            .cloned(),
+        );
+        vars
+      }
+      None => vec![var],
+    };
+    for var in vars.iter() {
+      debug_assert!(
+        self.local_context.contains_key(var),
+        "scrutinee {} not in local context",
+        var
+      );
+      let pos = self.scrutinees.iter().position(|s| s == var).unwrap();
+      self.scrutinees.remove(pos);
+      info!("case-split on {}", var);
+    }
+    state.num_splits += 1;
+    // Get the types of the variables and the constructors of their datatypes
+    let env = self.env;
+    let splits: Vec<(Symbol, Type, &Vec<Symbol>)> = vars
+      .iter()
+      .map(|var| {
+        let ty = self.local_context[var].clone();
+        let (_, cons) = env.get(&Symbol::from(ty.datatype().unwrap())).unwrap();
+        (*var, ty, cons)
+      })
+      .collect();
+    // Each case picks one constructor per variable
+    // (we process constructors in reverse order so that base case ends up at the top of the stack)
+    let cases: Vec<Vec<Symbol>> = splits
+      .iter()
+      .map(|(_, _, cons)| cons.iter().rev().cloned())
+      .multi_cartesian_product()
+      .collect();
+    // Number the fresh variables of every constructor of every variable;
+    // the counter makes their names unique within the proof, so they cannot clash with a variable
+    // that was removed from the e-graph (egg would still find the removed leaf in its hashcons),
+    // and in a joint split each instantiation gets the same names in every case it occurs in
+    let mut first_fresh_var: HashMap<(Symbol, Symbol), usize> = HashMap::new();
+    for (var, _, cons) in splits.iter() {
+      for con in cons.iter() {
+        first_fresh_var.insert((*var, *con), state.num_fresh_vars);
+        state.num_fresh_vars += self.global_context[con].args_ret().0.len();
+      }
+    }
+    // The cases are recorded in the proof right away, but only become subgoals when they are taken,
+    // so that the goal is copied for no more cases than are worked on
+    let guard_expr = self.guard_exprs.get(&var.to_string()).cloned();
+    for case in cases.iter() {
+      let mut goal_name = self.name.clone();
+      let mut instantiations = vec![];
+      for ((var, ty, _), &con) in splits.iter().zip(case.iter()) {
+        let first_fresh_var = first_fresh_var[&(*var, con)];
+        let arity = self.global_context[&con].args_ret().0.len();
+        let fresh_vars: Vec<Symbol> = (0..arity)
+          .map(|i| fresh_var(*var, first_fresh_var + i))
+          .collect();
+        let con_app_string = con_app_string(con, &fresh_vars);
+        let con_app: Expr = con_app_string.parse().unwrap();
+        let child_name = format!("{}:{}={}", goal_name, var, con_app);
+        // We split on var into the various instantiated constructors and subgoals;
+        // a joint split is recorded as a sequence of single splits, one per variable,
+        // so the names of intermediate goals are the prefixes of the final ones.
+        //
+        // If the var is a guard, it is an ITE split and we will
+        // add the condition that was split on to our proof term. This is necessary
+        // because for ITE splits we introduce a new variable that we bind an
+        // expression to. (We don't just check the guard prefix here,
+        // because the fields of a guard split on a match also start with it.)
+        // Otherwise, we are doing a case split on a variable.
+        state.record_split(&goal_name, *var, &guard_expr, con_app_string, &child_name);
+        instantiations.push((*var, ty.clone(), con, first_fresh_var));
+        goal_name = child_name;
+      }
+      self.pending_cases.push(PendingCase {
+        name: goal_name,
+        instantiations,
+      });
+    }
+    self.lemma_rewrites = new_lemmas;
+    self.induction_hypotheses = induction_hypotheses;
+    self.discharged_by = None;
+    self.saturation_stats = None;
+    state.goals.push_back(self);
+    var
+  }
+
+  /// The names of the goals this goal stands for in the proof state:
+  /// its pending cases (see `PendingCase`), or the goal itself
+  fn case_names(&self) -> Vec<String> {
+    if self.pending_cases.is_empty() {
+      vec![self.name.clone()]
+    } else {
+      self
+        .pending_cases
+        .iter()
+        .map(|case| case.name.clone())
+        .collect()
+    }
+  }
+
+  /// Snapshots of the goals this goal stands for in the proof state (see `case_names`)
+  fn case_snapshots(&self) -> Vec<GoalSnapshot> {
+    if self.pending_cases.is_empty() {
+      vec![self.snapshot()]
+    } else {
+      (self.pending_cases.iter())
+        .map(|case| self.subgoal(case).snapshot())
+        .collect()
+    }
+  }
+
+  /// The subgoal of a pending case of this goal, on a copy of it
+  fn subgoal(&self, case: &PendingCase) -> Goal<'a> {
+    let mut subgoal = self.copy();
+    subgoal.lemma_rewrites = self.lemma_rewrites.clone();
+    subgoal.instantiate_case(case);
+    subgoal
+  }
+
+  /// Turn this goal (which has been split) into the subgoal of case
+  fn instantiate_case(&mut self, case: &PendingCase) {
+    let guard_expr = self
+      .guard_exprs
+      .get(&case.instantiations[0].0.to_string())
+      .cloned();
+    let mut goal_name = self.name.clone();
+    let mut next_group = vec![];
+    for (var, ty, con, first_fresh_var) in case.instantiations.iter() {
+      self.name = goal_name.clone();
+      let (con_app_string, recursive_vars) =
+        self.instantiate_scrutinee(*var, ty, *con, *first_fresh_var, &guard_expr);
+      let con_app: Expr = con_app_string.parse().unwrap();
+      goal_name = format!("{}:{}={}", goal_name, var, con_app);
+      self.case_splits.push((*var, con_app));
+      next_group.extend(recursive_vars);
+    }
+    debug_assert_eq!(goal_name, case.name);
+    self.name = goal_name;
+    // The recursive fields of a joint split are split jointly again
+    if case.instantiations.len() > 1 && next_group.len() > 1 {
+      self.joint_groups.push(next_group);
+    }
+  }
+
+  /// Instantiate the scrutinee var of type ty with constructor con applied to fresh variables
+  /// (numbered from first_fresh_var);
+  /// returns the constructor application (as a string) and those fresh variables that have the same type as var.
+  fn instantiate_scrutinee(
+    &mut self,
+    var: Symbol,
+    ty: &Type,
+    con: Symbol,
+    first_fresh_var: usize,
+    guard_expr: &Option<Expr>,
+  ) -> (String, Vec<Symbol>) {
+    let var_node = SymbolLang::leaf(var);
+    let var_pattern_ast: RecExpr<ENodeOrVar<SymbolLang>> = vec![ENodeOrVar::ENode(var_node)].into();
+    // Get the types of constructor arguments
+    let con_ty = self.global_context.get(&con).unwrap();
+    let con_args = Goal::instantiate_constructor(con_ty, ty);
+    // For each argument: create a fresh variable and add it to the context and to scrutinees
+    let mut fresh_vars = vec![];
+    let mut recursive_vars = vec![];
+
+    for (i, arg_type) in con_args.iter().enumerate() {
+      let fresh_var = fresh_var(var, first_fresh_var + i);
+      let depth = var_depth(fresh_var.as_str());
+      debug_assert!(
+        self.egraph.lookup(SymbolLang::leaf(fresh_var)).is_none(),
+        "fresh variable {} is already in the e-graph",
+        fresh_var
+      );
+      fresh_vars.push(fresh_var);
+      // Add new variable to context
+      self.local_context.insert(fresh_var, arg_type.clone());
+      self.add_scrutinee(fresh_var, arg_type, depth);
+      let id = self.egraph.add(SymbolLang::leaf(fresh_var));
+      self.var_classes.insert(fresh_var, id);
+
+      if ty == arg_type {
+        recursive_vars.push(fresh_var);
+        if !self.config.is_cyclic() {
+          // This is a recursive constructor parameter:
+          // add new grounding instantiations replacing var with fresh_var
+          self.add_grounding(var, fresh_var);
+        }
+      }
+    }
+
+    // Create an application of the constructor to the fresh vars
+    let con_app_string = con_app_string(con, &fresh_vars);
+    let con_app: Expr = con_app_string.parse().unwrap();
+    let reason = format!("{}:{}={}", self.name, var, con_app);
+
+    // Add con_app to the new goal's egraph and union it with var
+    self.egraph.add_expr(&con_app);
+    // Not sure if it's proper to use the goal name here
+    let (var_id, _) = self.egraph.union_instantiations(
+      &var_pattern_ast,
+      &rec_expr_to_pattern_ast(con_app.clone()),
+      &Subst::default(),
+      reason,
+    );
+    self.mark_dirty(var_id);
+    self.egraph.rebuild();
+
+    // Remove old variable from the egraph and context;
+    // its class survives (with con_app in it), so the cached ids of terms mentioning var stay valid
+    remove_node(&mut self.egraph, &SymbolLang::leaf(var));
+    // warn!("removing var {}", var);
+    self.egraph.rebuild();
+    self.canonicalize_roots();
+
+    // In cyclic mode: add the guard to premises,
+    if self.config.is_cyclic() && guard_expr.is_some() {
+      let lhs = ETerm::from_expr(guard_expr.clone().unwrap(), &self.egraph);
+      let rhs = ETerm::from_expr(con_app.clone(), &self.egraph);
+      let eq = Equation { lhs, rhs };
+      self.premises.push(eq);
+    }
+    (con_app_string, recursive_vars)
+  }
+
+  /// A signature of the proof obligation of this goal that does not depend on variable names:
+  /// the sides of the equation, the premises and the guards split on so far in terms of the current variables,
+  /// followed by the sorted types of the scrutinees;
+  /// variables are numbered in the order they occur,
+  /// after sorting the arguments of symmetric constructors (see `Env::symmetric`).
+  /// Returns the signature together with the variables in the order they were numbered,
+  /// or None if the goal has pending guards, whose meaning the signature does not capture.
+  fn signature(&self) -> Option<(String, Vec<Symbol>)> {
+    if self.scrutinees.iter().any(|s| self.is_guard_scrutinee(s)) {
+      return None;
+    }
+    // Replace every variable with its canonical form, like `Equation::update_variables`
+    let mut subst = SSubst::new();
+    for (x, id) in self.var_classes.iter() {
+      let expr = CanonicalFormAnalysis::extract_canonical(&self.egraph, *id)?;
+      subst.insert(x.to_string(), parser::parse_str(&expr.to_string()).ok()?);
+    }
+    let mut sides = vec![];
+    for eq in self.eqs.iter().chain(self.premises.iter()) {
+      sides.push(eq.lhs.sexp.clone());
+      sides.push(eq.rhs.sexp.clone());
+    }
+    // The values of the guards we split on are only recorded in the e-graph
+    for (var, con_app) in self.case_splits.iter() {
+      if let Some(guard_expr) = self.guard_exprs.get(var.as_str()) {
+        sides.push(parser::parse_str(&guard_expr.to_string()).ok()?);
+        sides.push(parser::parse_str(&con_app.to_string()).ok()?);
+      }
+    }
+    let mut vars: Vec<Symbol> = vec![];
+    let is_var = |x: &Symbol| self.local_context.contains_key(x);
+    let normalized: Vec<String> = sides
+      .into_iter()
+      .map(|side| {
+        let side = self
+          .env
+          .sort_symmetric_args(&resolve_sexp(&side, &subst), &is_var);
+        number_vars(&side, &is_var, &mut vars).to_string()
+      })
+      .collect();
+    let mut scrutinee_types: Vec<String> = self
+      .scrutinees
+      .iter()
+      .map(|s| match self.local_context.get(s) {
+        Some(ty) => ty.to_string(),
+        None => s.to_string(),
+      })
+      .collect();
+    scrutinee_types.sort();
+    let mut signature = format!("{} | {}", normalized.join(" "), scrutinee_types.join(" "));
+    if self.depth_exceeded {
+      signature.push_str(" | depth exceeded");
+    }
+    Some((signature, vars))
+  }
+
+  /// Whether this goal comes from a case split on a symmetric constructor,
+  /// so its descendants may differ from each other only by argument order
+  fn split_on_symmetric(&self) -> bool {
+    self
+      .case_splits
+      .iter()
+      .any(|(_, con_app)| self.env.is_symmetric(&con_app.as_ref().last().unwrap().op))
+  }
+
+  /// The e-graph in Graphviz, with the classes of the sides of the goal's equations shaded and labelled
+  /// (blue for lhs, red for rhs, green for both) and the leaves of the scrutinees left to split on filled in yellow;
+  /// the annotations are extra statements that Graphviz merges into egg's clusters and nodes
+  fn annotated_dot(&self) -> Dot<'_, SymbolLang, CanonicalFormAnalysis> {
+    let lhs: HashSet<Id> = self
+      .eqs
+      .iter()
+      .map(|eq| self.egraph.find(eq.lhs.id))
+      .collect();
+    let rhs: HashSet<Id> = self
+      .eqs
+      .iter()
+      .map(|eq| self.egraph.find(eq.rhs.id))
+      .collect();
+    let mut dot = self.egraph.dot();
+    for id in lhs.union(&rhs).sorted() {
+      let (label, color) = match (lhs.contains(id), rhs.contains(id)) {
+        (true, true) => ("lhs = rhs", "#d9ead3"),
+        (true, false) => ("lhs", "#cfe2f3"),
+        _ => ("rhs", "#f4cccc"),
+      };
+      dot = dot.with_config_line(format!(
+        "subgraph cluster_{} {{ bgcolor=\"{}\" label=\"{}\" }}",
+        id, color, label
+      ));
+    }
+    for var in self.scrutinees.iter() {
+      let leaf = SymbolLang::leaf(*var);
+      if let Some(id) = self.egraph.lookup(leaf.clone()) {
+        if let Some(i) = self.egraph[id].iter().position(|node| *node == leaf) {
+          dot = dot.with_config_line(format!("{}.{} [style=filled fillcolor=\"#ffe599\"]", id, i));
+        }
+      }
+    }
+    dot
+  }
+
+  /// Save a drawing of the e-graph to the graph directory in the configured format;
+  /// this is only a debugging aid, so failures (e.g. a missing Graphviz) are logged rather than fatal
+  fn save_egraph(&self) {
+    let format = self.config.graph_format;
+    let path = self.config.graph_dir.join(format!(
+      "{}.{}",
+      filesystem_safe_name(&self.name),
+      format.extension()
+    ));
+    let dot = self.annotated_dot();
+    let result = match format {
+      GraphFormat::Dot => dot.to_dot(&path),
+      _ => {
+        let output_format = format!("-T{}", format.extension());
+        let verbosity = format!("-q{}", log_level_index(self.config.log_level));
+        dot.run_dot([
+          output_format.as_str(),
+          verbosity.as_str(),
+          "-o",
+          &path.to_string_lossy(),
+        ])
+      }
+    };
+    if let Err(err) = result {
+      warn!("could not save e-graph to {}: {}", path.display(), err);
+    }
+  }
+
+  /// Export the e-graph as JSON, so that it can be inspected or diffed without Graphviz.
+  /// The schema is:
+  /// ```
+  /// { "goal": name, "lhs": class id, "rhs": class id, "equations": [[class id, class id]],
+  ///   "classes": [{ "id": class id, "roots": ["lhs", "rhs"], "form": "stuck" | "var" | "const" | "inconsistent",
+  ///                 "nodes": [{ "op": symbol, "children": [class id] }] }] }
+  /// ```
+  /// where "lhs" and "rhs" are the sides of the first equation, "equations" lists the sides of all of them,
+  /// classes are sorted by id and "roots" lists which sides of the goal's equations are in the class.
+  pub fn export_egraph_json(&self, path: &Path) -> std::io::Result<()> {
+    let lhs: Vec<Id> = self
+      .eqs
+      .iter()
+      .map(|eq| self.egraph.find(eq.lhs.id))
+      .collect();
+    let rhs: Vec<Id> = self
+      .eqs
+      .iter()
+      .map(|eq| self.egraph.find(eq.rhs.id))
+      .collect();
+    let mut classes: Vec<&EClass<SymbolLang, CanonicalForm>> = self.egraph.classes().collect();
+    classes.sort_by_key(|class| class.id);
+    let classes: Vec<serde_json::Value> = classes
+      .into_iter()
+      .map(|class| {
+        let mut roots = vec![];
+        if lhs.contains(&class.id) {
+          roots.push("lhs");
+        }
+        if rhs.contains(&class.id) {
+          roots.push("rhs");
+        }
+        let form = match class.data {
+          CanonicalForm::Stuck => "stuck",
+          CanonicalForm::Var(_) => "var",
+          CanonicalForm::Const(_) => "const",
+          CanonicalForm::Inconsistent(..) => "inconsistent",
+        };
+        let nodes: Vec<serde_json::Value> = class
+          .nodes
+          .iter()
+          .map(|node| {
+            let children: Vec<usize> = node
+              .children
+              .iter()
+              .map(|c| usize::from(self.egraph.find(*c)))
+              .collect();
+            json!({ "op": node.op.as_str(), "children": children })
+          })
+          .collect();
+        json!({ "id": usize::from(class.id), "roots": roots, "form": form, "nodes": nodes })
+      })
+      .collect();
+    let json = json!({
+      "goal": self.name,
+      "lhs": usize::from(lhs[0]),
+      "rhs": usize::from(rhs[0]),
+      "equations": lhs.iter().zip(rhs.iter()).map(|(l, r)| [usize::from(*l), usize::from(*r)]).collect::<Vec<_>>(),
+      "classes": classes,
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&json)?)
+  }
+
+  /// Given a polymorphic constructor type and a concrete instantiation of a datatype,
+  /// return the concrete types of constructor arguments
+  /// (e.g. the arguments of `Cons` in a `(List Nat)` are a `Nat` and a `(List Nat)`).
+  fn instantiate_constructor(con_ty: &Type, actual: &Type) -> Vec<Type> {
+    let (args, ret) = con_ty.args_ret();
+    let instantiations = find_instantiations(&ret, actual);
+    args
+      .iter()
+      .map(|arg| arg.instantiate(&instantiations))
+      .collect()
+  }
+
+  /// Add new grounding instantiations
+  /// that replace parent with child in previous instantiations
+  fn add_grounding(&mut self, parent: Symbol, child: Symbol) {
+    // First gather all the terms we want to instantiate:
+    // take both sides of the equations and all the premises
+    let mut sides = vec![];
+    for eq in self.eqs.iter().chain(self.premises.iter()) {
+      sides.push(&eq.lhs);
+      sides.push(&eq.rhs);
+    }
+
+    // Now create new instantiations from existing ones
+    let mut new_instantiations = vec![];
+    for inst in self.grounding_instantiations.iter() {
+      let replaced_canonicals: Vec<(Symbol, ETerm, bool)> = self
+        .params
+        .iter()
+        .map(|x| {
+          // Which class was this param instantiated to?
+          let id = inst.get(x).unwrap();
+          // Parameters must be canonical (at least in a clean state)
+          let canonical = CanonicalFormAnalysis::extract_canonical(&self.egraph, *id).unwrap();
+          // Try replacing the case-split variable with its child
+          let (new_expr, replaced) = replace_var(&canonical, parent, child);
+          let eterm = if replaced {
+            ETerm::new_from_expr(&new_expr, &mut self.egraph)
+          } else {
+            ETerm::from_expr(new_expr, &self.egraph)
+          };
+          (*x, eterm, replaced)
+        })
+        .collect();
+      // If any of the canonical forms had a replacement, add a new instantiation:
+      if replaced_canonicals.iter().any(|(_, _, b)| *b) {
+        let new_instantiation = replaced_canonicals
+          .iter()
+          .map(|(x, e, _)| (x.to_string(), e.sexp.clone()))
+          .collect();
+        // For each new instantiation, instantiate all the sides and add them to the egraph
+        for term in sides.iter() {
+          let new_term = resolve_sexp(&term.sexp, &new_instantiation);
+          ETerm::new(&new_term, &mut self.egraph);
+        }
+        // Add the new instantiation to the list of grounding instantiations
+        let new_subst = replaced_canonicals
+          .iter()
+          .map(|(x, e, _)| (*x, e.id))
+          .collect();
+        new_instantiations.push(new_subst);
+      }
+    }
+
+    // Add the new instantiations to the list of grounding instantiations
+    self.grounding_instantiations.extend(new_instantiations);
+  }
+}
+
+impl<'a> Display for Goal<'a> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if !self.premises.is_empty() {
+      let premises_string = self
+        .premises
+        .iter()
+        .map(|premise| format!("{}", premise))
+        .collect::<Vec<String>>()
+        .join(", ");
+      write!(f, "{} ==> ", premises_string)?;
+    }
+    let eqs_string = self
+      .eqs
+      .iter()
+      .map(|eq| format!("{}", eq))
+      .collect::<Vec<String>>()
+      .join(" && ");
+    write!(f, "{}", eqs_string)
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ProofTerm {
+  /// - Arg0: Name of the variable we split on
+  /// - Arg1: List of cases we split on
+  ///   * Arg0: Sexp we split to
+  ///   * Arg1: Name of goal from this split
+  ///
+  /// Example:
+  /// ```
+  /// CaseSplit("x", [("(Z)", "goal_1"), ("(S x')","goal_2")])
+  /// ```
+  /// corresponds to the proof
+  /// ```
+  /// case x of
+  ///   Z    -> goal_1
+  ///   S x' -> goal_2
+  /// ```
+  CaseSplit(String, Vec<(String, String)>),
+  /// The same thing as a case split, except instead of splitting on one of the
+  /// symbolic variables, we split on an expression.
+  ///
+  /// - Arg0: A fresh variable introduced that is equal to the expression
+  /// - Arg1: The expression we split on
+  /// - Arg2: List of cases we split on (same as above).
+  ///   For the condition of an `ite` there will be two cases, corresponding to `True` and `False`;
+  ///   for the scrutinee of a `match` there is one case per constructor.
+  ///
+  /// Example:
+  /// ```
+  /// ITESplit("g0", "(lt x y)", [("True", "goal_1"), ("False", "goal_2")])
+  /// ```
+  /// corresponds to the proof
+  /// ```
+  /// let g0 = lt x y in
+  ///   case g0 of
+  ///     True  -> goal_1
+  ///     False -> goal_2
+  /// ```
+  ITESplit(String, String, Vec<(String, String)>),
+  /// A goal that is the same proof obligation as another goal, up to renaming variables.
+  ///
+  /// - Arg0: Name of the other goal
+  /// - Arg1: Each variable of the other goal together with the variable of this goal it corresponds to
+  ///
+  /// Example:
+  /// ```
+  /// Duplicate("goal_1", [("x'", "y'")])
+  /// ```
+  /// corresponds to the proof
+  /// ```
+  /// let x' = y' in goal_1
+  /// ```
+  Duplicate(String, Vec<(String, String)>),
+}
+
+/// A structured record of how a goal was (or was not) proved,
+/// reconstructed from a proof state
+#[derive(Clone, Debug)]
+pub enum ProofTree {
+  /// A goal discharged by saturation, together with the lemmas used to discharge it
+  Leaf { name: String, lemmas: Vec<String> },
+  /// A goal that was case-split on `scrutinee`;
+  /// each branch is the constructor application the scrutinee was instantiated to and the resulting subproof
+  /// (if the scrutinee is a guard, `guard` is the condition it stands for)
+  Split {
+    name: String,
+    scrutinee: String,
+    guard: Option<String>,
+    branches: Vec<(String, ProofTree)>,
+  },
+  /// A goal that is the same obligation as the goal `of`, whose proof is elsewhere in the tree
+  Duplicate { name: String, of: String },
+  /// A goal that was never discharged (only present if the proof is incomplete)
+  Open { name: String },
+}
+
+impl ProofTree {
+  fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+    let indent = " ".repeat(2 * depth);
+    match self {
+      ProofTree::Leaf { name, lemmas } if lemmas.is_empty() => {
+        writeln!(f, "{}{}: by saturation", indent, name)
+      }
+      ProofTree::Leaf { name, lemmas } => {
+        writeln!(f, "{}{}: by saturation using", indent, name)?;
+        for lemma in lemmas {
+          writeln!(f, "{}  {}", indent, lemma)?;
+        }
+        Ok(())
+      }
+      ProofTree::Split {
+        name,
+        scrutinee,
+        branches,
+        ..
+      } => {
+        writeln!(f, "{}{}: case split on {}", indent, name, scrutinee)?;
+        for (con_app, subproof) in branches {
+          writeln!(f, "{}  {} = {}:", indent, scrutinee, con_app)?;
+          subproof.fmt_indented(f, depth + 2)?;
+        }
+        Ok(())
+      }
+      ProofTree::Duplicate { name, of } => writeln!(f, "{}{}: same as {}", indent, name, of),
+      ProofTree::Open { name } => writeln!(f, "{}{}: not proved", indent, name),
+    }
+  }
+}
+
+impl ProofTree {
+  /// Render the proof as a script of steps that can be replayed or checked independently:
+  /// every goal is saturated, then either closed (naming the lemmas it applied) or split,
+  /// in which case the script for every case follows, indented under it.
+  pub fn to_script(&self) -> String {
+    let mut script = String::new();
+    self.write_script(&mut script, 0);
+    script
+  }
+
+  fn write_script(&self, script: &mut String, depth: usize) {
+    let indent = " ".repeat(2 * depth);
+    match self {
+      ProofTree::Leaf { name, lemmas } => {
+        script.push_str(&format!("{}goal {}\n{}  saturate\n", indent, name, indent));
+        for lemma in lemmas {
+          script.push_str(&format!("{}  apply {}\n", indent, lemma));
+        }
+        script.push_str(&format!("{}  qed\n", indent));
+      }
+      ProofTree::Split {
+        name,
+        scrutinee,
+        branches,
+        ..
+      } => {
+        let con_apps: Vec<&str> = branches.iter().map(|(c, _)| c.as_str()).collect();
+        script.push_str(&format!(
+          "{}goal {}\n{}  saturate\n{}  split {} into {}\n",
+          indent,
+          name,
+          indent,
+          indent,
+          scrutinee,
+          con_apps.join(", ")
+        ));
+        for (_, subproof) in branches {
+          subproof.write_script(script, depth + 1);
+        }
+      }
+      ProofTree::Duplicate { name, of } => {
+        script.push_str(&format!(
+          "{}goal {}\n{}  same {}\n",
+          indent, name, indent, of
+        ));
+      }
+      ProofTree::Open { name } => {
+        script.push_str(&format!(
+          "{}goal {}\n{}  saturate\n{}  admit\n",
+          indent, name, indent, indent
+        ));
+      }
+    }
+  }
+}
+
+impl Display for ProofTree {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.fmt_indented(f, 0)
+  }
+}
+
+/// Why a goal could not be created
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoalError {
+  /// The left-hand side of an equation is not a valid term
+  LhsNotFound(Sexp),
+  /// The right-hand side of an equation is not a valid term
+  RhsNotFound(Sexp),
+  /// A parameter type mentions a datatype that is not declared
+  UnknownType(Symbol),
+  /// The goal mentions functions or constructors that are not declared
+  UnknownSymbols(Vec<Symbol>),
+  /// A snapshot contains something that is not a valid expression, pattern, or type
+  InvalidSnapshot(String),
+  /// The goal contains a let that does not bind a variable to a single term
+  InvalidLet(Sexp),
+}
+
+impl Display for GoalError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      GoalError::LhsNotFound(sexp) => write!(f, "invalid left-hand side {}", sexp),
+      GoalError::RhsNotFound(sexp) => write!(f, "invalid right-hand side {}", sexp),
+      GoalError::UnknownType(dt) => write!(f, "unknown datatype {}", dt),
+      GoalError::UnknownSymbols(symbols) => write!(
+        f,
+        "unknown function or constructor {}",
+        symbols.iter().join(", ")
+      ),
+      GoalError::InvalidSnapshot(s) => write!(f, "invalid snapshot entry {}", s),
+      GoalError::InvalidLet(sexp) => write!(f, "invalid let {}", sexp),
+    }
+  }
+}
+
+impl std::error::Error for GoalError {}
+
+/// Check that every datatype mentioned in ty is declared in env
+/// (type variables, whether written `a` or `?a`, arrows, and `Int` need no declaration)
+pub(crate) fn check_type(ty: &Type, env: &Env) -> Result<(), GoalError> {
+  let mut symbols = vec![];
+  collect_subterms(&ty.repr, &mut symbols);
+  for symbol in symbols {
+    if let Sexp::String(s) = symbol {
+      if s != ARROW
+        && s != *INT_TYPE
+        && !s.starts_with(char::is_lowercase)
+        && !s.starts_with('?')
+        && !env.contains_key(&Symbol::from(&s))
+      {
+        return Err(GoalError::UnknownType(Symbol::from(&s)));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// The equations with their lets expanded (see `expand_lets`)
+fn expand_equation_lets(eqs: &[RawEquation]) -> Result<Vec<RawEquation>, GoalError> {
+  eqs
+    .iter()
+    .map(|eq| {
+      Ok(RawEquation {
+        lhs: expand_lets(&eq.lhs).map_err(GoalError::InvalidLet)?,
+        rhs: expand_lets(&eq.rhs).map_err(GoalError::InvalidLet)?,
+      })
+    })
+    .collect()
+}
+
+/// Check that every symbol in the sides of eqs is either a parameter,
+/// a declared function or constructor, or a builtin;
+/// otherwise a typo would silently turn into an opaque constant
+fn check_symbols<'b>(
+  eqs: impl Iterator<Item = &'b RawEquation>,
+  params: &[(Symbol, Type)],
+  env: &Env,
+  global_context: &Context,
+) -> Result<(), GoalError> {
+  let mut unknown: Vec<Symbol> = vec![];
+  for eq in eqs {
+    let mut subterms = vec![];
+    collect_subterms(&eq.lhs, &mut subterms);
+    collect_subterms(&eq.rhs, &mut subterms);
+    for subterm in subterms {
+      if let Sexp::String(s) = subterm {
+        let symbol = Symbol::from(&s);
+        let known = s == APPLY
+          || is_builtin(&symbol)
+          || int_literal(&s).is_some()
+          || params.iter().any(|(x, _)| *x == symbol)
+          || global_context.contains_key(&symbol)
+          || env.values().any(|(_, cons)| cons.contains(&symbol));
+        if !known && !unknown.contains(&symbol) {
+          unknown.push(symbol);
+        }
+      }
+    }
+  }
+  if unknown.is_empty() {
+    Ok(())
+  } else {
+    Err(GoalError::UnknownSymbols(unknown))
+  }
+}
+
+/// Name of the lemma rewrite `lhs => rhs`
+fn lemma_name(lhs: &Pat, rhs: &Pat) -> String {
+  format!("{}{}={}", LEMMA_PREFIX, lhs, rhs)
+}
+
+/// A rewrite from the lhs to the rhs of a hypothesis;
+/// the sides are ground, since the parameters of a goal are not pattern variables
+fn hypothesis_rewrite(eq: &Equation) -> Rw {
+  let searcher: Pat = to_pattern(&eq.lhs.expr, |_| false);
+  let applier: Pat = to_pattern(&eq.rhs.expr, |_| false);
+  Rewrite::new(
+    format!("{}{}={}", HYPOTHESIS_PREFIX, eq.lhs, eq.rhs),
+    searcher,
+    applier,
+  )
+  .unwrap()
+}
+
+/// The rewrite rw together with its reverse, if there is one that cannot make saturation diverge:
+/// the reverse is only added if it makes every term it applies to strictly smaller
+/// (so it cannot apply to its own result forever), and only rewrites between patterns can be reversed.
+pub fn bidirectional(rw: Rw) -> Vec<Rw> {
+  let reversed = reverse_rewrite(&rw);
+  let mut rewrites = vec![rw];
+  rewrites.extend(reversed);
+  rewrites
+}
+
+/// Reverse the definitional rewrites of the given functions with `bidirectional`
+/// (a definition is identified by the function at the root of its left-hand side)
+pub fn reverse_definitions(rules: Vec<Rw>, functions: &[String]) -> Vec<Rw> {
+  if functions.is_empty() {
+    return rules;
+  }
+  rules
+    .into_iter()
+    .flat_map(|rw| {
+      let defines = |ast: &PatternAst<SymbolLang>| match ast.as_ref().last() {
+        Some(ENodeOrVar::ENode(node)) => functions.iter().any(|f| node.op.as_str() == f),
+        _ => false,
+      };
+      if rw.searcher.get_pattern_ast().is_some_and(defines) {
+        bidirectional(rw)
+      } else {
+        vec![rw]
+      }
+    })
+    .collect()
+}
+
+/// The reverse of rw, if it strictly decreases the size of every term it applies to:
+/// this is the case if its new right-hand side is smaller than its new left-hand side
+/// and does not contain any variable more often
+fn reverse_rewrite(rw: &Rw) -> Option<Rw> {
+  let lhs = rw.searcher.get_pattern_ast()?;
+  let rhs = rw.applier.get_pattern_ast()?;
+  let mut lhs_vars = HashMap::new();
+  let mut rhs_vars = HashMap::new();
+  let lhs_size = pattern_size(lhs, Id::from(lhs.as_ref().len() - 1), &mut lhs_vars);
+  let rhs_size = pattern_size(rhs, Id::from(rhs.as_ref().len() - 1), &mut rhs_vars);
+  let shrinks = lhs_size < rhs_size
+    && lhs_vars
+      .iter()
+      .all(|(var, n)| rhs_vars.get(var).is_some_and(|m| n <= m));
+  if !shrinks {
+    debug!(
+      "not reversing {}, which would not make terms smaller",
+      rw.name
+    );
+    return None;
+  }
+  let reversed = Rewrite::new(
+    format!("{}{}", REVERSED_PREFIX, rw.name),
+    Pattern::new(rhs.clone()),
+    Pattern::new(lhs.clone()),
+  );
+  reversed.ok()
+}
+
+/// Number of nodes in the tree of pattern ast rooted at id,
+/// recording how often every variable occurs in it
+fn pattern_size(ast: &PatternAst<SymbolLang>, id: Id, vars: &mut HashMap<Var, usize>) -> usize {
+  match &ast[id] {
+    ENodeOrVar::Var(var) => {
+      *vars.entry(*var).or_insert(0) += 1;
+      1
+    }
+    ENodeOrVar::ENode(node) => {
+      1 + node
+        .children
+        .iter()
+        .map(|child| pattern_size(ast, *child, vars))
+        .sum::<usize>()
+    }
+  }
+}
+
+/// Replace every variable in sexp with `?i`, where i is its position in vars
+/// (variables that are not in vars yet are appended to it)
+fn number_vars<F: Fn(&Symbol) -> bool>(sexp: &Sexp, is_var: &F, vars: &mut Vec<Symbol>) -> Sexp {
+  match sexp {
+    Sexp::String(s) if is_var(&Symbol::from(s)) => {
+      let x = Symbol::from(s);
+      let i = vars.iter().position(|y| *y == x).unwrap_or_else(|| {
+        vars.push(x);
+        vars.len() - 1
+      });
+      Sexp::String(format!("?{}", i))
+    }
+    Sexp::List(xs) => Sexp::List(xs.iter().map(|x| number_vars(x, is_var, vars)).collect()),
+    _ => sexp.clone(),
+  }
+}
+
+/// All subterms of sexp (including itself and its leaves)
+fn collect_subterms(sexp: &Sexp, res: &mut Vec<Sexp>) {
+  res.push(sexp.clone());
+  if let Sexp::List(xs) = sexp {
+    for x in xs {
+      collect_subterms(x, res);
+    }
+  }
+}
+
+/// Replace every occurrence of subterm in sexp with replacement
+fn replace_subterm(sexp: &Sexp, subterm: &Sexp, replacement: &Sexp) -> Sexp {
+  if sexp == subterm {
+    replacement.clone()
+  } else if let Sexp::List(xs) = sexp {
+    Sexp::List(
+      xs.iter()
+        .map(|x| replace_subterm(x, subterm, replacement))
+        .collect(),
+    )
+  } else {
+    sexp.clone()
+  }
+}
+
+/// Names of all lemmas used in a flat explanation term
+fn collect_lemmas(flat_term: &FlatTerm<SymbolLang>, lemmas: &mut Vec<String>) {
+  for rule in flat_term
+    .forward_rule
+    .iter()
+    .chain(flat_term.backward_rule.iter())
+  {
+    let rule = rule.to_string();
+    if rule.starts_with(LEMMA_PREFIX) && !lemmas.contains(&rule) {
+      lemmas.push(rule);
+    }
+  }
+  for child in &flat_term.children {
+    collect_lemmas(child, lemmas);
+  }
+}
+
+/// Apply rewrites to an e-graph that was saturated before the classes in dirty changed,
+/// searching only those classes and their ancestors (the only places where new matches can appear),
+/// and repeat with the classes changed by each round until nothing changes;
+/// returns the number of rounds, and adds to applied the number of times each rewrite changed the e-graph.
+fn propagate(
+  egraph: &mut Eg,
+  rewrites: &[&Rw],
+  mut dirty: Vec<Id>,
+  config: &ProverConfig,
+  time_limit: Option<Duration>,
+  cancel: &AtomicBool,
+  applied: &mut HashMap<String, usize>,
+) -> usize {
+  let start_time = Instant::now();
+  let mut rounds = 0;
+  while !dirty.is_empty()
+    && rounds < config.max_iterations
+    && egraph.total_size() <= config.max_egraph_nodes
+    && time_limit.is_none_or(|limit| start_time.elapsed() < limit)
+    && !cancel.load(Ordering::Relaxed)
+  {
+    let region = ancestors(egraph, dirty);
+    let matches: Vec<Vec<SearchMatches<SymbolLang>>> = rewrites
+      .iter()
+      .map(|rw| {
+        region
+          .iter()
+          .filter_map(|id| rw.searcher.search_eclass(egraph, *id))
+          .collect()
+      })
+      .collect();
+    dirty = vec![];
+    for (rw, matches) in rewrites.iter().zip(matches.iter()) {
+      let changed = rw.apply(egraph, matches);
+      if !changed.is_empty() {
+        *applied.entry(rw.name.to_string()).or_default() += changed.len();
+      }
+      dirty.extend(changed);
+    }
+    egraph.rebuild();
+    rounds += 1;
+  }
+  rounds
+}
+
+/// The canonical classes of ids together with all classes that (transitively) contain them
+fn ancestors(egraph: &Eg, ids: Vec<Id>) -> HashSet<Id> {
+  let mut visited = HashSet::new();
+  let mut stack: Vec<Id> = ids.into_iter().map(|id| egraph.find(id)).collect();
+  while let Some(id) = stack.pop() {
+    if visited.insert(id) {
+      stack.extend(egraph[id].parents().map(|(_, parent)| egraph.find(parent)));
+    }
+  }
+  visited
+}
+
+/// Saturation statistics aggregated over all goals of a proof attempt
+#[derive(Debug, Clone, Default)]
+pub struct ProofReport {
+  /// Number of goals that were saturated
+  pub goals_saturated: usize,
+  /// Total number of saturation iterations across all goals
+  pub total_iterations: usize,
+  /// Total number of rounds of incremental propagation across all goals
+  pub total_propagation_rounds: usize,
+  /// Largest number of e-nodes in any goal's e-graph after saturation
+  pub peak_egraph_nodes: usize,
+  /// Largest number of e-classes in any goal's e-graph after saturation
+  pub peak_egraph_classes: usize,
+  /// Number of times each rewrite (by name) changed an e-graph, summed over all goals
+  pub rewrite_usage: HashMap<String, usize>,
+  /// Number of cyclic lemmas created across all goals
+  pub lemmas_created: usize,
+  /// Number of candidate lemmas dropped because a lemma with the same patterns already existed
+  pub duplicate_lemmas: usize,
+}
+
+impl ProofReport {
+  fn record(&mut self, stats: &SaturationStats) {
+    self.goals_saturated += 1;
+    self.total_iterations += stats.iterations;
+    self.total_propagation_rounds += stats.propagation_rounds;
+    self.peak_egraph_nodes = self.peak_egraph_nodes.max(stats.egraph_nodes);
+    self.peak_egraph_classes = self.peak_egraph_classes.max(stats.egraph_classes);
+    for (name, count) in stats.applied.iter() {
+      *self.rewrite_usage.entry(name.clone()).or_default() += count;
+    }
+  }
+}
+
+impl Display for ProofReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} goals saturated, {} iterations, ",
+      self.goals_saturated, self.total_iterations
+    )?;
+    if self.total_propagation_rounds > 0 {
+      write!(f, "{} propagation rounds, ", self.total_propagation_rounds)?;
+    }
+    write!(f, "{} lemmas created, ", self.lemmas_created)?;
+    if self.duplicate_lemmas > 0 {
+      write!(f, "{} duplicate lemmas dropped, ", self.duplicate_lemmas)?;
+    }
+    write!(
+      f,
+      "peak e-graph size {} nodes / {} classes",
+      self.peak_egraph_nodes, self.peak_egraph_classes
+    )
+  }
+}
+
+/// A proof state is a list of subgoals,
+/// all of which have to be discharged
+pub struct ProofState<'a> {
+  pub config: &'a ProverConfig,
+  pub goals: VecDeque<Goal<'a>>,
+  /// Every discharged goal, with its explanation (if the configuration enables them) and its variables
+  pub solved_goal_explanation_and_context:
+    HashMap<String, (Option<Explanation<SymbolLang>>, Context)>,
+  pub proof: HashMap<String, ProofTerm>,
+  pub start_time: Instant,
+  /// Saturation statistics collected so far
+  pub report: ProofReport,
+  /// Sides of the lemmas created without premises (in goals without equivalences), by name;
+  /// if the proof succeeds, these hold as standalone equations
+  unconditional_lemmas: HashMap<String, (Pat, Pat)>,
+  /// Signatures of the goals added so far, with the name and the numbered variables of the first goal
+  /// that had each signature (only used when pruning duplicate goals)
+  seen_goals: HashMap<String, (String, Vec<Symbol>)>,
+  /// Number of fresh variables created by case splits and guard scrutinees so far,
+  /// which is used to give each of them a unique name that does not depend on the e-graph
+  num_fresh_vars: usize,
+  /// Number of case splits performed so far
+  pub num_splits: usize,
+  /// Goals that were found invalid or unknown, with their verdicts, in the order they were concluded
+  /// (only collected with `exhaustive_failures`, since otherwise the first one ends the proof)
+  pub failures: Vec<(String, Outcome)>,
+  /// The goals found invalid or unknown, in the order they were concluded
+  /// (the one that ended the proof, or with `exhaustive_failures` those in `failures`),
+  /// so that their e-graphs and contexts can be inspected once the proof is over
+  pub dead_ends: Vec<Goal<'a>>,
+  /// Once set (e.g. from another thread), the proof stops as soon as it checks (see `prove_cancellable`)
+  pub cancel: Arc<AtomicBool>,
+}
+
+impl<'a> ProofState<'a> {
+  /// The initial proof state for proving goal
+  pub fn new(goal: Goal<'a>) -> Self {
+    ProofState {
+      config: goal.config,
+      goals: VecDeque::from([goal]),
+      solved_goal_explanation_and_context: HashMap::default(),
+      proof: HashMap::default(),
+      start_time: Instant::now(),
+      report: ProofReport::default(),
+      unconditional_lemmas: HashMap::default(),
+      seen_goals: HashMap::default(),
+      num_fresh_vars: 0,
+      num_splits: 0,
+      failures: vec![],
+      dead_ends: vec![],
+      cancel: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Conclude that goal is invalid
+  fn refute(&mut self, goal: Goal<'a>) -> StepResult {
+    let counterexample = shrink_counterexample(&goal, &goal.counterexample());
+    self.dead_ends.push(goal);
+    StepResult::Done(Outcome::Invalid(counterexample))
+  }
+
+  /// Conclude that it is unknown whether goal holds, for the given reason
+  fn give_up(&mut self, goal: Goal<'a>, reason: fn(String) -> UnknownReason) -> StepResult {
+    let name = goal.name.clone();
+    self.dead_ends.push(goal);
+    StepResult::Done(Outcome::Unknown(reason(name)))
+  }
+
+  /// The verdict once no goals are left:
+  /// valid, unless some goal failed (see `failures`), in which case
+  /// invalid with the counterexample of the first invalid goal, if there is one,
+  /// and otherwise unknown for the reason of the first failed goal
+  fn verdict(&self) -> Outcome {
+    let invalid = self
+      .failures
+      .iter()
+      .find(|(_, outcome)| matches!(outcome, Outcome::Invalid(_)));
+    match invalid.or_else(|| self.failures.first()) {
+      Some((_, outcome)) => outcome.clone(),
+      None => Outcome::Valid,
+    }
+  }
+
+  /// Number of times each rewrite changed the e-graph of some goal so far, keyed by the rewrite's name
+  /// (definitions by the names the parser gives them, lemmas by `lemma_name`);
+  /// rewrites that never fired are missing
+  pub fn rewrite_usage(&self) -> HashMap<String, usize> {
+    self.report.rewrite_usage.clone()
+  }
+
+  /// Has the proof been cancelled?
+  pub fn cancelled(&self) -> bool {
+    self.cancel.load(Ordering::Relaxed)
+  }
+
+  // Has timeout been reached?
+  pub fn timeout(&self) -> bool {
+    self.config.timeout.is_some()
+      && self.start_time.elapsed() > Duration::new(self.config.timeout.unwrap(), 0)
+  }
+
+  /// Whether a subgoal can be closed by referring to another goal instead, which it then is:
+  /// if pruning duplicates is enabled and a goal with the same signature has already been taken
+  /// (other than one of its ancestors, which would make the proof circular)
+  fn is_duplicate(&mut self, goal: &Goal<'a>) -> bool {
+    // Symmetric constructors are opt-in, so their duplicates are pruned even without --prune-duplicates
+    if self.config.prune_duplicates || goal.split_on_symmetric() {
+      if let Some((signature, vars)) = goal.signature() {
+        match self.seen_goals.get(&signature) {
+          Some((name, orig_vars)) if !goal.name.starts_with(&format!("{}:", name)) => {
+            info!("goal {} is a duplicate of {}", goal.name, name);
+            let renaming = orig_vars
+              .iter()
+              .zip(vars.iter())
+              .map(|(x, y)| (x.to_string(), y.to_string()))
+              .collect();
+            self.proof.insert(
+              goal.name.clone(),
+              ProofTerm::Duplicate(name.clone(), renaming),
+            );
+            return true;
+          }
+          Some(_) => (),
+          None => {
+            self.seen_goals.insert(signature, (goal.name.clone(), vars));
+          }
+        }
+      }
+    }
+    false
+  }
+
+  fn record_unconditional_lemma(&mut self, lhs: &Pat, rhs: &Pat) {
+    self
+      .unconditional_lemmas
+      .insert(lemma_name(lhs, rhs), (lhs.clone(), rhs.clone()));
+  }
+
+  /// The unconditional lemmas that were applied in the explanation of some discharged goal
+  pub fn used_unconditional_lemmas(&mut self) -> Vec<(Pat, Pat)> {
+    let mut used = vec![];
+    for (explanation, _) in self.solved_goal_explanation_and_context.values_mut() {
+      let explanation = match explanation {
+        Some(explanation) => explanation,
+        None => continue,
+      };
+      for flat_term in explanation.make_flat_explanation() {
+        collect_lemmas(flat_term, &mut used);
+      }
+    }
+    // Sort by name, so that the result does not depend on the order of goals in the map
+    used.sort();
+    used.dedup();
+    used
+      .iter()
+      .filter_map(|name| self.unconditional_lemmas.get(name).cloned())
+      .collect()
+  }
+
+  /// Remove the next goal to work on according to the search strategy,
+  /// with its records destructured (see `Goal::destructure_records`)
+  pub fn next_goal(&mut self) -> Option<Goal<'a>> {
+    loop {
+      let mut goal = match self.config.search_strategy {
+        SearchStrategy::DepthFirst => self.goals.pop_back(),
+        SearchStrategy::BreadthFirst => self.goals.pop_front(),
+      }?;
+      // A split goal stands for its pending cases, which are taken in the order
+      // they would have been taken as separate goals (see `Goal::case_split`)
+      if !goal.pending_cases.is_empty() {
+        let case = match self.config.search_strategy {
+          SearchStrategy::DepthFirst => goal.pending_cases.pop().unwrap(),
+          SearchStrategy::BreadthFirst => goal.pending_cases.remove(0),
+        };
+        if goal.pending_cases.is_empty() {
+          goal.instantiate_case(&case);
+        } else {
+          let subgoal = goal.subgoal(&case);
+          match self.config.search_strategy {
+            SearchStrategy::DepthFirst => self.goals.push_back(goal),
+            SearchStrategy::BreadthFirst => self.goals.push_front(goal),
+          }
+          goal = subgoal;
+        }
+        if self.is_duplicate(&goal) {
+          continue;
+        }
+      }
+      goal.destructure_records(self);
+      return Some(goal);
+    }
+  }
+
+  /// Record in the proof that the goal named goal_name was split on var
+  /// (or on the guard it stands for), with con_app_string being the case proven by child_name
+  fn record_split(
+    &mut self,
+    goal_name: &str,
+    var: Symbol,
+    guard_expr: &Option<Expr>,
+    con_app_string: String,
+    child_name: &str,
+  ) {
+    let proof_term = self
+      .proof
+      .entry(goal_name.to_string())
+      .or_insert_with(|| match guard_expr {
+        Some(guard_expr) => ProofTerm::ITESplit(var.to_string(), guard_expr.to_string(), vec![]),
+        None => ProofTerm::CaseSplit(var.to_string(), vec![]),
+      });
+    if let ProofTerm::CaseSplit(_, cases) | ProofTerm::ITESplit(_, _, cases) = proof_term {
+      if !cases.iter().any(|(_, child)| child == child_name) {
+        cases.push((con_app_string, child_name.to_string()));
+      }
+    }
+  }
+
+  /// Reconstruct the proof tree rooted at the goal with the given name
+  pub fn proof_tree(&mut self, name: &str) -> ProofTree {
+    if let Some((explanation, _)) = self.solved_goal_explanation_and_context.get_mut(name) {
+      // Without an explanation, there is no telling which lemmas the goal used
+      let mut lemmas = vec![];
+      if let Some(explanation) = explanation {
+        for flat_term in explanation.make_flat_explanation() {
+          collect_lemmas(flat_term, &mut lemmas);
+        }
+      }
+      return ProofTree::Leaf {
+        name: name.to_string(),
+        lemmas,
+      };
+    }
+    match self.proof.get(name).cloned() {
+      Some(ProofTerm::CaseSplit(scrutinee, cases)) => ProofTree::Split {
+        name: name.to_string(),
+        scrutinee,
+        guard: None,
+        branches: cases
+          .into_iter()
+          .map(|(con_app, subgoal)| (con_app, self.proof_tree(&subgoal)))
+          .collect(),
+      },
+      Some(ProofTerm::ITESplit(scrutinee, guard, cases)) => ProofTree::Split {
+        name: name.to_string(),
+        scrutinee,
+        guard: Some(guard),
+        branches: cases
+          .into_iter()
+          .map(|(con_app, subgoal)| (con_app, self.proof_tree(&subgoal)))
+          .collect(),
+      },
+      Some(ProofTerm::Duplicate(of, _)) => ProofTree::Duplicate {
+        name: name.to_string(),
+        of,
+      },
+      None => ProofTree::Open {
+        name: name.to_string(),
+      },
+    }
+  }
+
+  /// How much time is left before the timeout (None if there is no timeout)
+  pub fn remaining_time(&self) -> Option<Duration> {
+    self
+      .config
+      .timeout
+      .map(|t| Duration::new(t, 0).saturating_sub(self.start_time.elapsed()))
+  }
+}
+
+/// A file name for a (sub)goal: goal names spell out the case splits that led to them,
+/// e.g. `goal:x=(S x_0)`, so the characters that are not safe in file names are replaced.
+/// Since constructors have fixed arities, dropping the parentheses keeps names distinct.
+pub fn filesystem_safe_name(name: &str) -> String {
+  name
+    .chars()
+    .filter_map(|c| match c {
+      ':' => Some('.'),
+      '=' => Some('-'),
+      ' ' => Some('+'),
+      '(' | ')' => None,
+      c if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' => Some(c),
+      _ => Some('_'),
+    })
+    .collect()
+}
+
+/// Numeric verbosity of a log level, from 1 (errors only) to 5 (everything)
+fn log_level_index(level: Level) -> usize {
+  match level {
+    Level::ERROR => 1,
+    Level::WARN => 2,
+    Level::INFO => 3,
+    Level::DEBUG => 4,
+    Level::TRACE => 5,
+  }
+}
+
+/// Pretty-printed proof state
+pub fn pretty_state(state: &ProofState) -> String {
+  format!(
+    "[{}]",
+    state
+      .goals
+      .iter()
+      .flat_map(Goal::case_names)
+      .collect::<Vec<String>>()
+      .join(", ")
+  )
+}
+
+/// A goal with what it currently asserts: its premises and equations as the best expressions
+/// of their e-classes (which reflect the case splits so far),
+/// followed by the variables it can still split on and their types
+pub fn pretty_goal(goal: &Goal) -> String {
+  let side = |id: Id| goal.extract_stable(id);
+  let eq_string = |eq: &Equation| format!("{} = {}", side(eq.lhs.id), side(eq.rhs.id));
+  let mut obligation = goal.eqs.iter().map(eq_string).join(" && ");
+  if !goal.premises.is_empty() {
+    obligation = format!(
+      "{} ==> {}",
+      goal.premises.iter().map(eq_string).join(", "),
+      obligation
+    );
+  }
+  let scrutinees = goal
+    .scrutinees
+    .iter()
+    .map(|x| match goal.local_context.get(x) {
+      Some(ty) => format!("{}: {}", x, ty),
+      None => x.to_string(),
+    })
+    .join(", ");
+  format!("{}: {} [{}]", goal.name, obligation, scrutinees)
+}
+
+/// The pending goals of a proof state, one per line, as printed by `pretty_goal`
+pub fn pretty_state_verbose(state: &ProofState) -> String {
+  let pretty_cases = |goal: &Goal| {
+    if goal.pending_cases.is_empty() {
+      vec![pretty_goal(goal)]
+    } else {
+      (goal.pending_cases.iter())
+        .map(|case| pretty_goal(&goal.subgoal(case)))
+        .collect()
+    }
+  };
+  state.goals.iter().flat_map(pretty_cases).join("\n")
+}
+
+/// An assignment of the top-level parameters of a goal
+/// under which the goal does not hold
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub struct Counterexample {
+  pub assignment: Vec<(Symbol, Expr)>,
+}
+
+impl Counterexample {
+  fn new(assignment: Vec<(Symbol, Sexp)>) -> Self {
+    let assignment = assignment
+      .into_iter()
+      .map(|(x, value)| (x, value.to_string().parse().unwrap()))
+      .collect();
+    Self { assignment }
+  }
+}
+
+impl Display for Counterexample {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let bindings = self
+      .assignment
+      .iter()
+      .map(|(var, value)| format!("{} = {}", var, value))
+      .collect::<Vec<String>>()
+      .join(", ");
+    write!(f, "{}", bindings)
+  }
+}
+
+/// Why the prover could neither prove nor disprove a conjecture;
+/// each reason carries the name of the goal where the proof stalled.
+/// (Running out of time is reported as `Outcome::Timeout` instead.)
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub enum UnknownReason {
+  /// The goal could only be split on variables beyond the maximum split depth
+  DepthExceeded(String),
+  /// Saturation stopped because the e-graph reached the node limit
+  NodeLimit(String),
+  /// Saturation stopped at the iteration limit, and the goal has nothing left to split on
+  IterationLimit(String),
+  /// A generalized goal failed, but the original conjecture may still hold
+  Generalized(String),
+  /// The goal needed a case split, but the proof already made the maximum number of them
+  SplitBudget(String),
+  /// The goal could only be split on variables of datatypes with too many constructors
+  Fanout(String),
+  /// The goal has nothing left to split on but integer variables, which cannot be split
+  IntVars(String),
+  /// The goal has nothing left to split on, but it has variables of function type,
+  /// whose applications can be anything
+  FunctionVars(String),
+  /// The goal has nothing left to split on, but it uses functions that were not unfolded
+  Opaque(String),
+  /// The goal has nothing left to split on, but it is proven up to equivalences,
+  /// under which different constructor terms may be equal
+  Equivalences(String),
+  /// The goal could only be split on variables that occur nowhere in it
+  Irrelevant(String),
+  /// The goal has nothing left to split on, but a function is applied in it to constructor terms
+  /// that no case of its definition matches (see `Goal::stuck_application`)
+  Stuck(String),
+  /// A `ProofObserver` or the token passed to `prove_cancellable` cancelled the proof
+  Cancelled,
+  /// The prover was not run in this mode
+  NotAttempted,
+}
+
+impl std::fmt::Display for UnknownReason {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      UnknownReason::DepthExceeded(goal) => write!(f, "depth exceeded at {}", goal),
+      UnknownReason::NodeLimit(goal) => write!(f, "node limit reached at {}", goal),
+      UnknownReason::IterationLimit(goal) => write!(f, "iteration limit reached at {}", goal),
+      UnknownReason::Generalized(goal) => write!(f, "generalization failed at {}", goal),
+      UnknownReason::SplitBudget(goal) => write!(f, "split budget exhausted at {}", goal),
+      UnknownReason::Fanout(goal) => write!(f, "constructor fanout exceeded at {}", goal),
+      UnknownReason::IntVars(goal) => write!(f, "integer variables left at {}", goal),
+      UnknownReason::FunctionVars(goal) => write!(f, "function variables left at {}", goal),
+      UnknownReason::Opaque(goal) => write!(f, "opaque functions left at {}", goal),
+      UnknownReason::Equivalences(goal) => {
+        write!(f, "cannot refute up to equivalences at {}", goal)
+      }
+      UnknownReason::Irrelevant(goal) => write!(f, "only irrelevant variables left at {}", goal),
+      UnknownReason::Stuck(goal) => write!(f, "partial definition stuck at {}", goal),
+      UnknownReason::Cancelled => write!(f, "cancelled"),
+      UnknownReason::NotAttempted => write!(f, "not attempted"),
+    }
+  }
+}
+
+/// Outcome of a proof attempt
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Outcome {
+  Valid,
+  /// The conjecture does not hold for the given counterexample
+  Invalid(Counterexample),
+  Unknown(UnknownReason),
+  Timeout,
+}
+
+impl Outcome {
+  /// Name of the outcome without any payload
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Outcome::Valid => "Valid",
+      Outcome::Invalid(_) => "Invalid",
+      Outcome::Unknown(_) => "Unknown",
+      Outcome::Timeout => "Timeout",
+    }
+  }
+}
+
+impl std::fmt::Display for Outcome {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Outcome::Valid => write!(f, "{}", "VALID".green()),
+      Outcome::Invalid(cex) if cex.assignment.is_empty() => write!(f, "{}", "INVALID".red()),
+      Outcome::Invalid(cex) => write!(f, "{} ({})", "INVALID".red(), cex),
+      Outcome::Unknown(reason) => write!(f, "{} ({})", "UNKNOWN".yellow(), reason),
+      Outcome::Timeout => write!(f, "{}", "TIMEOUT".yellow()),
+    }
+  }
+}
+
+pub fn explain_goal_failure(goal: &Goal) {
+  debug!("could not prove {}", goal.name);
+  let extractor = goal.extractor();
+  for eq in goal.eqs.iter() {
+    debug!("LHS nodes:");
+    for lhs_node in goal.egraph[eq.lhs.id].nodes.iter() {
+      let child_rec_exprs: String = lhs_node
+        .children
+        .iter()
+        .map(|child_id| {
+          let (_, best_rec_expr) = extractor.find_best(*child_id);
+          best_rec_expr.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+      if child_rec_exprs.is_empty() {
+        debug!("({})", lhs_node);
+      } else {
+        debug!("({} {})", lhs_node, child_rec_exprs);
+      }
+    }
+    debug!("RHS nodes:");
+    for rhs_node in goal.egraph[eq.rhs.id].nodes.iter() {
+      let child_rec_exprs: String = rhs_node
+        .children
+        .iter()
+        .map(|child_id| {
+          let (_, best_rec_expr) = extractor.find_best(*child_id);
+          best_rec_expr.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+      if child_rec_exprs.is_empty() {
+        debug!("({})", rhs_node);
+      } else {
+        debug!("({} {})", rhs_node, child_rec_exprs);
+      }
+    }
+  }
+}
+
+/// Whether a variable of type ty, which took depth splits to create, can be case-split on:
+/// None if it is not of a datatype, and false if the depth bound for that datatype is exceeded
+fn within_split_depth(ty: &Type, depth: usize, env: &Env, config: &ProverConfig) -> Option<bool> {
+  if ty.is_arrow() {
+    // Functions cannot be case-split (lemmas still generalize over them)
+    return None;
+  }
+  if ty.is_int() {
+    // Integers have infinitely many constructors, see `Goal::has_int_vars`
+    return None;
+  }
+  let dt = ty.datatype().ok()?;
+  if !env.contains_key(&Symbol::from(dt)) {
+    return None;
+  }
+  Some(depth < config.max_split_depth_for(dt))
+}
+
+/// The only constructor of the datatype of ty, if that is a record:
+/// a datatype with a single constructor that is not recursive, not even through other datatypes, such as a pair
+fn record_constructor(ty: &Type, env: &Env, global_context: &Context) -> Option<Symbol> {
+  let dt = Symbol::from(ty.datatype().ok()?);
+  let (_, cons) = env.get(&dt)?;
+  if cons.len() != 1 {
+    return None;
+  }
+  let fields = Goal::instantiate_constructor(&global_context[&cons[0]], ty);
+  let mut visited = HashSet::from([dt]);
+  if fields
+    .iter()
+    .any(|field| reaches_datatype(&field.repr, dt, env, global_context, &mut visited))
+  {
+    None
+  } else {
+    Some(cons[0])
+  }
+}
+
+/// Whether a value of type ty can contain a value of datatype dt,
+/// looking into the fields of datatypes other than those already visited
+fn reaches_datatype(
+  ty: &Sexp,
+  dt: Symbol,
+  env: &Env,
+  global_context: &Context,
+  visited: &mut HashSet<Symbol>,
+) -> bool {
+  let mut subterms = vec![];
+  collect_subterms(ty, &mut subterms);
+  subterms.iter().any(|subterm| {
+    let name = match subterm {
+      Sexp::String(name) => Symbol::from(name),
+      _ => return false,
+    };
+    if name == dt {
+      return true;
+    }
+    match env.get(&name) {
+      Some((_, cons)) if visited.insert(name) => cons.iter().any(|con| {
+        let (args, _) = global_context[con].args_ret();
+        args
+          .iter()
+          .any(|arg| reaches_datatype(&arg.repr, dt, env, global_context, visited))
+      }),
+      _ => false,
+    }
+  })
+}
+
+/// Number of constructors of the datatype of scrutinee var
+fn constructor_fanout(var: &Symbol, context: &Context, env: &Env) -> Option<usize> {
+  let dt = context.get(var)?.datatype().ok()?;
+  let (_, cons) = env.get(&Symbol::from(dt))?;
+  Some(cons.len())
+}
+
+/// See `Goal::defer_wide_scrutinees`
+fn defer_wide_scrutinees(
+  scrutinees: &mut VecDeque<Symbol>,
+  context: &Context,
+  env: &Env,
+  config: &ProverConfig,
+) -> bool {
+  let max = match config.max_constructor_fanout {
+    Some(max) => max,
+    None => return true,
+  };
+  let (narrow, wide): (VecDeque<Symbol>, VecDeque<Symbol>) = scrutinees
+    .iter()
+    .partition(|var| constructor_fanout(var, context, env).is_none_or(|n| n <= max));
+  let has_narrow = !narrow.is_empty();
+  *scrutinees = narrow;
+  scrutinees.extend(wide);
+  has_narrow
+}
+
+/// The case splits a proof of a goal would make if saturation never discharged any goal,
+/// as computed by `plan`
+#[derive(Clone, Debug)]
+pub enum SplitPlan {
+  /// A goal with nothing left to split on
+  Leaf { name: String },
+  /// A goal that would be case-split on `scrutinee`;
+  /// each branch is the constructor application the scrutinee would be instantiated to and the resulting plan
+  Split {
+    name: String,
+    scrutinee: String,
+    branches: Vec<(String, SplitPlan)>,
+  },
+  /// A goal that could only be split beyond the depth bound
+  /// (or on variables with too many constructors, see `max_constructor_fanout`)
+  Unexplored { name: String },
+}
+
+impl SplitPlan {
+  /// Number of case splits in the plan
+  pub fn num_splits(&self) -> usize {
+    match self {
+      SplitPlan::Split { branches, .. } => {
+        1 + branches
+          .iter()
+          .map(|(_, plan)| plan.num_splits())
+          .sum::<usize>()
+      }
+      _ => 0,
+    }
+  }
+
+  /// Number of goals at the leaves of the plan
+  pub fn num_leaves(&self) -> usize {
+    match self {
+      SplitPlan::Split { branches, .. } => branches.iter().map(|(_, plan)| plan.num_leaves()).sum(),
+      _ => 1,
+    }
+  }
+
+  fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+    let indent = " ".repeat(2 * depth);
+    match self {
+      SplitPlan::Leaf { name } => writeln!(f, "{}{}", indent, name),
+      SplitPlan::Split {
+        name,
+        scrutinee,
+        branches,
+      } => {
+        writeln!(f, "{}{}: case split on {}", indent, name, scrutinee)?;
+        for (con_app, subplan) in branches {
+          writeln!(f, "{}  {} = {}:", indent, scrutinee, con_app)?;
+          subplan.fmt_indented(f, depth + 2)?;
+        }
+        Ok(())
+      }
+      SplitPlan::Unexplored { name } => writeln!(f, "{}{}: not explored", indent, name),
+    }
+  }
+}
+
+impl Display for SplitPlan {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.fmt_indented(f, 0)
+  }
+}
+
+/// Enumerate the case splits the prover would make on goal up to the depth bound,
+/// without saturating (or even copying) any e-graphs.
+///
+/// This is only the structure of the search: scrutinees are taken in the order of the queue
+/// (as with `ScrutineeHeuristic::Fifo`), one at a time (ignoring joint induction),
+/// and the conditionals that saturation would expose are not known, so no guards are split;
+/// subgoal names are the ones a depth-first search of the whole tree would give them.
+pub fn plan(goal: &Goal) -> SplitPlan {
+  let mut num_fresh_vars = 0;
+  plan_goal(
+    goal,
+    goal.name.clone(),
+    goal.scrutinees.clone(),
+    goal.depth_exceeded,
+    goal.local_context.clone(),
+    &mut num_fresh_vars,
+  )
+}
+
+fn plan_goal(
+  goal: &Goal,
+  name: String,
+  mut scrutinees: VecDeque<Symbol>,
+  depth_exceeded: bool,
+  mut context: Context,
+  num_fresh_vars: &mut usize,
+) -> SplitPlan {
+  if scrutinees.is_empty() {
+    if depth_exceeded {
+      return SplitPlan::Unexplored { name };
+    }
+    return SplitPlan::Leaf { name };
+  }
+  if !defer_wide_scrutinees(&mut scrutinees, &context, goal.env, goal.config) {
+    return SplitPlan::Unexplored { name };
+  }
+  let var = scrutinees.pop_front().unwrap();
+  let ty = context[&var].clone();
+  let (_, cons) = &goal.env[&Symbol::from(ty.datatype().unwrap())];
+  // Number the fresh variables of all constructors first, as `case_split` does
+  let first_fresh_vars: Vec<usize> = cons
+    .iter()
+    .map(|con| {
+      let first = *num_fresh_vars;
+      *num_fresh_vars += goal.global_context[con].args_ret().0.len();
+      first
+    })
+    .collect();
+  let mut branches = vec![];
+  for (con, first_fresh_var) in cons.iter().zip(first_fresh_vars) {
+    let con_args = Goal::instantiate_constructor(&goal.global_context[con], &ty);
+    let mut child_scrutinees = scrutinees.clone();
+    let mut child_depth_exceeded = depth_exceeded;
+    let mut fresh_vars = vec![];
+    for (i, arg_type) in con_args.iter().enumerate() {
+      let fresh_var_name = format!("{}_{}", var, first_fresh_var + i);
+      let fresh_var = Symbol::from(&fresh_var_name);
+      context.insert(fresh_var, arg_type.clone());
+      let depth = var_depth(&fresh_var_name);
+      match within_split_depth(arg_type, depth, goal.env, goal.config) {
+        Some(true) => child_scrutinees.push_back(fresh_var),
+        Some(false) => child_depth_exceeded = true,
+        None => {}
+      }
+      fresh_vars.push(fresh_var_name);
+    }
+    let con_app = if fresh_vars.is_empty() {
+      con.to_string()
+    } else {
+      format!("({} {})", con, fresh_vars.join(" "))
+    };
+    let child_name = format!("{}:{}={}", name, var, con_app);
+    let child = plan_goal(
+      goal,
+      child_name,
+      child_scrutinees,
+      child_depth_exceeded,
+      context.clone(),
+      num_fresh_vars,
+    );
+    branches.push((con_app, child));
+  }
+  SplitPlan::Split {
+    name,
+    scrutinee: var.to_string(),
+    branches,
+  }
+}
+
+/// Is the canonical form of e-class id a constructor term without variables?
+fn is_closed_constructor_term(egraph: &Eg, id: Id) -> bool {
+  match &egraph[id].data {
+    CanonicalForm::Const(node) => node
+      .children
+      .iter()
+      .all(|child| is_closed_constructor_term(egraph, *child)),
+    _ => false,
+  }
+}
+
+/// An empty e-graph for a goal, which records explanations if the configuration enables them
+fn new_egraph(config: &ProverConfig) -> Eg {
+  let egraph = EGraph::new(CanonicalFormAnalysis {
+    fold_nats: config.fold_nats,
+    free_constructors: true,
+  });
+  if config.explanations {
+    egraph.with_explanations_enabled()
+  } else {
+    egraph
+  }
+}
+
+/// What saturating a goal works on, taken out of the goal (see `Goal::take_saturation`):
+/// unlike a goal, which may hold an explanation, this can be sent to another thread
+struct Saturation<'a> {
+  egraph: Eg,
+  rewrites: Vec<Rw>,
+  dirty_classes: Option<Vec<Id>>,
+  config: &'a ProverConfig,
+  stats: Option<SaturationStats>,
+}
+
+impl<'a> Saturation<'a> {
+  /// Apply all rewrites to the e-graph (see `Goal::saturate_cancellable`)
+  fn run(mut self, time_limit: Option<Duration>, cancel: &Arc<AtomicBool>) -> Self {
+    let rewrites: Vec<&Rw> = self.rewrites.iter().collect();
+    let mut propagation_rounds = 0;
+    let mut applied = HashMap::new();
+    if self.config.incremental_saturation {
+      if let Some(dirty) = self.dirty_classes.take() {
+        propagation_rounds = propagate(
+          &mut self.egraph,
+          &rewrites,
+          dirty,
+          self.config,
+          time_limit,
+          cancel,
+          &mut applied,
+        );
+      }
+    }
+    // The full saturation is still necessary: propagation does not see new lemmas matching elsewhere,
+    // nor merges made by the analysis, so it only saves the runner the iterations it already did
+    let mut runner = Runner::default()
+      .with_node_limit(self.config.max_egraph_nodes)
+      .with_iter_limit(self.config.max_iterations)
+      .with_egraph(self.egraph);
+    let priorities: HashMap<Symbol, i32> = rewrites
+      .iter()
+      .map(|rw| (rw.name, self.config.rewrite_priority(rw.name.as_str())))
+      .collect();
+    runner = match self.config.scheduler {
+      SchedulerKind::Backoff if self.config.rewrite_priorities.is_empty() => {
+        runner.with_scheduler(BackoffScheduler::default())
+      }
+      SchedulerKind::Simple if self.config.rewrite_priorities.is_empty() => {
+        runner.with_scheduler(SimpleScheduler)
+      }
+      SchedulerKind::Backoff => runner.with_scheduler(PriorityScheduler::new(
+        BackoffScheduler::default(),
+        priorities,
+      )),
+      SchedulerKind::Simple => {
+        runner.with_scheduler(PriorityScheduler::new(SimpleScheduler, priorities))
+      }
+    };
+    if let Some(time_limit) = time_limit {
+      runner = runner.with_time_limit(time_limit);
+    }
+    let cancel = cancel.clone();
+    let memoize = self.config.memoize_termination_checks;
+    runner = runner.with_hook(move |_| {
+      // The previous iteration may have changed the values the termination checks compare with
+      TERMINATION_MEMO.with(|memo| *memo.borrow_mut() = memoize.then(TerminationMemo::default));
+      if cancel.load(Ordering::Relaxed) {
+        Err("cancelled".to_string())
+      } else {
+        Ok(())
+      }
+    });
+    let runner = runner.run(rewrites);
+    TERMINATION_MEMO.with(|memo| *memo.borrow_mut() = None);
+    // Only a saturated e-graph can be propagated into incrementally later
+    self.dirty_classes = match runner.stop_reason {
+      Some(StopReason::Saturated) => Some(vec![]),
+      _ => None,
+    };
+    for iteration in runner.iterations.iter() {
+      for (name, count) in iteration.applied.iter() {
+        *applied.entry(name.to_string()).or_default() += count;
+      }
+    }
+    self.stats = Some(SaturationStats {
+      iterations: runner.iterations.len(),
+      egraph_nodes: runner.egraph.total_size(),
+      egraph_classes: runner.egraph.number_of_classes(),
+      stop_reason: runner.stop_reason,
+      propagation_rounds,
+      applied,
+    });
+    self.egraph = runner.egraph;
+    self
+  }
+}
+
+/// A scheduler that saturates rewrites in order of priority (see `ProverConfig::rewrite_priorities`):
+/// only the rewrites of the highest priority are searched at first,
+/// and the next priority is let in each time those searched so far stop changing the e-graph.
+///
+/// Scheduling within the priorities let in is left to the inner scheduler.
+/// With the backoff scheduler, a banned rewrite counts as not matching,
+/// so it does not hold back lower priorities while it is banned;
+/// but the next priority is only let in once the inner scheduler agrees to stop,
+/// which the backoff scheduler does not while any rewrite is banned (it lifts the bans instead),
+/// so every rewrite of a higher priority gets to run unbanned before a lower one is searched.
+/// Letting a priority in takes an iteration of the runner, which counts towards `max_iterations`.
+struct PriorityScheduler<S> {
+  inner: S,
+  priorities: HashMap<Symbol, i32>,
+  /// The distinct priorities of the rewrites, from highest to lowest
+  levels: Vec<i32>,
+  /// How many of `levels` are let in
+  open: usize,
+}
+
+impl<S> PriorityScheduler<S> {
+  fn new(inner: S, priorities: HashMap<Symbol, i32>) -> Self {
+    let levels: Vec<i32> = priorities
+      .values()
+      .copied()
+      .sorted_by(|a, b| b.cmp(a))
+      .dedup()
+      .collect();
+    Self {
+      inner,
+      priorities,
+      levels,
+      open: 1,
+    }
+  }
+
+  fn is_open(&self, name: Symbol) -> bool {
+    let priority = self.priorities.get(&name).copied().unwrap_or(0);
+    self
+      .levels
+      .get(self.open - 1)
+      .is_none_or(|lowest| priority >= *lowest)
+  }
+}
+
+impl<S: RewriteScheduler<SymbolLang, CanonicalFormAnalysis>>
+  RewriteScheduler<SymbolLang, CanonicalFormAnalysis> for PriorityScheduler<S>
+{
+  fn can_stop(&mut self, iteration: usize) -> bool {
+    if !self.inner.can_stop(iteration) {
+      return false;
+    }
+    if self.open < self.levels.len() {
+      self.open += 1;
+      return false;
+    }
+    true
+  }
+
+  fn search_rewrite<'a>(
+    &mut self,
+    iteration: usize,
+    egraph: &Eg,
+    rewrite: &'a Rw,
+  ) -> Vec<SearchMatches<'a, SymbolLang>> {
+    if self.is_open(rewrite.name) {
+      self.inner.search_rewrite(iteration, egraph, rewrite)
+    } else {
+      vec![]
+    }
+  }
+
+  fn apply_rewrite(
+    &mut self,
+    iteration: usize,
+    egraph: &mut Eg,
+    rewrite: &Rw,
+    matches: Vec<SearchMatches<SymbolLang>>,
+  ) -> usize {
+    self
+      .inner
+      .apply_rewrite(iteration, egraph, rewrite, matches)
+  }
+}
+
+/// What happened in a single step of the prover
+#[derive(Debug)]
+pub enum StepResult {
+  /// The popped goal was discharged by saturation
+  Discharged(String),
+  /// The popped goal's common subterms were generalized, and the goal was put back
+  Generalized(String),
+  /// The popped goal was case-split on the given scrutinee
+  /// (for a joint split, the first of the scrutinees split together)
+  Split(String, Symbol),
+  /// The popped goal turned out invalid or unknown, which was recorded in `ProofState::failures`
+  /// instead of ending the proof (only with `exhaustive_failures`)
+  Failed(String),
+  /// The proof attempt is over
+  Done(Outcome),
+}
+
+/// Top-level interface to the theorem prover.
+/// Besides the verdict, returns the final proof state: if the conjecture is valid, no goals are pending;
+/// otherwise the pending goals are the cases that were left to prove,
+/// and the goal that turned out invalid or unknown (if any) is in `ProofState::dead_ends`.
+pub fn prove(goal: Goal) -> (Outcome, ProofState) {
+  prove_cancellable(goal, Arc::new(AtomicBool::new(false)))
+}
+
+/// Like `prove`, but the proof can be cancelled by setting token, e.g. from another thread:
+/// the prover checks it before each step and while saturating a goal,
+/// and once it is set, gives up with `UnknownReason::Cancelled`.
+/// A goal whose saturation was cut short is put back, so like with a timeout,
+/// the pending goals are the cases left to prove; but cancellation may leave them undischarged
+/// even if the conjecture holds, and goals that were split are not revisited.
+pub fn prove_cancellable(goal: Goal, token: Arc<AtomicBool>) -> (Outcome, ProofState) {
+  let mut search = ProofSearch::new(goal);
+  search.state.cancel = token;
+  search.finish_with_observer(&mut NoObserver)
+}
+
+/// Like `prove`, but notifying observer of the progress of the proof, which it can also cancel
+pub fn prove_with_observer<'a>(
+  goal: Goal<'a>,
+  observer: &mut dyn ProofObserver,
+) -> (Outcome, ProofState<'a>) {
+  ProofSearch::new(goal).finish_with_observer(observer)
+}
+
+/// Like `prove`, but saturating the pending goals in parallel (see `ProofSearch::finish_parallel`)
+pub fn prove_parallel(goal: Goal) -> (Outcome, ProofState) {
+  ProofSearch::new(goal).finish_parallel()
+}
+
+/// Search exhaustively for a counterexample to goal:
+/// try every assignment of constructor terms up to the given depth (as in `Goal::all_values`)
+/// to the top-level parameters, smaller depths first, and return the first one the goal fails on
+/// (see `Goal::fails_on`).
+/// Unlike a dead end in the proof search, a counterexample found this way is definitive.
+/// Finding none is not a proof: the goal may still fail on larger values,
+/// and some sides may not evaluate completely within the saturation limits.
+/// Returns None right away if some parameter has a type whose values cannot be enumerated,
+/// such as a type variable or a function type, or if the goal is proven up to equivalences (as in `Goal::quickcheck_goal`).
+pub fn disprove(goal: &Goal, depth: usize) -> Option<Counterexample> {
+  if goal.has_equivalences() {
+    return None;
+  }
+  if goal.params.is_empty() {
+    return goal.fails_on(&[]).then(|| Counterexample::new(vec![]));
+  }
+  // The values tried at the previous depth, for every parameter
+  let mut tried: Vec<HashSet<String>> = vec![HashSet::new(); goal.params.len()];
+  for d in 0..=depth {
+    let values = goal
+      .params
+      .iter()
+      .map(|param| goal.all_values(&goal.local_context[param], d))
+      .collect::<Option<Vec<Vec<Sexp>>>>()?;
+    for assignment in values.iter().multi_cartesian_product() {
+      // Assignments of values that were all available at the previous depth have been tried already
+      let new = assignment
+        .iter()
+        .zip(tried.iter())
+        .any(|(value, tried)| !tried.contains(&value.to_string()));
+      if !new {
+        continue;
+      }
+      let assignment: Vec<(Symbol, Sexp)> = goal
+        .params
+        .iter()
+        .cloned()
+        .zip(assignment.into_iter().cloned())
+        .collect();
+      if goal.fails_on(&assignment) {
+        return Some(Counterexample::new(assignment));
+      }
+    }
+    tried = values
+      .iter()
+      .map(|values| values.iter().map(Sexp::to_string).collect())
+      .collect();
+  }
+  None
+}
+
+/// Shrink a counterexample to goal, as property-based testing does:
+/// repeatedly replace the value of some parameter by a smaller one (see `Goal::shrinks`),
+/// as long as the goal still fails on the result (see `Goal::fails_on`), until no smaller value does.
+/// The result is a smallest counterexample in that no single shrink of it is one,
+/// though a different sequence of shrinks might lead to an even smaller one.
+/// The counterexample is returned unchanged unless its values are closed constructor terms
+/// on which the goal fails, e.g. when it comes from a dead end whose variables were not all split.
+pub fn shrink_counterexample(goal: &Goal, cex: &Counterexample) -> Counterexample {
+  let mut assignment: Vec<(Symbol, Sexp)> = cex
+    .assignment
+    .iter()
+    .map(|(x, value)| (*x, parser::parse_str(&value.to_string()).unwrap()))
+    .collect();
+  let closed = cex.assignment.iter().all(|(_, value)| {
+    value
+      .as_ref()
+      .iter()
+      .all(|node| is_constructor(node.op.as_str()))
+  });
+  let params_match = assignment.iter().map(|(x, _)| x).eq(goal.params.iter());
+  if !closed || !params_match || goal.has_equivalences() || !goal.fails_on(&assignment) {
+    return cex.clone();
+  }
+  loop {
+    let smaller = assignment.iter().enumerate().find_map(|(i, (x, value))| {
+      goal
+        .shrinks(value, &goal.local_context[x])
+        .into_iter()
+        .map(|value| {
+          let mut smaller = assignment.clone();
+          smaller[i].1 = value;
+          smaller
+        })
+        .find(|smaller| goal.fails_on(smaller))
+    });
+    match smaller {
+      Some(smaller) => assignment = smaller,
+      None => return Counterexample::new(assignment),
+    }
+  }
+}
+
+/// Prove goal using the given lemmas in addition to the definitions;
+/// the lemmas are trusted without being checked (see `Goal::add_lemmas`).
+pub fn prove_with_lemmas(mut goal: Goal, lemmas: Vec<Rw>) -> (Outcome, ProofState) {
+  goal.add_lemmas(lemmas);
+  prove(goal)
+}
+
+/// A step of the proof search that did not conclude it
+#[derive(Debug)]
+pub struct SearchStep {
+  /// The pending subgoals after the step, as printed by `pretty_state`
+  pub state: String,
+  /// What the step did (never `StepResult::Done`)
+  pub action: StepResult,
+}
+
+/// A serializable summary of a proof state, from which the search can be resumed with `ProofState::resume`.
+///
+/// It is lossy: instead of its e-graph, every pending goal records its equations and premises,
+/// the values of its variables and the conditions it has split on, from which a fresh e-graph is rebuilt;
+/// whatever saturation derived from these is derived again when the goal is saturated.
+/// Explanations of the cases proven so far are not recorded, so no proofs can be emitted for a resumed search.
+/// Neither are the cases that failed with `exhaustive_failures`, which a resumed search does not report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofStateSnapshot {
+  pub goals: Vec<GoalSnapshot>,
+  pub proof: Vec<(String, ProofTerm)>,
+  /// Name, lhs and rhs of every unconditional lemma created so far
+  pub unconditional_lemmas: Vec<(String, String, String)>,
+  /// Signature, goal name and numbered variables of every goal signature seen so far
+  pub seen_goals: Vec<(String, String, Vec<String>)>,
+  pub num_fresh_vars: usize,
+  pub num_splits: usize,
+  /// Time spent on the proof so far, in milliseconds
+  pub elapsed_ms: u64,
+}
+
+/// The sides of an equation in a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EquationSnapshot {
+  pub lhs: String,
+  pub rhs: String,
+}
+
+/// A pending goal in a snapshot;
+/// expressions are in terms of the variables of the goal at the time of the snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoalSnapshot {
+  pub name: String,
+  pub eqs: Vec<EquationSnapshot>,
+  pub premises: Vec<EquationSnapshot>,
+  pub params: Vec<String>,
+  /// Every variable of the goal (including the ones split away) with its type
+  pub local_context: Vec<(String, String)>,
+  /// Every variable of the goal with its value (a constructor application if it has been split)
+  pub var_values: Vec<(String, String)>,
+  pub scrutinees: Vec<String>,
+  pub depth_exceeded: bool,
+  pub joint_groups: Vec<Vec<String>>,
+  /// Instantiations of the induction hypothesis (non-cyclic mode)
+  pub grounding_instantiations: Vec<Vec<(String, String)>>,
+  /// Every guard scrutinee with the condition it stands for and its value
+  pub guards: Vec<(String, String, String)>,
+  pub case_splits: Vec<(String, String)>,
+  pub lemmas: Vec<LemmaSnapshot>,
+  /// Names of the lemmas that are the induction hypotheses of this goal
+  pub induction_hypotheses: Vec<String>,
+  pub generalized: bool,
+  pub accumulators: Vec<String>,
+}
+
+/// A lemma in a snapshot; the values of its variables are those they had when the lemma was created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LemmaSnapshot {
+  pub name: String,
+  pub lhs: String,
+  pub rhs: String,
+  pub free_vars: Vec<(String, String)>,
+  pub higher_order_vars: Vec<(String, String)>,
+  pub premises: Vec<EquationSnapshot>,
+}
+
+impl<'a> Goal<'a> {
+  /// The expression of e-class id to record in a snapshot:
+  /// its canonical form if it has one, since that is what identifies the class after a split
+  fn snapshot_value(
+    &self,
+    extractor: &Extractor<ConfiguredCost, SymbolLang, CanonicalFormAnalysis>,
+    id: Id,
+  ) -> String {
+    CanonicalFormAnalysis::extract_canonical(&self.egraph, id)
+      .unwrap_or_else(|| extractor.find_best(id).1)
+      .to_string()
+  }
+
+  /// Summarize this goal for a `ProofStateSnapshot`
+  pub fn snapshot(&self) -> GoalSnapshot {
+    let extractor = self.extractor();
+    let value = |id: Id| self.snapshot_value(&extractor, id);
+    // Equations and premises are recorded as they are written, in terms of the goal's parameters,
+    // since grounding instantiates them; the variables are unioned with their values when the goal is rebuilt
+    let eq_snapshot = |eq: &Equation| EquationSnapshot {
+      lhs: eq.lhs.sexp.to_string(),
+      rhs: eq.rhs.sexp.to_string(),
+    };
+    let subst_snapshot = |subst: &IdSubst| {
+      subst
+        .iter()
+        .map(|(x, id)| (x.to_string(), value(*id)))
+        .collect()
+    };
+    // The conditions guards stand for are in terms of the variables when they were added,
+    // so we look them up with the current values of the variables
+    let values: SSubst = self
+      .var_classes
+      .iter()
+      .map(|(x, id)| (x.to_string(), parser::parse_str(&value(*id)).unwrap()))
+      .collect();
+    let guards = self
+      .guard_exprs
+      .iter()
+      .sorted_by_key(|(g, _)| g.to_string())
+      .map(|(g, expr)| {
+        let current = resolve_sexp(&parser::parse_str(&expr.to_string()).unwrap(), &values);
+        let current_expr: Expr = current.to_string().parse().unwrap();
+        // A guard that has not been split yet is its own value
+        let guard_value = match self.egraph.lookup_expr(&current_expr) {
+          Some(id) if !self.scrutinees.contains(&Symbol::from(g)) => value(id),
+          _ => g.clone(),
+        };
+        (g.clone(), current.to_string(), guard_value)
+      })
+      .collect();
+    let lemmas = self
+      .lemma_rewrites
+      .iter()
+      .sorted_by_key(|(name, _)| name.to_string())
+      .map(|(name, lemma)| LemmaSnapshot {
+        name: name.clone(),
+        lhs: lemma.lhs.to_string(),
+        rhs: lemma.rhs.to_string(),
+        free_vars: subst_snapshot(&lemma.condition.free_vars),
+        higher_order_vars: subst_snapshot(&lemma.condition.higher_order_vars),
+        premises: lemma
+          .condition
+          .premises
+          .iter()
+          .map(|eq| EquationSnapshot {
+            lhs: eq.lhs.sexp.to_string(),
+            rhs: eq.rhs.sexp.to_string(),
+          })
+          .collect(),
+      })
+      .collect();
+    GoalSnapshot {
+      name: self.name.clone(),
+      eqs: self.eqs.iter().map(eq_snapshot).collect(),
+      premises: self.premises.iter().map(eq_snapshot).collect(),
+      params: self.params.iter().map(|x| x.to_string()).collect(),
+      local_context: self
+        .local_context
+        .iter()
+        .map(|(x, ty)| (x.to_string(), ty.to_string()))
+        .sorted()
+        .collect(),
+      var_values: subst_snapshot(&self.var_classes),
+      scrutinees: self.scrutinees.iter().map(|x| x.to_string()).collect(),
+      depth_exceeded: self.depth_exceeded,
+      joint_groups: self
+        .joint_groups
+        .iter()
+        .map(|group| group.iter().map(|x| x.to_string()).collect())
+        .collect(),
+      grounding_instantiations: self
+        .grounding_instantiations
+        .iter()
+        .map(subst_snapshot)
+        .collect(),
+      guards,
+      case_splits: self
+        .case_splits
+        .iter()
+        .map(|(x, con_app)| (x.to_string(), con_app.to_string()))
+        .collect(),
+      lemmas,
+      induction_hypotheses: self
+        .induction_hypotheses
+        .iter()
+        .map(|rw| rw.name.to_string())
+        .collect(),
+      generalized: self.generalized,
+      accumulators: self.accumulators.iter().map(|x| x.to_string()).collect(),
+    }
+  }
+
+  /// Rebuild a goal from a snapshot;
+  /// everything that is not part of the snapshot (definitions, environment, configuration, and external lemmas)
+  /// comes from top, the top-level goal of the proof the snapshot was taken from.
+  pub fn from_snapshot(snapshot: &GoalSnapshot, top: &Goal<'a>) -> Result<Self, GoalError> {
+    let invalid = |s: &str| GoalError::InvalidSnapshot(s.to_string());
+    let sexp = |s: &str| parser::parse_str(s).map_err(|_| invalid(s));
+    let expr = |s: &str| s.parse::<Expr>().map_err(|_| invalid(s));
+    let pattern = |s: &str| s.parse::<Pat>().map_err(|_| invalid(s));
+    let raw_eq = |eq: &EquationSnapshot| -> Result<RawEquation, GoalError> {
+      Ok(RawEquation {
+        lhs: sexp(&eq.lhs)?,
+        rhs: sexp(&eq.rhs)?,
+      })
+    };
+    let mut egraph = new_egraph(top.config);
+    egraph.analysis.free_constructors = top.egraph.analysis.free_constructors;
+    let add_subst = |egraph: &mut Eg, subst: &[(String, String)]| -> Result<IdSubst, GoalError> {
+      subst
+        .iter()
+        .map(|(x, value)| Ok((Symbol::from(x), egraph.add_expr(&expr(value)?))))
+        .collect()
+    };
+    let eqs = snapshot
+      .eqs
+      .iter()
+      .map(|eq| Equation::new(&raw_eq(eq)?, &mut egraph, false))
+      .collect::<Result<Vec<Equation>, GoalError>>()?;
+    let premises = snapshot
+      .premises
+      .iter()
+      .map(|eq| Equation::new(&raw_eq(eq)?, &mut egraph, true))
+      .collect::<Result<Vec<Equation>, GoalError>>()?;
+    let mut var_classes = IdSubst::default();
+    for (x, value) in snapshot.var_values.iter() {
+      let var_id = egraph.add(SymbolLang::leaf(x));
+      let value_id = egraph.add_expr(&expr(value)?);
+      egraph.union_trusted(var_id, value_id, format!("case split {}={}", x, value));
+      var_classes.insert(Symbol::from(x), var_id);
+    }
+    let mut guard_exprs = HashMap::new();
+    for (g, condition, value) in snapshot.guards.iter() {
+      let condition = expr(condition)?;
+      let condition_id = egraph.add_expr(&condition);
+      let value_id = egraph.add_expr(&expr(value)?);
+      egraph.union_trusted(condition_id, value_id, format!("guard {}", g));
+      guard_exprs.insert(g.clone(), condition);
+    }
+    let grounding_instantiations = snapshot
+      .grounding_instantiations
+      .iter()
+      .map(|subst| add_subst(&mut egraph, subst))
+      .collect::<Result<Vec<IdSubst>, GoalError>>()?;
+    // Add the instances of the goal's sides that grounding added, as in `add_grounding`
+    for inst in snapshot.grounding_instantiations.iter() {
+      let inst: SSubst = inst
+        .iter()
+        .map(|(x, value)| Ok((x.clone(), sexp(value)?)))
+        .collect::<Result<SSubst, GoalError>>()?;
+      for eq in eqs.iter().chain(premises.iter()) {
+        ETerm::new(&resolve_sexp(&eq.lhs.sexp, &inst), &mut egraph);
+        ETerm::new(&resolve_sexp(&eq.rhs.sexp, &inst), &mut egraph);
+      }
+    }
+    let mut lemmas = HashMap::new();
+    for lemma in snapshot.lemmas.iter() {
+      let condition = Soundness {
+        free_vars: add_subst(&mut egraph, &lemma.free_vars)?,
+        higher_order_vars: add_subst(&mut egraph, &lemma.higher_order_vars)?,
+        premises: lemma
+          .premises
+          .iter()
+          .map(|eq| Equation::new(&raw_eq(eq)?, &mut egraph, false))
+          .collect::<Result<Vec<Equation>, GoalError>>()?,
+        order: top.config.termination_order,
+        custom_order: top.config.custom_termination,
+        verify: top.config.verify_lemmas,
+      };
+      let lemma = Lemma::new(
+        lemma.name.clone(),
+        pattern(&lemma.lhs)?,
+        pattern(&lemma.rhs)?,
+        condition,
+      );
+      lemmas.insert(lemma.rewrite.name.to_string(), lemma);
+    }
+    egraph.rebuild();
+    let induction_hypotheses = snapshot
+      .induction_hypotheses
+      .iter()
+      .filter_map(|name| lemmas.get(name).map(|lemma| lemma.rewrite.clone()))
+      .collect();
+    Ok(Goal {
+      name: snapshot.name.clone(),
+      egraph,
+      def_rewrites: top.def_rewrites.clone(),
+      lemma_rewrites: lemmas,
+      induction_hypotheses,
+      external_lemmas: top.external_lemmas.clone(),
+      equivalence_rewrites: top.equivalence_rewrites.clone(),
+      local_context: snapshot
+        .local_context
+        .iter()
+        .map(|(x, ty)| Ok((Symbol::from(x), Type::new(sexp(ty)?))))
+        .collect::<Result<Context, GoalError>>()?,
+      var_classes,
+      params: snapshot.params.iter().map(Symbol::from).collect(),
+      scrutinees: snapshot.scrutinees.iter().map(Symbol::from).collect(),
+      depth_exceeded: snapshot.depth_exceeded,
+      joint_groups: snapshot
+        .joint_groups
+        .iter()
+        .map(|group| group.iter().map(Symbol::from).collect())
+        .collect(),
+      grounding_instantiations,
+      eqs,
+      premises,
+      env: top.env,
+      global_context: top.global_context,
+      discharged_by: None,
+      defns: top.defns,
+      guard_exprs,
+      config: top.config,
+      saturation_stats: None,
+      dirty_classes: None,
+      case_splits: snapshot
+        .case_splits
+        .iter()
+        .map(|(x, con_app)| Ok((Symbol::from(x), expr(con_app)?)))
+        .collect::<Result<Vec<(Symbol, Expr)>, GoalError>>()?,
+      generalized: snapshot.generalized,
+      accumulators: snapshot.accumulators.iter().map(Symbol::from).collect(),
+      pending_cases: vec![],
+    })
   }
 }
 
-#[derive(Clone, Debug)]
-pub enum ProofTerm {
-  /// - Arg0: Name of the variable we split on
-  /// - Arg1: List of cases we split on
-  ///   * Arg0: Sexp we split to
-  ///   * Arg1: Name of goal from this split
-  ///
-  /// Example:
-  /// ```
-  /// CaseSplit("x", [("(Z)", "goal_1"), ("(S x')","goal_2")])
-  /// ```
-  /// corresponds to the proof
-  /// ```
-  /// case x of
-  ///   Z    -> goal_1
-  ///   S x' -> goal_2
-  /// ```
-  CaseSplit(String, Vec<(String, String)>),
-  /// The same thing as a case split, except instead of splitting on one of the
-  /// symbolic variables, we split on an expression.
-  ///
-  /// - Arg0: A fresh variable introduced that is equal to the expression
-  /// - Arg1: The expression we split on
-  /// - Arg2: List of cases we split on (same as above).
-  ///         There will always be two cases, corresponding to `True` and `False`.
+impl<'a> ProofState<'a> {
+  /// Summarize this proof state so that the search can be resumed later (see `ProofStateSnapshot`)
+  pub fn snapshot(&self) -> ProofStateSnapshot {
+    ProofStateSnapshot {
+      goals: self.goals.iter().flat_map(Goal::case_snapshots).collect(),
+      proof: self
+        .proof
+        .iter()
+        .map(|(name, term)| (name.clone(), term.clone()))
+        .sorted_by(|(name1, _), (name2, _)| name1.cmp(name2))
+        .collect(),
+      unconditional_lemmas: self
+        .unconditional_lemmas
+        .iter()
+        .map(|(name, (lhs, rhs))| (name.clone(), lhs.to_string(), rhs.to_string()))
+        .sorted()
+        .collect(),
+      seen_goals: self
+        .seen_goals
+        .iter()
+        .map(|(signature, (name, vars))| {
+          (
+            signature.clone(),
+            name.clone(),
+            vars.iter().map(|x| x.to_string()).collect(),
+          )
+        })
+        .sorted()
+        .collect(),
+      num_fresh_vars: self.num_fresh_vars,
+      num_splits: self.num_splits,
+      elapsed_ms: self.start_time.elapsed().as_millis() as u64,
+    }
+  }
+
+  /// Rebuild a proof state from a snapshot of a proof of top,
+  /// which provides the definitions, environment, configuration and external lemmas;
+  /// the time already spent counts towards the timeout.
+  pub fn resume(snapshot: &ProofStateSnapshot, top: Goal<'a>) -> Result<Self, GoalError> {
+    let invalid = |s: &str| GoalError::InvalidSnapshot(s.to_string());
+    let pattern = |s: &str| s.parse::<Pat>().map_err(|_| invalid(s));
+    let goals = snapshot
+      .goals
+      .iter()
+      .map(|goal| Goal::from_snapshot(goal, &top))
+      .collect::<Result<VecDeque<Goal>, GoalError>>()?;
+    let unconditional_lemmas = snapshot
+      .unconditional_lemmas
+      .iter()
+      .map(|(name, lhs, rhs)| Ok((name.clone(), (pattern(lhs)?, pattern(rhs)?))))
+      .collect::<Result<HashMap<String, (Pat, Pat)>, GoalError>>()?;
+    let elapsed = Duration::from_millis(snapshot.elapsed_ms);
+    Ok(ProofState {
+      config: top.config,
+      goals,
+      solved_goal_explanation_and_context: HashMap::default(),
+      proof: snapshot.proof.iter().cloned().collect(),
+      start_time: Instant::now()
+        .checked_sub(elapsed)
+        .unwrap_or_else(Instant::now),
+      report: ProofReport::default(),
+      unconditional_lemmas,
+      seen_goals: snapshot
+        .seen_goals
+        .iter()
+        .map(|(signature, name, vars)| {
+          (
+            signature.clone(),
+            (name.clone(), vars.iter().map(Symbol::from).collect()),
+          )
+        })
+        .collect(),
+      num_fresh_vars: snapshot.num_fresh_vars,
+      num_splits: snapshot.num_splits,
+      failures: vec![],
+      dead_ends: vec![],
+      cancel: Arc::new(AtomicBool::new(false)),
+    })
+  }
+}
+
+/// The proof search as an iterator over its steps, for driving the prover lazily;
+/// the iterator ends when the proof concludes, after which `outcome` gives the verdict.
+/// Iterating to the end is the same as calling `prove`.
+pub struct ProofSearch<'a> {
+  state: ProofState<'a>,
+  outcome: Option<Outcome>,
+}
+
+impl<'a> ProofSearch<'a> {
+  pub fn new(goal: Goal<'a>) -> Self {
+    Self::started_at(goal, Instant::now())
+  }
+
+  /// Like `new`, but with the timeout counted from start_time
+  fn started_at(mut goal: Goal<'a>, start_time: Instant) -> Self {
+    if goal.config.generalize_accumulators {
+      goal.add_accumulator_lemma(start_time);
+    }
+    let trials = goal.config.quickcheck_trials;
+    let refutation = if trials > 0 && !goal.is_trivially_valid() {
+      goal.quickcheck_goal(trials)
+    } else {
+      None
+    };
+    let outcome = refutation.map(|cex| {
+      let cex = shrink_counterexample(&goal, &cex);
+      info!("refuted by testing: {}", cex);
+      Outcome::Invalid(cex)
+    });
+    let mut state = ProofState::new(goal);
+    state.start_time = start_time;
+    Self { state, outcome }
+  }
+
+  /// Continue the search from a proof state, e.g. one resumed from a snapshot
+  pub fn from_state(state: ProofState<'a>) -> Self {
+    Self {
+      state,
+      outcome: None,
+    }
+  }
+
+  /// The verdict, once the search has concluded
+  pub fn outcome(&self) -> Option<&Outcome> {
+    self.outcome.as_ref()
+  }
+
+  /// The current proof state
+  pub fn state(&self) -> &ProofState<'a> {
+    &self.state
+  }
+
+  /// Run the search to the end (if it has not concluded yet) and return the verdict with the final state
+  pub fn finish(self) -> (Outcome, ProofState<'a>) {
+    self.finish_with_observer(&mut NoObserver)
+  }
+
+  /// Like `finish`, but notifying observer of every step, which can also cancel the search
+  pub fn finish_with_observer(
+    mut self,
+    observer: &mut dyn ProofObserver,
+  ) -> (Outcome, ProofState<'a>) {
+    while self.outcome.is_none() {
+      if let StepResult::Done(outcome) = observed_step(&mut self.state, observer) {
+        self.outcome = Some(outcome);
+      }
+    }
+    let outcome = self.outcome.unwrap();
+    observer.on_outcome(&outcome);
+    (outcome, self.state)
+  }
+
+  /// Run the search to the end like `finish`, but saturate all pending goals at once, in parallel:
+  /// the goals pending at any point are independent of each other, and saturation is most of the work.
   ///
-  /// Example:
-  /// ```
-  /// ITESplit("g0", "(lt x y)", [("True", "goal_1"), ("False", "goal_2")])
-  /// ```
-  /// corresponds to the proof
-  /// ```
-  /// let g0 = lt x y in
-  ///   case g0 of
-  ///     True  -> goal_1
-  ///     False -> goal_2
-  /// ```
-  ITESplit(String, String, Vec<(String, String)>),
+  /// The search proceeds in rounds: every round takes all pending goals (the frontier),
+  /// saturates them concurrently, and then concludes them one at a time, in the order `next_goal` would pop them,
+  /// exactly as `prove_step` does after saturating a goal, which makes the new pending goals of the next round.
+  /// The first goal in that order that settles the proof (e.g. one that is invalid,
+  /// or exceeds the depth bound) gives the verdict, and the rest of the round is dropped;
+  /// no later round is started. Since saturation of one goal does not depend on any other,
+  /// the verdict (and proof) is the same however the saturations are scheduled,
+  /// except when the timeout cuts them short.
+  /// Rounds explore the search tree breadth first, whatever the search strategy,
+  /// so a proof that fails may saturate more goals than `finish` before it does.
+  pub fn finish_parallel(mut self) -> (Outcome, ProofState<'a>) {
+    while self.outcome.is_none() {
+      if self.state.goals.is_empty() {
+        self.outcome = Some(self.state.verdict());
+        break;
+      }
+      if self.state.timeout() {
+        self.outcome = Some(Outcome::Timeout);
+        break;
+      }
+      if self.state.cancelled() {
+        self.outcome = Some(Outcome::Unknown(UnknownReason::Cancelled));
+        break;
+      }
+      debug!("PROOF STATE: {}", pretty_state(&self.state));
+      let mut frontier = vec![];
+      while let Some(goal) = self.state.next_goal() {
+        frontier.push(goal);
+      }
+      // Goals themselves stay on this thread, only what saturation works on is sent to the pool
+      // (as in `saturate_goal`, trivially valid goals are not saturated)
+      let saturations: Vec<Option<Saturation>> = frontier
+        .iter_mut()
+        .map(|goal| (!goal.is_trivially_valid()).then(|| goal.take_saturation()))
+        .collect();
+      let time_limit = self.state.remaining_time();
+      let cancel = &self.state.cancel;
+      let saturations: Vec<Option<Saturation>> = saturations
+        .into_par_iter()
+        .map(|saturation| saturation.map(|saturation| saturation.run(time_limit, cancel)))
+        .collect();
+      for (mut goal, saturation) in frontier.into_iter().zip(saturations) {
+        let _span = info_span!("goal", name = %goal.name).entered();
+        let saturated = saturation.is_some();
+        if let Some(saturation) = saturation {
+          goal.put_saturation(saturation);
+        }
+        if let StepResult::Done(outcome) = conclude_step(&mut self.state, goal, saturated) {
+          self.outcome = Some(outcome);
+          break;
+        }
+      }
+    }
+    (self.outcome.unwrap(), self.state)
+  }
 }
 
-/// A proof state is a list of subgoals,
-/// all of which have to be discharged
-pub struct ProofState<'a> {
-  pub goals: Vec<Goal<'a>>,
-  pub solved_goal_explanation_and_context: HashMap<String, (Explanation<SymbolLang>, Context)>,
-  pub proof: HashMap<String, ProofTerm>,
-  pub start_time: Instant,
+impl<'a> Iterator for ProofSearch<'a> {
+  type Item = SearchStep;
+
+  fn next(&mut self) -> Option<SearchStep> {
+    if self.outcome.is_some() {
+      return None;
+    }
+    match prove_step(&mut self.state) {
+      StepResult::Done(outcome) => {
+        self.outcome = Some(outcome);
+        None
+      }
+      action => Some(SearchStep {
+        state: pretty_state(&self.state),
+        action,
+      }),
+    }
+  }
 }
 
-impl<'a> ProofState<'a> {
-  // Has timeout been reached?
-  pub fn timeout(&self) -> bool {
-    CONFIG.timeout.is_some()
-      && self.start_time.elapsed() > Duration::new(CONFIG.timeout.unwrap(), 0)
+/// Hooks through which an embedder (e.g. a GUI) can follow a proof while it runs.
+/// Every hook but `on_outcome` returns whether the proof should go on:
+/// returning false cancels it, and it ends as `UnknownReason::Cancelled`
+/// (a goal that was popped but not split is dropped from the proof state).
+/// All hooks do nothing by default.
+pub trait ProofObserver {
+  /// A goal was taken from the pending goals, and is about to be saturated
+  fn on_goal_popped(&mut self, _goal: &Goal) -> bool {
+    true
+  }
+
+  /// A goal was saturated (goals whose sides are already equal are not)
+  fn on_saturated(&mut self, _goal: &Goal, _stats: &SaturationStats) -> bool {
+    true
+  }
+
+  /// A goal was case-split on var, which was instantiated to each of the constructor applications
+  /// (for a joint split, var is the first of the variables split together)
+  fn on_split(&mut self, _var: Symbol, _constructors: &[String]) -> bool {
+    true
   }
+
+  /// The proof concluded
+  fn on_outcome(&mut self, _outcome: &Outcome) {}
 }
 
-/// Pretty-printed proof state
-pub fn pretty_state(state: &ProofState) -> String {
-  format!(
-    "[{}]",
-    state
-      .goals
-      .iter()
-      .map(|g| g.name.clone())
-      .collect::<Vec<String>>()
-      .join(", ")
-  )
+/// An observer that ignores everything
+pub struct NoObserver;
+
+impl ProofObserver for NoObserver {}
+
+/// Pop the next subgoal from the proof state, saturate it, and then either discharge it or split it;
+/// returns a verdict once the conjecture is proved or it is clear that it cannot be.
+pub fn prove_step(state: &mut ProofState) -> StepResult {
+  observed_step(state, &mut NoObserver)
 }
 
-/// Outcome of a proof attempt
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
-pub enum Outcome {
-  Valid,
-  Invalid,
-  Unknown,
-  Timeout,
+/// Like `prove_step`, notifying observer of what the step does
+/// (but not of the verdict, which is up to the caller)
+pub fn observed_step(state: &mut ProofState, observer: &mut dyn ProofObserver) -> StepResult {
+  if state.goals.is_empty() {
+    // All goals have been concluded, so unless one of them failed, the conjecture is valid:
+    return StepResult::Done(state.verdict());
+  }
+  if state.timeout() {
+    return StepResult::Done(Outcome::Timeout);
+  }
+  if state.cancelled() {
+    return StepResult::Done(Outcome::Unknown(UnknownReason::Cancelled));
+  }
+
+  debug!("PROOF STATE: {}", pretty_state(state));
+  trace!("PENDING GOALS:\n{}", pretty_state_verbose(state));
+  // Pop the next subgoal
+  let goal = state.next_goal().unwrap();
+  let _span = info_span!("goal", name = %goal.name).entered();
+  let cancelled = StepResult::Done(Outcome::Unknown(UnknownReason::Cancelled));
+  if !observer.on_goal_popped(&goal) {
+    return cancelled;
+  }
+  let (goal, saturated) = saturate_goal(goal, state.remaining_time(), &state.cancel);
+  if let (true, Some(stats)) = (saturated, &goal.saturation_stats) {
+    if !observer.on_saturated(&goal, stats) {
+      return cancelled;
+    }
+  }
+  let result = conclude_step(state, goal, saturated);
+  if let StepResult::Split(name, var) = &result {
+    let constructors: Vec<String> = match state.proof.get(name) {
+      Some(ProofTerm::CaseSplit(_, cases)) | Some(ProofTerm::ITESplit(_, _, cases)) => {
+        // (printed as expressions, which drops the parentheses around nullary constructors)
+        cases
+          .iter()
+          .map(|(con_app, _)| con_app.parse::<Expr>().unwrap().to_string())
+          .collect()
+      }
+      _ => vec![],
+    };
+    if !observer.on_split(*var, &constructors) {
+      return cancelled;
+    }
+  }
+  result
 }
 
-impl std::fmt::Display for Outcome {
-  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    match *self {
-      Outcome::Valid => write!(f, "{}", "VALID".green()),
-      Outcome::Invalid => write!(f, "{}", "INVALID".red()),
-      Outcome::Unknown => write!(f, "{}", "UNKNOWN".yellow()),
-      Outcome::Timeout => write!(f, "{}", "TIMEOUT".yellow()),
+/// Saturate goal (but not past the time limit),
+/// unless its sides are equal already, which saturation cannot change;
+/// returns whether it was saturated
+fn saturate_goal<'a>(
+  goal: Goal<'a>,
+  time_limit: Option<Duration>,
+  cancel: &Arc<AtomicBool>,
+) -> (Goal<'a>, bool) {
+  if goal.is_trivially_valid() {
+    (goal, false)
+  } else {
+    (goal.saturate_cancellable(time_limit, cancel), true)
+  }
+}
+
+/// The rest of a step of the prover once goal has been popped and saturated (see `prove_step`);
+/// with `exhaustive_failures`, a goal that is invalid or unknown does not end the proof,
+/// but is recorded along with its verdict
+fn conclude_step<'a>(state: &mut ProofState<'a>, goal: Goal<'a>, saturated: bool) -> StepResult {
+  let name = goal.name.clone();
+  match conclude_goal(state, goal, saturated) {
+    StepResult::Done(outcome @ (Outcome::Invalid(_) | Outcome::Unknown(_)))
+      if state.config.exhaustive_failures =>
+    {
+      info!("case {} failed: {}", name, outcome);
+      state.failures.push((name.clone(), outcome));
+      StepResult::Failed(name)
     }
+    result => result,
   }
 }
 
-pub fn explain_goal_failure(goal: &Goal) {
-  println!("{} {}", "Could not prove".red(), goal.name);
-  println!("{}", "LHS Nodes".cyan());
-  let extractor = egg::Extractor::new(&goal.egraph, AstSize);
-  for lhs_node in goal.egraph[goal.eq.lhs.id].nodes.iter() {
-    let child_rec_exprs: String = lhs_node
-      .children
-      .iter()
-      .map(|child_id| {
-        let (_, best_rec_expr) = extractor.find_best(*child_id);
-        best_rec_expr.to_string()
-      })
-      .collect::<Vec<String>>()
-      .join(" ");
-    if child_rec_exprs.is_empty() {
-      println!("({})", lhs_node);
-    } else {
-      println!("({} {})", lhs_node, child_rec_exprs);
+/// Discharge, generalize or split goal, or else return a verdict for it (see `conclude_step`)
+fn conclude_goal<'a>(
+  state: &mut ProofState<'a>,
+  mut goal: Goal<'a>,
+  saturated: bool,
+) -> StepResult {
+  if saturated {
+    if let Some(stats) = &goal.saturation_stats {
+      state.report.record(stats);
     }
   }
-  println!("{}", "RHS Nodes".cyan());
-  for rhs_node in goal.egraph[goal.eq.rhs.id].nodes.iter() {
-    let child_rec_exprs: String = rhs_node
-      .children
-      .iter()
-      .map(|child_id| {
-        let (_, best_rec_expr) = extractor.find_best(*child_id);
-        best_rec_expr.to_string()
-      })
-      .collect::<Vec<String>>()
-      .join(" ");
-    if child_rec_exprs.is_empty() {
-      println!("({})", rhs_node);
+  if state.config.save_graphs {
+    goal.save_egraph();
+  }
+  if state.config.export_json {
+    let path = state
+      .config
+      .output_directory
+      .join(format!("{}.json", filesystem_safe_name(&goal.name)));
+    if let Err(err) = goal.export_egraph_json(&path) {
+      warn!("could not export e-graph to {}: {}", path.display(), err);
+    }
+  }
+  goal.check_validity();
+  if goal.is_discharged() {
+    // This goal has been discharged, proceed to the next goal
+    info!("proved case {}", goal.name);
+    let mut explanation = goal.explanation();
+    if let Some(explanation) = &mut explanation {
+      if tracing::enabled!(Level::DEBUG) {
+        debug!("{}", explanation.get_flat_string());
+      }
+    }
+    state
+      .solved_goal_explanation_and_context
+      .insert(goal.name.clone(), (explanation, goal.local_context));
+    return StepResult::Discharged(goal.name);
+  }
+  if state.timeout() {
+    // Saturation might have been cut short by the timeout,
+    // so we cannot conclude anything from the fact that this goal is not discharged;
+    // it is put back, so that the pending goals are all the cases left to prove
+    state.goals.push_back(goal);
+    return StepResult::Done(Outcome::Timeout);
+  }
+  if state.cancelled() {
+    // Likewise, saturation might have been cut short by the cancellation
+    state.goals.push_back(goal);
+    return StepResult::Done(Outcome::Unknown(UnknownReason::Cancelled));
+  }
+  if goal.node_limit_reached() {
+    // The e-graph is incomplete, so splitting it further or declaring it invalid would be unjustified
+    info!("goal {} exceeded the e-graph node limit", goal.name);
+    return state.give_up(goal, UnknownReason::NodeLimit);
+  }
+  if tracing::enabled!(Level::DEBUG) {
+    explain_goal_failure(&goal);
+  }
+  if state.config.generalize && goal.generalize() {
+    // The generalized goal has a fresh e-graph, so it needs to be saturated again
+    let name = goal.name.clone();
+    state.goals.push_back(goal);
+    return StepResult::Generalized(name);
+  }
+  trace!("goal scrutinees before split: {:?}", goal.scrutinees);
+  goal.split_ite(state);
+  trace!("goal scrutinees after split: {:?}", goal.scrutinees);
+  if goal.scrutinees.is_empty() {
+    if goal.depth_exceeded {
+      // This goal could be further split, but we have reached the maximum depth,
+      // we cannot prove or disprove the conjecture
+      return state.give_up(goal, UnknownReason::DepthExceeded);
+    }
+    // This goal has no more variables to case-split on,
+    // so this goal, and hence the whole conjecture, is invalid
+    for name in state.goals.iter().flat_map(Goal::case_names) {
+      info!("remaining case {}", name);
+    }
+    if goal.generalized {
+      // ... unless we generalized it, in which case only the generalization is invalid
+      return state.give_up(goal, UnknownReason::Generalized);
+    }
+    if goal.iteration_limit_reached() {
+      // ... or saturation was cut short, in which case more rewriting might still discharge it
+      return state.give_up(goal, UnknownReason::IterationLimit);
+    }
+    if goal.has_int_vars() {
+      // ... or it might hold for every value of its integer variables
+      return state.give_up(goal, UnknownReason::IntVars);
+    }
+    if goal.has_function_vars() {
+      // ... or of its function variables (e.g. an equation between applications of the same function)
+      return state.give_up(goal, UnknownReason::FunctionVars);
+    }
+    if goal.uses_opaque_functions() {
+      // ... or unfolding the functions we left opaque might still discharge it
+      return state.give_up(goal, UnknownReason::Opaque);
+    }
+    if goal.has_equivalences() {
+      // ... or its sides might still be equivalent, even if their constructors differ
+      return state.give_up(goal, UnknownReason::Equivalences);
+    }
+    if state.config.detect_stuck {
+      if let Some(app) = goal.stuck_application() {
+        // ... or its sides only differ because a definition is missing a case
+        info!("goal {} is stuck at {}", goal.name, app);
+        return state.give_up(goal, UnknownReason::Stuck);
+      }
+    }
+    return state.refute(goal);
+  }
+  if !goal.defer_irrelevant_scrutinees() {
+    // Splitting any of the variables left would only copy the goal
+    return state.give_up(goal, UnknownReason::Irrelevant);
+  }
+  if !goal.defer_wide_scrutinees() {
+    // Every variable left to split on has too many constructors
+    return state.give_up(goal, UnknownReason::Fanout);
+  }
+  if let Some(max) = state.config.max_total_splits {
+    // Checked before splitting, so that the subgoals of the split are never added
+    if state.num_splits >= max {
+      return state.give_up(goal, UnknownReason::SplitBudget);
+    }
+  }
+  let name = goal.name.clone();
+  let var = goal.case_split(state);
+  StepResult::Split(name, var)
+}
+
+/// Unconditional lemmas that outlive a single proof attempt:
+/// every goal proven with a library gets all of its lemmas as external lemmas.
+#[derive(Default, Clone)]
+pub struct LemmaLibrary {
+  rewrites: Vec<Rw>,
+}
+
+impl LemmaLibrary {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add a lemma, unless there already is one with the same name;
+  /// it is up to the caller to make sure the lemma is valid.
+  /// Returns whether the lemma was added.
+  pub fn add(&mut self, rewrite: Rw) -> bool {
+    if self.rewrites.iter().any(|rw| rw.name == rewrite.name) {
+      false
     } else {
-      println!("({} {})", rhs_node, child_rec_exprs);
+      self.rewrites.push(rewrite);
+      true
+    }
+  }
+
+  /// Add an equation as rewrites in every direction that does not introduce new variables
+  pub fn add_equation(&mut self, lhs: Pat, rhs: Pat) {
+    let lhs_vars = var_set(&lhs);
+    let rhs_vars = var_set(&rhs);
+    if rhs_vars.is_subset(&lhs_vars) {
+      let name = format!("{}{}={}", LIBRARY_PREFIX, lhs, rhs);
+      self.add(Rewrite::new(name, lhs.clone(), rhs.clone()).unwrap());
     }
+    if lhs_vars.is_subset(&rhs_vars) {
+      let name = format!("{}{}={}", LIBRARY_PREFIX, rhs, lhs);
+      self.add(Rewrite::new(name, rhs, lhs).unwrap());
+    }
+  }
+
+  /// Promote the induction hypotheses used in a successful proof into the library;
+  /// only the ones without premises are promoted, since those hold as standalone equations
+  /// once the whole proof has gone through.
+  /// Which hypotheses were used comes from the explanations, so the proof must have enabled them.
+  pub fn promote(&mut self, state: &mut ProofState) {
+    for (lhs, rhs) in state.used_unconditional_lemmas() {
+      self.add_equation(lhs, rhs);
+    }
+  }
+
+  pub fn rewrites(&self) -> &[Rw] {
+    &self.rewrites
+  }
+
+  pub fn len(&self) -> usize {
+    self.rewrites.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rewrites.is_empty()
   }
 }
 
-/// Top-level interface to the theorem prover.
-pub fn prove(mut goal: Goal) -> (Outcome, ProofState) {
-  let mut state = ProofState {
-    goals: vec![goal],
-    solved_goal_explanation_and_context: HashMap::default(),
-    proof: HashMap::default(),
-    start_time: Instant::now(),
-  };
-  while !state.goals.is_empty() {
-    if state.timeout() {
-      return (Outcome::Timeout, state);
-    }
-
-    // TODO: This should be info! but I don't know how to suppress all the info output from egg
-    warn!("PROOF STATE: {}", pretty_state(&state));
-    // Pop the first subgoal
-    goal = state.goals.pop().unwrap();
-    // Saturate the goal
-    goal = goal.saturate();
-    if CONFIG.save_graphs {
-      goal.save_egraph();
-    }
-    goal.check_validity();
-    if let Some(mut explanation) = goal.explanation {
-      // This goal has been discharged, proceed to the next goal
-      if CONFIG.verbose {
-        println!("{} {}", "Proved case".bright_blue(), goal.name);
-        println!("{}", explanation.get_flat_string());
-      }
-      state
-        .solved_goal_explanation_and_context
-        .insert(goal.name, (explanation, goal.local_context));
-      continue;
+impl Extend<Rw> for LemmaLibrary {
+  /// Seed the library with (user-supplied) lemmas
+  fn extend<I: IntoIterator<Item = Rw>>(&mut self, rewrites: I) {
+    for rewrite in rewrites {
+      self.add(rewrite);
     }
-    if CONFIG.verbose {
-      explain_goal_failure(&goal);
-    }
-    warn!("goal scrutinees before split: {:?}", goal.scrutinees);
-    goal.split_ite();
-    warn!("goal scrutinees after split: {:?}", goal.scrutinees);
-    if goal.scrutinees.is_empty() {
-      // This goal has no more variables to case-split on,
-      // so this goal, and hence the whole conjecture, is invalid
-      if CONFIG.verbose {
-        for remaining_goal in &state.goals {
-          println!("{} {}", "Remaining case".yellow(), remaining_goal.name);
-        }
+  }
+}
+
+/// Group the goals of a batch into the ones that may depend on each other,
+/// and order the groups so that every goal comes after the goals it may use as lemmas
+/// (see `Goal::may_use`; this is only a heuristic).
+/// Within a group, goals with fewer scrutinees and smaller equations come first;
+/// ties keep the given order.
+pub fn analyze_dependencies(goals: &[Goal]) -> Vec<Vec<usize>> {
+  let n = goals.len();
+  // reaches[i][j]: goal i may depend on goal j, directly or through other goals
+  let mut reaches: Vec<Vec<bool>> = (0..n)
+    .map(|i| {
+      (0..n)
+        .map(|j| i != j && goals[i].may_use(&goals[j]))
+        .collect()
+    })
+    .collect();
+  for k in 0..n {
+    let through_k = reaches[k].clone();
+    for row in reaches.iter_mut().filter(|row| row[k]) {
+      for (reach, via_k) in row.iter_mut().zip(through_k.iter()) {
+        *reach |= via_k;
       }
-      return (Outcome::Invalid, state);
     }
-    if goal.scrutinees.front().unwrap() == &Symbol::from(BOUND_EXCEEDED) {
-      // This goal could be further split, but we have reached the maximum depth,
-      // we cannot prove or disprove the conjecture
-      if CONFIG.verbose {
-        for remaining_goal in &state.goals {
-          println!("{} {}", "Remaining case".yellow(), remaining_goal.name);
+  }
+  let mut groups: Vec<Vec<usize>> = vec![];
+  let mut grouped = vec![false; n];
+  for i in 0..n {
+    if grouped[i] {
+      continue;
+    }
+    let group: Vec<usize> = (i..n)
+      .filter(|&j| j == i || (reaches[i][j] && reaches[j][i]))
+      .collect();
+    for &j in group.iter() {
+      grouped[j] = true;
+    }
+    groups.push(group);
+  }
+  // A group comes after another one it depends on,
+  // since it then depends on strictly more goals (counting its own)
+  let dependencies = |group: &Vec<usize>| {
+    (0..n)
+      .filter(|&j| group.contains(&j) || reaches[group[0]][j])
+      .count()
+  };
+  groups.sort_by_key(dependencies);
+  for group in groups.iter_mut() {
+    group.sort_by_key(|&i| (goals[i].scrutinees.len(), goals[i].size()));
+  }
+  groups
+}
+
+/// Prove a batch of goals that share an environment and global context.
+///
+/// Goals are attempted strictly in the given order, and the results are reported in the same order.
+/// Every goal can use the lemmas in `library`.
+/// If `reuse_lemmas` is set, every unconditional goal that is proven `Valid`
+/// is added to the library, together with the unconditional induction hypotheses used in its proof,
+/// so they are available to all goals after it in the batch.
+/// Goals with any other outcome (including `Unknown` and `Timeout`) are never used as lemmas,
+/// so a later goal that depends on them is attempted as if they had not been stated;
+/// neither are goals proven up to equivalences, nor their induction hypotheses,
+/// which only hold up to the equivalences.
+pub fn prove_all(goals: Vec<Goal>, library: &mut LemmaLibrary, reuse_lemmas: bool) -> BatchReport {
+  let mut report = BatchReport::default();
+  for mut goal in goals {
+    goal.add_lemmas(library.rewrites().iter().cloned());
+    let name = goal.name.clone();
+    let lemmas = if reuse_lemmas {
+      goal.to_lemmas()
+    } else {
+      vec![]
+    };
+    let promote = reuse_lemmas && !goal.has_equivalences();
+    let start_time = Instant::now();
+    let (outcome, mut state) = prove(goal);
+    let duration = start_time.elapsed();
+    report
+      .goals
+      .push(GoalReport::new(name, &outcome, duration, &state));
+    if outcome == Outcome::Valid && promote {
+      library.extend(lemmas);
+      library.promote(&mut state);
+    }
+  }
+  report
+}
+
+/// The results of a batch of goals (see `prove_all`), which serializes to JSON for tracking them across runs
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchReport {
+  pub goals: Vec<GoalReport>,
+}
+
+impl BatchReport {
+  /// The outcomes of the goals, in order
+  pub fn outcomes(&self) -> Vec<(String, Outcome)> {
+    self
+      .goals
+      .iter()
+      .map(|goal| (goal.name.clone(), goal.outcome.clone()))
+      .collect()
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).unwrap()
+  }
+}
+
+/// The result of a single goal of a batch
+#[derive(Debug, Clone, Serialize)]
+pub struct GoalReport {
+  pub name: String,
+  /// Serialized as its kind (e.g. "Valid")
+  #[serde(serialize_with = "serialize_outcome_kind")]
+  pub outcome: Outcome,
+  /// Why the outcome is `Unknown`, or the counterexample if it is `Invalid`
+  pub reason: Option<String>,
+  /// Time taken by the proof, in milliseconds
+  pub time_ms: f64,
+  /// Largest number of e-nodes in any subgoal's e-graph after saturation
+  pub peak_egraph_nodes: usize,
+  /// Largest number of e-classes in any subgoal's e-graph after saturation
+  pub peak_egraph_classes: usize,
+  /// Number of case splits the proof made
+  pub num_splits: usize,
+}
+
+impl GoalReport {
+  fn new(name: String, outcome: &Outcome, duration: Duration, state: &ProofState) -> Self {
+    let reason = match outcome {
+      Outcome::Unknown(reason) => Some(reason.to_string()),
+      Outcome::Invalid(cex) if !cex.assignment.is_empty() => Some(cex.to_string()),
+      _ => None,
+    };
+    GoalReport {
+      name,
+      outcome: outcome.clone(),
+      reason,
+      time_ms: duration.as_secs_f64() * 1000.,
+      peak_egraph_nodes: state.report.peak_egraph_nodes,
+      peak_egraph_classes: state.report.peak_egraph_classes,
+      num_splits: state.num_splits,
+    }
+  }
+}
+
+fn serialize_outcome_kind<S: serde::Serializer>(
+  outcome: &Outcome,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(outcome.kind())
+}
+
+/// Like `prove_all`, but attempts the goals in the order given by `analyze_dependencies`,
+/// so that lemmas are proven (and, with `reuse_lemmas`, added to the library) before the goals that need them.
+/// Since the goals in a group may depend on each other in any order,
+/// the unproven goals of a group are attempted again as long as another goal of the group gets proven.
+/// The results are reported in the given order, each from the last attempt at its goal.
+pub fn prove_all_by_dependencies(
+  goals: Vec<Goal>,
+  library: &mut LemmaLibrary,
+  reuse_lemmas: bool,
+) -> BatchReport {
+  let groups = analyze_dependencies(&goals);
+  let mut goals: Vec<Option<Goal>> = goals.into_iter().map(Some).collect();
+  let mut results: Vec<Option<GoalReport>> = goals.iter().map(|_| None).collect();
+  for group in groups {
+    let mut pending: Vec<(usize, Goal)> = group
+      .iter()
+      .map(|&i| (i, goals[i].take().unwrap()))
+      .collect();
+    loop {
+      let copies = pending.iter().map(|(_, goal)| goal.copy()).collect();
+      let batch = prove_all(copies, library, reuse_lemmas);
+      let mut progress = false;
+      let mut unproven = vec![];
+      for ((i, goal), result) in pending.into_iter().zip(batch.goals) {
+        if result.outcome == Outcome::Valid {
+          progress = true;
+        } else {
+          unproven.push((i, goal));
         }
+        results[i] = Some(result);
+      }
+      pending = unproven;
+      if !reuse_lemmas || !progress || pending.is_empty() {
+        break;
       }
-      return (Outcome::Unknown, state);
-    }
-    goal.case_split(&mut state);
-    if CONFIG.verbose {
-      println!("{}", "Case splitting and continuing...".purple());
     }
   }
-  // All goals have been discharged, so the conjecture is valid:
-  (Outcome::Valid, state)
+  BatchReport {
+    goals: results.into_iter().map(Option::unwrap).collect(),
+  }
 }
@@ -0,0 +1,8 @@
+pub mod ast;
+pub mod config;
+pub mod coq;
+pub mod egraph;
+pub mod explain;
+pub mod goal;
+pub mod parser;
+pub mod smtlib;
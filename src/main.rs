@@ -3,24 +3,30 @@ use std::fs::*;
 use std::io::{Result, Write};
 use std::time::{Duration, Instant};
 
-pub mod ast;
-pub mod config;
-pub mod egraph;
-pub mod explain;
-pub mod goal;
-pub mod parser;
-
-use config::{ARGS, CONFIG};
-use explain::explain_top;
-use goal::*;
-use parser::*;
-
-use crate::{explain::goal_name_to_filename, goal::Goal};
+use cyclegg::config::{ProverConfig, ARGS, CONFIG};
+use cyclegg::coq::to_coq;
+use cyclegg::explain::{explain_top, goal_name_to_filename};
+use cyclegg::goal::{self, *};
+use cyclegg::parser::*;
+use cyclegg::smtlib::parse_smtlib_file;
 
 fn main() -> Result<()> {
-  simple_logger::init_with_level(CONFIG.log_level).unwrap();
+  tracing_subscriber::fmt()
+    .with_max_level(CONFIG.log_level)
+    .init();
 
-  let parser_state = parse_file(&ARGS.filename).unwrap();
+  let parsed = if ARGS.filename.ends_with(".smt2") {
+    parse_smtlib_file(&ARGS.filename)
+  } else {
+    parse_file(&ARGS.filename)
+  };
+  let parser_state = match parsed {
+    Ok(state) => state,
+    Err(err) => {
+      eprintln!("{}:{}", ARGS.filename, err);
+      std::process::exit(1);
+    }
+  };
 
   let mut result_file = if CONFIG.save_results {
     Some(File::create(CONFIG.output_directory.join("results.csv"))?)
@@ -31,23 +37,49 @@ fn main() -> Result<()> {
   let mut num_differing_goals = 0;
   let mut cyclic_num_valid = 0;
   let mut non_cyclic_num_valid = 0;
+  let uncyclic_config = CONFIG.clone().with_cyclic(false);
+  let cyclic_config = CONFIG.clone().with_cyclic(true);
   for raw_goal in parser_state.raw_goals.iter() {
-    let (reductions, defns) =
-      parser_state.get_reductions_and_definitions(raw_goal, raw_goal.local_rules.clone());
-    let mut goal = Goal::top(
+    let (reductions, defns) = parser_state.get_reductions_and_definitions(
+      raw_goal,
+      raw_goal.local_rules.clone(),
+      &CONFIG.opaque_functions,
+    );
+    let reductions = reverse_definitions(reductions, &CONFIG.bidirectional_defs);
+    if let Some(prop_name) = &CONFIG.prop {
+      if &raw_goal.name != prop_name {
+        continue;
+      }
+    }
+    let mut goal = match Goal::top(
       &raw_goal.name,
-      &raw_goal.equation,
-      &raw_goal.premise,
+      &raw_goal.equations,
+      &raw_goal.premises,
       raw_goal.params.clone(),
       &parser_state.env,
       &parser_state.context,
       &reductions,
+      None,
       &defns,
-    );
-    if let Some(prop_name) = &CONFIG.prop {
-      if &goal.name != prop_name {
+      &CONFIG,
+    ) {
+      Ok(goal) => goal.with_equivalences(raw_goal.equivalences.clone()),
+      Err(err) => {
+        println!("{} {}: {}", "Skipping".red(), raw_goal.name.red(), err);
         continue;
       }
+    };
+    if ARGS.plan {
+      let plan = goal::plan(&goal);
+      print!("{}", plan);
+      println!(
+        "{} {}: {} splits, {} goals",
+        "Plan".blue(),
+        raw_goal.name.blue(),
+        plan.num_splits(),
+        plan.num_leaves()
+      );
+      continue;
     }
     num_goals_attempted += 1;
     println!(
@@ -58,15 +90,21 @@ fn main() -> Result<()> {
     );
 
     let (result, duration) = if ARGS.do_uncyclic() {
-      prove_goal(&goal, false)?
+      prove_goal(&goal, &uncyclic_config)?
     } else {
-      (Outcome::Unknown, Duration::from_secs(0))
+      (
+        Outcome::Unknown(UnknownReason::NotAttempted),
+        Duration::from_secs(0),
+      )
     };
     let (result_cyclic, duration_cyclic) = if ARGS.do_cyclic() {
       goal.name = format!("{}_cyclic", goal.name);
-      prove_goal(&goal, true)?
+      prove_goal(&goal, &cyclic_config)?
     } else {
-      (Outcome::Unknown, Duration::from_secs(0))
+      (
+        Outcome::Unknown(UnknownReason::NotAttempted),
+        Duration::from_secs(0),
+      )
     };
 
     if CONFIG.verbose {
@@ -87,7 +125,7 @@ fn main() -> Result<()> {
         result_cyclic,
         duration_cyclic.as_millis()
       );
-      if result_cyclic != result {
+      if result_cyclic.kind() != result.kind() {
         num_differing_goals += 1;
         println!("{}", "Differing results".red());
       }
@@ -114,10 +152,10 @@ fn main() -> Result<()> {
     }
     if let Some(ref mut file) = result_file {
       let line = format!(
-        "{},{:?},{:?},{},{}\n",
+        "{},{},{},{},{}\n",
         raw_goal.name,
-        result_cyclic,
-        result,
+        result_cyclic.kind(),
+        result.kind(),
         // Convert to ms
         1000. * duration_cyclic.as_secs_f32(),
         1000. * duration.as_secs_f32(),
@@ -140,19 +178,51 @@ fn main() -> Result<()> {
 
 /// Prove a goal using either cyclic or uncyclic mode;
 /// record the duration and emit the proof.
-fn prove_goal(goal: &Goal, cyclic: bool) -> Result<(Outcome, Duration)> {
-  CONFIG.set_cyclic(cyclic);
+fn prove_goal<'a>(goal: &Goal<'a>, config: &'a ProverConfig) -> Result<(Outcome, Duration)> {
+  let mut goal_copy = goal.copy();
+  goal_copy.config = config;
   let start_time = Instant::now();
-  let (result, mut proof_state) = goal::prove(goal.copy());
+  let (result, mut proof_state) = if config.parallel {
+    goal::prove_parallel(goal_copy)
+  } else {
+    goal::prove(goal_copy)
+  };
   let duration = start_time.elapsed();
-  if CONFIG.emit_proofs {
+  if CONFIG.verbose {
+    println!("{}", proof_state.report);
+  }
+  for (name, outcome) in &proof_state.failures {
+    println!("  failed case {}: {}", name, outcome);
+  }
+  if CONFIG.record_proof {
+    print!("{}", proof_state.proof_tree(&goal.name));
+  }
+  if CONFIG.save_proof_script {
+    let script = proof_state.proof_tree(&goal.name).to_script();
+    let filename = format!("{}.script", goal_name_to_filename(&goal.name));
+    write(CONFIG.output_directory.join(filename), script)?;
+  }
+  if CONFIG.save_coq_proof && !goal.has_equivalences() {
     if let Outcome::Valid = result {
+      let coq = to_coq(&proof_state.proof_tree(&goal.name), goal);
+      let filename = format!("{}.v", goal_name_to_filename(&goal.name));
+      write(CONFIG.output_directory.join(filename), coq)?;
+    }
+  }
+  if CONFIG.emit_proofs {
+    if goal.eqs.len() > 1 {
+      // The explanation of a discharged goal only covers its first equation
+      tracing::warn!("cannot emit a proof of conjunction {}", goal.name);
+    } else if goal.has_equivalences() {
+      // The emitted proof would claim an equality rather than an equivalence
+      tracing::warn!("cannot emit a proof up to equivalences of {}", goal.name);
+    } else if let Outcome::Valid = result {
       let filename = goal_name_to_filename(&goal.name);
       let explanation = explain_top(
         &filename,
         &goal.name,
         &mut proof_state,
-        &goal.eq,
+        &goal.eqs[0],
         &goal.params,
         &goal.local_context,
         goal.defns,
@@ -1,9 +1,12 @@
 use egg::*;
 use std::char;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::read_to_string;
 use symbolic_expressions::*;
 
 use crate::ast::*;
+use crate::egraph::ConditionalSearcher;
 use crate::goal::*;
 
 fn make_rewrite_for_defn(name: &str, args: &Sexp, value: &Sexp) -> Rw {
@@ -25,6 +28,132 @@ fn make_rewrite_for_defn(name: &str, args: &Sexp, value: &Sexp) -> Rw {
   Rewrite::new(lhs, searcher, applier).unwrap()
 }
 
+/// Names of the rules generated for every AC function (see `ac_rewrites`)
+const AC_RULES: [&str; 3] = ["commutative", "associative", "associative-reversed"];
+
+fn ac_rule_name(name: &Symbol, rule: &str) -> String {
+  format!("{}-{}", name, rule)
+}
+
+/// Rewrites that reorder and regroup applications of an associative-commutative function
+pub fn ac_rewrites(name: &Symbol) -> Vec<Rw> {
+  let sides = [
+    (format!("({} ?x ?y)", name), format!("({} ?y ?x)", name)),
+    (
+      format!("({f} ({f} ?x ?y) ?z)", f = name),
+      format!("({f} ?x ({f} ?y ?z))", f = name),
+    ),
+    (
+      format!("({f} ?x ({f} ?y ?z))", f = name),
+      format!("({f} ({f} ?x ?y) ?z)", f = name),
+    ),
+  ];
+  AC_RULES
+    .iter()
+    .zip(sides)
+    .map(|(rule, (lhs, rhs))| {
+      let searcher: Pat = lhs.parse().unwrap();
+      let applier: Pat = rhs.parse().unwrap();
+      Rewrite::new(ac_rule_name(name, rule), searcher, applier).unwrap()
+    })
+    .collect()
+}
+
+/// Built-in simplification rules for the Boolean connectives
+pub fn boolean_rewrites() -> Vec<Rw> {
+  let (t, f) = (&*TRUE, &*FALSE);
+  let (and, or, not) = (&*AND, &*OR, &*NOT);
+  let rules = [
+    (format!("({} {})", not, t), f.to_string()),
+    (format!("({} {})", not, f), t.to_string()),
+    (format!("({} ({} ?x))", not, not), "?x".to_string()),
+    (format!("({} {} ?x)", and, t), "?x".to_string()),
+    (format!("({} ?x {})", and, t), "?x".to_string()),
+    (format!("({} {} ?x)", and, f), f.to_string()),
+    (format!("({} ?x {})", and, f), f.to_string()),
+    (format!("({} {} ?x)", or, t), t.to_string()),
+    (format!("({} ?x {})", or, t), t.to_string()),
+    (format!("({} {} ?x)", or, f), "?x".to_string()),
+    (format!("({} ?x {})", or, f), "?x".to_string()),
+    (
+      format!("({} ?x ?y)", *IMPLIES),
+      format!("({} ({} ?x) ?y)", or, not),
+    ),
+    // De Morgan's laws push negations towards the atoms
+    (
+      format!("({} ({} ?x ?y))", not, and),
+      format!("({} ({} ?x) ({} ?y))", or, not, not),
+    ),
+    (
+      format!("({} ({} ?x ?y))", not, or),
+      format!("({} ({} ?x) ({} ?y))", and, not, not),
+    ),
+  ];
+  rules
+    .iter()
+    .map(|(lhs, rhs)| {
+      let searcher: Pattern<SymbolLang> = lhs.parse().unwrap();
+      let applier: Pattern<SymbolLang> = rhs.parse().unwrap();
+      // The prefix keeps the names distinct from user definitions of the same connectives
+      Rewrite::new(format!("bool-{}", lhs), searcher, applier).unwrap()
+    })
+    .collect()
+}
+
+/// Built-in algebraic rules for the integer operations;
+/// operations on literals are evaluated by `CanonicalFormAnalysis` instead,
+/// so the rules do not apply to e-classes that are already literals
+/// (there, associativity would only keep combining literals into new ones).
+pub fn integer_rewrites() -> Vec<Rw> {
+  let (t, f) = (&*TRUE, &*FALSE);
+  let (add, sub, mul) = (INT_ADD, INT_SUB, INT_MUL);
+  let rules = [
+    (format!("({} ?x 0)", add), "?x".to_string()),
+    (format!("({} ?x ?y)", add), format!("({} ?y ?x)", add)),
+    (
+      format!("({} ({} ?x ?y) ?z)", add, add),
+      format!("({} ?x ({} ?y ?z))", add, add),
+    ),
+    (
+      format!("({} ?x ({} ?y ?z))", add, add),
+      format!("({} ({} ?x ?y) ?z)", add, add),
+    ),
+    (format!("({} ?x 1)", mul), "?x".to_string()),
+    (format!("({} ?x 0)", mul), "0".to_string()),
+    (format!("({} ?x ?y)", mul), format!("({} ?y ?x)", mul)),
+    (
+      format!("({} ({} ?x ?y) ?z)", mul, mul),
+      format!("({} ?x ({} ?y ?z))", mul, mul),
+    ),
+    (
+      format!("({} ?x ({} ?y ?z))", mul, add),
+      format!("({} ({} ?x ?y) ({} ?x ?z))", add, mul, mul),
+    ),
+    // Subtraction is addition of the negation, which cancels out
+    (
+      format!("({} ?x ?y)", sub),
+      format!("({} ?x ({} -1 ?y))", add, mul),
+    ),
+    (format!("({} ?x ({} -1 ?x))", add, mul), "0".to_string()),
+    // Comparisons are normalized to < and <=
+    (format!("({} ?x ?y)", INT_GT), format!("({} ?y ?x)", INT_LT)),
+    (format!("({} ?x ?y)", INT_GE), format!("({} ?y ?x)", INT_LE)),
+    (format!("({} ?x ?x)", INT_LT), f.to_string()),
+    (format!("({} ?x ?x)", INT_LE), t.to_string()),
+  ];
+  rules
+    .iter()
+    .map(|(lhs, rhs)| {
+      let searcher = ConditionalSearcher {
+        searcher: lhs.parse::<Pattern<SymbolLang>>().unwrap(),
+        condition: NotIntLiteral,
+      };
+      let applier: Pattern<SymbolLang> = rhs.parse().unwrap();
+      Rewrite::new(format!("int-{}", lhs), searcher, applier).unwrap()
+    })
+    .collect()
+}
+
 pub struct RawEquation {
   pub lhs: Sexp,
   pub rhs: Sexp,
@@ -32,10 +161,14 @@ pub struct RawEquation {
 
 pub struct RawGoal {
   pub name: String,
-  pub equation: RawEquation,
-  pub premise: Option<RawEquation>,
+  /// Equations that are proven together (usually just one)
+  pub equations: Vec<RawEquation>,
+  /// Hypotheses under which the equations should hold
+  pub premises: Vec<RawEquation>,
   pub params: Vec<(Symbol, Type)>,
   pub local_rules: Vec<Rw>,
+  /// Rewrites generating the equivalence the goal is proven up to (see `Goal::with_equivalences`)
+  pub equivalences: Vec<Rw>,
 }
 
 #[derive(Default)]
@@ -49,8 +182,13 @@ pub struct ParserState {
 
 impl ParserState {
   /// Return all function definitions used in exprs,
-  /// including the functions transitively used in those definitions.
-  fn used_names_and_definitions(&self, exprs: &Vec<Expr>) -> (HashSet<Symbol>, Vec<Rw>) {
+  /// including the functions transitively used in those definitions;
+  /// the opaque functions count as used, but their definitions are not unfolded.
+  fn used_names_and_definitions(
+    &self,
+    exprs: &Vec<Expr>,
+    opaque: &HashSet<Symbol>,
+  ) -> (HashSet<Symbol>, Vec<Rw>) {
     let mut used_names = HashSet::new();
     let mut used_defs = vec![];
     let mut worklist = vec![];
@@ -66,6 +204,15 @@ impl ParserState {
     while let Some(s) = worklist.pop() {
       let def_rules = self.definition(&s);
       for rule in def_rules {
+        // An opaque function can still be applied partially, and reordered if it is AC
+        if opaque.contains(&s)
+          && rule.name.as_str() != ParserState::part_app_rule(&s)
+          && !AC_RULES
+            .iter()
+            .any(|ac_rule| rule.name.as_str() == ac_rule_name(&s, ac_rule))
+        {
+          continue;
+        }
         used_defs.push(rule.clone());
         let rhs = rule.applier.get_pattern_ast().unwrap();
         self.add_functions(rhs, &mut used_names, &mut worklist);
@@ -84,7 +231,7 @@ impl ParserState {
     for node_or_var in e.as_ref() {
       if let ENodeOrVar::ENode(node) = node_or_var {
         let s = node.op;
-        if self.context.contains_key(&s)
+        if (self.context.contains_key(&s) || is_builtin(&s))
           && !is_constructor(&s.to_string())
           && !used_names.contains(&s)
         {
@@ -150,23 +297,131 @@ impl ParserState {
     }
   }
 
+  /// Check that every constructor has a type whose arguments only mention declared datatypes
+  /// (or type variables), since case splits give the arguments of a constructor those types;
+  /// an error names the declaration to blame: the constructor's type, or its datatype if it has none
+  fn check_constructors(&self) -> Result<(), (Symbol, String)> {
+    let mut datatypes: Vec<_> = self.env.iter().collect();
+    datatypes.sort_by_key(|(datatype, _)| datatype.as_str());
+    for (datatype, (_, cons)) in datatypes {
+      for con in cons {
+        let ty = self.context.get(con).ok_or_else(|| {
+          (
+            *datatype,
+            format!("constructor {} of {} has no type", con, datatype),
+          )
+        })?;
+        for arg in ty.args_ret().0 {
+          check_type(&arg, &self.env)
+            .map_err(|err| (*con, format!("constructor {}: {}", con, err)))?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Rewrites that reduce a match on each constructor of datatype to the corresponding arm,
+  /// that is: (match (con_i ?x0 ... ?xk) ?a0 ... ?an) => ($ ... ($ ?ai ?x0) ... ?xk).
+  /// Declare the built-in pair type `(Pair a b)`, with its constructor `Pair`,
+  /// if the problem uses it without declaring it
+  /// (problems that do not use it are left alone, since every datatype ends up in the emitted proofs)
+  fn add_builtin_pair(&mut self) {
+    let pair = Symbol::from(&*PAIR);
+    if self.env.contains_key(&pair) || self.context.contains_key(&pair) {
+      return;
+    }
+    let goal_sexps = self.raw_goals.iter().flat_map(|goal| {
+      let eqs = goal.equations.iter().chain(goal.premises.iter());
+      let types = goal.params.iter().map(|(_, ty)| &ty.repr);
+      eqs.flat_map(|eq| [&eq.lhs, &eq.rhs]).chain(types)
+    });
+    let defn_sexps = self
+      .defns
+      .values()
+      .flatten()
+      .flat_map(|(args, value)| [args, value]);
+    let used = self
+      .context
+      .values()
+      .map(|ty| &ty.repr)
+      .chain(goal_sexps)
+      .chain(defn_sexps)
+      .any(|sexp| mentions(sexp, &PAIR));
+    if !used {
+      return;
+    }
+    let type_vars = vec![mangle_name("a"), mangle_name("b")];
+    let pair_type = Type::new(
+      parser::parse_str(&format!(
+        "(-> ({a} {b}) ({pair} {a} {b}))",
+        a = type_vars[0],
+        b = type_vars[1],
+        pair = *PAIR
+      ))
+      .unwrap(),
+    );
+    if let Some(rw) = ParserState::partial_application(&pair, &pair_type) {
+      self.rules.push(rw);
+    }
+    self.context.insert(pair, pair_type);
+    self.env.insert(pair, (type_vars, vec![pair]));
+  }
+
+  fn match_reductions(&self, datatype: &Symbol) -> Vec<Rw> {
+    let (_, cons) = &self.env[datatype];
+    let arms = (0..cons.len())
+      .map(|i| format!("?a{}", i))
+      .collect::<Vec<String>>()
+      .join(" ");
+    let mut res = vec![];
+    for (i, con) in cons.iter().enumerate() {
+      // Skip constructors whose type was never declared, since we don't know their arity
+      if let Some(con_ty) = self.context.get(con) {
+        let (args, _) = con_ty.args_ret();
+        let fields: Vec<String> = (0..args.len()).map(|j| format!("?x{}", j)).collect();
+        let scrutinee = if fields.is_empty() {
+          con.to_string()
+        } else {
+          format!("({} {})", con, fields.join(" "))
+        };
+        let lhs: Pat = format!("({} {} {})", *MATCH, scrutinee, arms)
+          .parse()
+          .unwrap();
+        let mut rhs_str = format!("?a{}", i);
+        for field in fields {
+          rhs_str = format!("({} {} {})", APPLY, rhs_str, field);
+        }
+        let rhs: Pat = rhs_str.parse().unwrap();
+        res.push(Rewrite::new(format!("match-{}", con), lhs, rhs).unwrap());
+      }
+    }
+    res
+  }
+
   /// This is done after parsing because that way the order we parse does not
   /// affect whether a goal has all definitions in scope.
+  /// The definitions of opaque functions are left out of the reductions
+  /// (but not out of the definitions); the local rules are always kept.
   pub fn get_reductions_and_definitions(
     &self,
     goal: &RawGoal,
     local_rules: Vec<Rw>,
+    opaque: &HashSet<Symbol>,
   ) -> (Vec<Rw>, Defns) {
-    let lhs: Expr = goal.equation.lhs.to_string().parse().unwrap();
-    let rhs: Expr = goal.equation.rhs.to_string().parse().unwrap();
-    let mut roots = vec![lhs, rhs];
-    if let Some(premise) = &goal.premise {
+    let mut roots = vec![];
+    for equation in goal.equations.iter() {
+      let lhs: Expr = equation.lhs.to_string().parse().unwrap();
+      let rhs: Expr = equation.rhs.to_string().parse().unwrap();
+      roots.push(lhs);
+      roots.push(rhs);
+    }
+    for premise in goal.premises.iter() {
       let premise_lhs: Expr = premise.lhs.to_string().parse().unwrap();
       let premise_rhs: Expr = premise.rhs.to_string().parse().unwrap();
       roots.push(premise_lhs);
       roots.push(premise_rhs);
     }
-    let (names, mut rules) = self.used_names_and_definitions(&roots);
+    let (names, mut rules) = self.used_names_and_definitions(&roots, opaque);
     let filtered_defns = self
       .defns
       .iter()
@@ -183,21 +438,94 @@ impl ParserState {
   }
 }
 
-fn validate_identifier(identifier: &str) {
+/// An error in a problem file, located at the declaration (or parenthesis) that caused it
+#[derive(Debug)]
+pub struct ParseError {
+  pub line: usize,
+  pub column: usize,
+  pub message: String,
+}
+
+impl Display for ParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}: {}", self.line, self.column, self.message)
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+fn validate_identifier(identifier: &str) -> Result<(), SexpError> {
   // Important: we disallow the use of underscore in our identifiers so that
   // autogenerated names like for guards or variable splits will not conflict
   // with variable names.
-  assert!(!identifier.contains('_'));
+  if identifier.contains('_') {
+    return Err(SexpError::Other(format!(
+      "identifier {} contains an underscore",
+      identifier
+    )));
+  }
+  Ok(())
 }
 
-fn validate_datatype(datatype: &str) {
-  validate_identifier(datatype);
-  assert!(datatype.starts_with(char::is_uppercase));
+fn validate_datatype(datatype: &str) -> Result<(), SexpError> {
+  validate_identifier(datatype)?;
+  if !datatype.starts_with(char::is_uppercase) {
+    return Err(SexpError::Other(format!(
+      "datatype or constructor {} must start with an uppercase letter",
+      datatype
+    )));
+  }
+  Ok(())
+}
+
+fn validate_variable(variable: &str) -> Result<(), SexpError> {
+  validate_identifier(variable)?;
+  if !variable.starts_with(char::is_lowercase) {
+    return Err(SexpError::Other(format!(
+      "variable or function {} must start with a lowercase letter",
+      variable
+    )));
+  }
+  Ok(())
 }
 
-fn validate_variable(variable: &str) {
-  validate_identifier(variable);
-  assert!(variable.starts_with(char::is_lowercase));
+/// Line and column (both starting at 1) of every top-level declaration in src,
+/// or an error if the parentheses in src are unbalanced.
+pub(crate) fn declaration_positions(src: &str) -> Result<Vec<(usize, usize)>, ParseError> {
+  let mut positions = vec![];
+  // Position of the innermost unclosed parenthesis for every depth
+  let mut open = vec![];
+  for (i, line) in src.lines().enumerate() {
+    for (j, c) in line.chars().enumerate() {
+      let pos = (i + 1, j + 1);
+      match c {
+        '(' => {
+          // The problem itself is the list at depth 0, so declarations are at depth 1
+          if open.len() == 1 {
+            positions.push(pos);
+          }
+          open.push(pos);
+        }
+        // Note that the guard pops the matching parenthesis when there is one
+        ')' if open.pop().is_none() => {
+          return Err(ParseError {
+            line: pos.0,
+            column: pos.1,
+            message: "unexpected )".to_string(),
+          });
+        }
+        _ => {}
+      }
+    }
+  }
+  match open.pop() {
+    Some((line, column)) => Err(ParseError {
+      line,
+      column,
+      message: "unclosed (".to_string(),
+    }),
+    None => Ok(positions),
+  }
 }
 
 /// Parsing the file returns the whole parser state.
@@ -212,187 +540,370 @@ fn validate_variable(variable: &str) {
 ///
 /// This comes with the minor disadvantage of having to create goals in main.rs from the raw_goals,
 /// but most of the work is done ahead of time.
-pub fn parse_file(filename: &str) -> Result<ParserState, SexpError> {
+pub fn parse_file(filename: &str) -> Result<ParserState, ParseError> {
+  let src = read_file(filename)?;
+  parse_problem(&src)
+}
+
+/// The contents of a problem file, with a failure to read it reported like a parse error
+pub(crate) fn read_file(filename: &str) -> Result<String, ParseError> {
+  read_to_string(filename).map_err(|e| ParseError {
+    line: 1,
+    column: 1,
+    message: e.to_string(),
+  })
+}
+
+/// Parse a whole problem (datatypes, function signatures and definitions, and goals) from a string;
+/// see `parse_file`.
+pub fn parse_problem(src: &str) -> Result<ParserState, ParseError> {
+  let positions = declaration_positions(src)?;
+  let sexpr = parser::parse_str(src).map_err(|e| ParseError {
+    line: 1,
+    column: 1,
+    message: e.to_string(),
+  })?;
+  let decls = sexpr.list().map_err(|_| ParseError {
+    line: 1,
+    column: 1,
+    message: "expected a list of declarations".to_string(),
+  })?;
+  parse_decls(decls.iter().zip(positions))
+}
+
+/// Parse top-level declarations, each paired with its position in the source
+pub(crate) fn parse_decls<'a, I>(decls: I) -> Result<ParserState, ParseError>
+where
+  I: IntoIterator<Item = (&'a Sexp, (usize, usize))>,
+{
   let mut state = ParserState::default();
-  let sexpr = parser::parse_file(filename).unwrap();
-
-  for decl in sexpr.list()? {
-    let decl_kind = decl.list()?[0].string()?.as_str();
-    match decl_kind {
-      "data" => {
-        // This is a datatype declaration: parse name, type variables, and constructor list:
-        let name = decl.list()?[1].string()?;
-        let mut cons_index = 2;
-        // We'll allow no type variables to be given, in which case the second
-        // argument must be the constructor list.
-        let mangled_type_var_names = if decl.list()?.len() == 3 {
-          vec![]
-        } else {
-          // The length should be 4.
-          assert_eq!(decl.list()?.len(), 4);
-          cons_index += 1;
-          let type_vars = decl.list()?[2].list()?;
-          type_vars
-            .iter()
-            .map(|x| {
-              let var_name = x.string()?;
-              validate_variable(var_name);
-              // FIXME: We should really only mangle names in the emitted
-              // explanations. If this is fixed, please change the config so
-              // that it does not implicitly adjust the maximum split depth to
-              // account for the additional underscore.
-              Ok(mangle_name(var_name))
-            })
-            .collect::<Result<Vec<String>, SexpError>>()?
-        };
-        let cons = decl.list()?[cons_index].list()?;
-        let mangled_cons_symbs = cons
+  // Where the datatypes and typed names are declared, for the errors found once all are known
+  let mut positions = HashMap::new();
+  for (decl, (line, column)) in decls {
+    parse_decl(&mut state, decl).map_err(|e| ParseError {
+      line,
+      column,
+      message: match e {
+        SexpError::Other(msg) => msg,
+        e => e.to_string(),
+      },
+    })?;
+    if let Some(name) = declared_name(decl) {
+      positions.insert(name, (line, column));
+    }
+  }
+  state.add_builtin_pair();
+  state.check_constructors().map_err(|(name, message)| {
+    let (line, column) = positions.get(&name).copied().unwrap_or((1, 1));
+    ParseError {
+      line,
+      column,
+      message,
+    }
+  })?;
+  // Now that all constructors are known, add the rules for matching on them
+  let datatypes: Vec<Symbol> = state.env.keys().cloned().collect();
+  for datatype in datatypes {
+    let rules = state.match_reductions(&datatype);
+    state.rules.extend(rules);
+  }
+  state.rules.extend(boolean_rewrites());
+  state.rules.extend(integer_rewrites());
+  Ok(state)
+}
+
+/// The (mangled) name declared by a datatype declaration or a type binding
+fn declared_name(decl: &Sexp) -> Option<Symbol> {
+  match decl.list().ok()?.as_slice() {
+    [kind, name, ..] if matches!(kind.string().ok()?.as_str(), "data" | "::") => {
+      Some(Symbol::from(&mangle_name(name.string().ok()?)))
+    }
+    _ => None,
+  }
+}
+
+/// Parse a single top-level declaration into state
+fn parse_decl(state: &mut ParserState, decl: &Sexp) -> Result<(), SexpError> {
+  let decl_kind = decl.list()?[0].string()?.as_str();
+  match decl_kind {
+    "data" => {
+      // This is a datatype declaration: parse name, type variables, and constructor list:
+      let name = decl.list()?[1].string()?;
+      let mut cons_index = 2;
+      // We'll allow no type variables to be given, in which case the second
+      // argument must be the constructor list.
+      let mangled_type_var_names = if decl.list()?.len() == 3 {
+        vec![]
+      } else {
+        // The length should be 4.
+        if decl.list()?.len() != 4 {
+          return Err(SexpError::Other(format!(
+            "malformed datatype declaration: {}",
+            decl
+          )));
+        }
+        cons_index += 1;
+        let type_vars = decl.list()?[2].list()?;
+        type_vars
           .iter()
           .map(|x| {
-            let cons_name = x.string()?;
-            validate_datatype(cons_name);
-            Ok(Symbol::from(&mangle_name(cons_name)))
+            let var_name = x.string()?;
+            validate_variable(var_name)?;
+            // FIXME: We should really only mangle names in the emitted
+            // explanations. If this is fixed, please change the config so
+            // that it does not implicitly adjust the maximum split depth to
+            // account for the additional underscore.
+            Ok(mangle_name(var_name))
           })
-          .collect::<Result<Vec<Symbol>, SexpError>>()?;
-        validate_datatype(name);
-        state.env.insert(
-          Symbol::from(&mangle_name(name)),
-          (mangled_type_var_names, mangled_cons_symbs),
-        );
+          .collect::<Result<Vec<String>, SexpError>>()?
+      };
+      let cons = decl.list()?[cons_index].list()?;
+      let mangled_cons_symbs = cons
+        .iter()
+        .map(|x| {
+          let cons_name = x.string()?;
+          validate_datatype(cons_name)?;
+          Ok(Symbol::from(&mangle_name(cons_name)))
+        })
+        .collect::<Result<Vec<Symbol>, SexpError>>()?;
+      validate_datatype(name)?;
+      state.env.insert(
+        Symbol::from(&mangle_name(name)),
+        (mangled_type_var_names, mangled_cons_symbs),
+      );
+    }
+    "::" => {
+      // This is a type binding: parse name and type:
+      let name = decl.list()?[1].string()?;
+      // This could be either a function or a datatype.
+      validate_identifier(name)?;
+      let mangled_name = Symbol::from(&mangle_name(name));
+      // Mangle each of the elements in the sexp.
+      let mangled_type = Type::new(mangle_sexp(&decl.list()?[2]));
+      if let Some(rw) = ParserState::partial_application(&mangled_name, &mangled_type) {
+        state.rules.push(rw);
       }
-      "::" => {
-        // This is a type binding: parse name and type:
-        let name = decl.list()?[1].string()?;
-        // This could be either a function or a datatype.
-        validate_identifier(name);
+      state.context.insert(mangled_name, mangled_type);
+    }
+    "symmetric" => {
+      // This is a symmetry annotation: the constructors' arguments may be permuted freely.
+      // Each constructor must already have a type, and all of its arguments must share it.
+      for con in decl.list()?[1..].iter() {
+        let name = con.string()?;
         let mangled_name = Symbol::from(&mangle_name(name));
-        // Mangle each of the elements in the sexp.
-        let mangled_type = Type::new(mangle_sexp(&decl.list()?[2]));
-        if let Some(rw) = ParserState::partial_application(&mangled_name, &mangled_type) {
-          state.rules.push(rw);
+        let (args, _) = match state.context.get(&mangled_name) {
+          Some(ty) if is_constructor(name) => ty.args_ret(),
+          _ => {
+            return Err(SexpError::Other(format!(
+              "symmetric: {} is not a declared constructor",
+              name
+            )))
+          }
+        };
+        if args.len() < 2 || args.iter().any(|arg| *arg != args[0]) {
+          return Err(SexpError::Other(format!(
+            "symmetric: the arguments of {} must all have the same type",
+            name
+          )));
         }
-        state.context.insert(mangled_name, mangled_type);
+        state.env.symmetric.insert(mangled_name);
       }
-      "let" => {
-        // This is a definition
-        let name = decl.list()?[1].string()?;
-        validate_variable(name);
-        let mangled_name = mangle_name(name);
-        // Extract the args and value
-        let mangled_args = mangle_sexp(&decl.list()?[2]);
-        let mangled_value = mangle_sexp(&decl.list()?[3]);
-        // Add to the rewrites
-        state.rules.push(make_rewrite_for_defn(
-          &mangled_name,
-          &mangled_args,
-          &mangled_value,
-        ));
-        // Add to the hashmap
-        if let Some(cases) = state.defns.get_mut(&mangled_name) {
-          cases.push((mangled_args, mangled_value));
-        } else {
-          state
-            .defns
-            .insert(mangled_name, vec![(mangled_args, mangled_value)]);
+    }
+    "ac" => {
+      // This is an AC annotation: the functions are associative and commutative.
+      // Each function must already have a type, with two arguments of its result type.
+      for f in decl.list()?[1..].iter() {
+        let name = f.string()?;
+        let mangled_name = Symbol::from(&mangle_name(name));
+        let (args, ret) = match state.context.get(&mangled_name) {
+          Some(ty) if !is_constructor(name) => ty.args_ret(),
+          _ => {
+            return Err(SexpError::Other(format!(
+              "ac: {} is not a declared function",
+              name
+            )))
+          }
+        };
+        if args.len() != 2 || args.iter().any(|arg| *arg != ret) {
+          return Err(SexpError::Other(format!(
+            "ac: {} must take two arguments of its result type",
+            name
+          )));
+        }
+        if state.env.ac.insert(mangled_name) {
+          state.rules.extend(ac_rewrites(&mangled_name));
         }
       }
-      "===" | "==>" => {
-        // This is a goal: parse name, parameter names, parameter types;
-        // if the goal is conditional, parse the lhs and rhs of the premise;
-        // then parse the lhs and rhs of the goal;
-        // finally, if there's more elements, parse a list of lemmas.
-        //
-        // Goal names are allowed to have underscores so we won't validate them. The
-        // worst this can do is have a goal wrongly match a variable name, which should
-        // hopefully never happen.
-        let name = decl.list()?[1].string()?.to_string();
-        let param_name_list = decl.list()?[2].list()?;
-        let mangled_param_names = param_name_list
-          .iter()
-          .map(|x| {
-            let var_name = x.string()?;
-            validate_variable(var_name);
-            Ok(Symbol::from(&mangle_name(var_name)))
-          })
-          .collect::<Result<Vec<Symbol>, SexpError>>()?;
-        let param_type_list = decl.list()?[3].list()?;
-        let mangled_param_types = param_type_list.iter().map(|x| Type::new(mangle_sexp(x)));
-        let params = mangled_param_names
-          .into_iter()
-          .zip(mangled_param_types)
-          .collect();
-
-        let mut index = 4;
-        let premise = if decl_kind == "==>" {
-          let lhs: Sexp = mangle_sexp(&decl.list()?[index]);
-          let rhs: Sexp = mangle_sexp(&decl.list()?[index + 1]);
-          index += 2;
-          Some(RawEquation { lhs, rhs })
-        } else {
-          None
-        };
+    }
+    "let" => {
+      // This is a definition
+      let name = decl.list()?[1].string()?;
+      validate_variable(name)?;
+      let mangled_name = mangle_name(name);
+      // Extract the args and value
+      let mangled_args = mangle_sexp(&decl.list()?[2]);
+      let mangled_value = expand_lets(&mangle_sexp(&decl.list()?[3]))
+        .map_err(|form| SexpError::Other(format!("invalid let {}", form)))?;
+      // Add to the rewrites
+      state.rules.push(make_rewrite_for_defn(
+        &mangled_name,
+        &mangled_args,
+        &mangled_value,
+      ));
+      // Add to the hashmap
+      if let Some(cases) = state.defns.get_mut(&mangled_name) {
+        cases.push((mangled_args, mangled_value));
+      } else {
+        state
+          .defns
+          .insert(mangled_name, vec![(mangled_args, mangled_value)]);
+      }
+    }
+    "===" | "==>" | "&&&" => {
+      // This is a goal: parse name, parameter names, parameter types;
+      // if the goal is conditional, parse the lhs and rhs of the premise;
+      // then parse the lhs and rhs of the goal
+      // (or, if the goal is a conjunction, a list of lhs-rhs pairs);
+      // finally, if there's more elements, parse a list of lemmas
+      // (and equivalences `~`, up to which the goal is proven).
+      //
+      // Goal names are allowed to have underscores so we won't validate them. The
+      // worst this can do is have a goal wrongly match a variable name, which should
+      // hopefully never happen.
+      let name = decl.list()?[1].string()?.to_string();
+      let param_name_list = decl.list()?[2].list()?;
+      let mangled_param_names = param_name_list
+        .iter()
+        .map(|x| {
+          let var_name = x.string()?;
+          validate_variable(var_name)?;
+          Ok(Symbol::from(&mangle_name(var_name)))
+        })
+        .collect::<Result<Vec<Symbol>, SexpError>>()?;
+      let param_type_list = decl.list()?[3].list()?;
+      let mangled_param_types = param_type_list.iter().map(|x| Type::new(mangle_sexp(x)));
+      let params = mangled_param_names
+        .into_iter()
+        .zip(mangled_param_types)
+        .collect();
 
+      let mut index = 4;
+      let mut premises = vec![];
+      if decl_kind == "==>" {
         let lhs: Sexp = mangle_sexp(&decl.list()?[index]);
         let rhs: Sexp = mangle_sexp(&decl.list()?[index + 1]);
         index += 2;
-        let equation = RawEquation { lhs, rhs };
-
-        let mut local_rules = vec![];
-        // If there's more to parse, these must be lemmas.
-        if decl.list()?.len() > index {
-          // Lemmas we are using to aid this proof
-          for rule_sexp in decl.list()?[index].list()? {
-            let lhs = mangle_sexp(&rule_sexp.list()?[1]);
-            let rhs = mangle_sexp(&rule_sexp.list()?[2]);
-            let searcher: Pattern<SymbolLang> = lhs.to_string().parse().unwrap();
-            let applier: Pattern<SymbolLang> = rhs.to_string().parse().unwrap();
-            // check if this is a bidirectional rewrite
-            match rule_sexp.list()?[0].string()?.as_str() {
-              "=>" => {
-                let rw = Rewrite::new(
-                  format!("hyp-lemma-{}", lhs),
-                  searcher.clone(),
-                  applier.clone(),
-                )
-                .unwrap();
-                local_rules.push(rw);
-                // println!("adding rewrite rule: {} => {}", lhs, rhs);
-              }
-              "<=>" => {
+        premises.push(RawEquation { lhs, rhs });
+      }
+
+      let mut equations = vec![];
+      if decl_kind == "&&&" {
+        // All the equations are proven together, so each can be used in the induction hypothesis of the others
+        while index < decl.list()?.len() {
+          let sides = decl.list()?[index].list()?;
+          if sides.len() != 2 {
+            return Err(SexpError::Other(format!(
+              "expected a pair of sides in conjunction: {}",
+              decl.list()?[index]
+            )));
+          }
+          let lhs: Sexp = mangle_sexp(&sides[0]);
+          let rhs: Sexp = mangle_sexp(&sides[1]);
+          equations.push(RawEquation { lhs, rhs });
+          index += 1;
+        }
+        if equations.is_empty() {
+          return Err(SexpError::Other(format!(
+            "conjunction {} has no equations",
+            name
+          )));
+        }
+      } else {
+        let lhs: Sexp = mangle_sexp(&decl.list()?[index]);
+        let rhs: Sexp = mangle_sexp(&decl.list()?[index + 1]);
+        index += 2;
+        equations.push(RawEquation { lhs, rhs });
+      }
+
+      let mut local_rules = vec![];
+      let mut equivalences = vec![];
+      // If there's more to parse, these must be lemmas (or equivalences).
+      if decl.list()?.len() > index {
+        // Lemmas we are using to aid this proof
+        for rule_sexp in decl.list()?[index].list()? {
+          let lhs = mangle_sexp(&rule_sexp.list()?[1]);
+          let rhs = mangle_sexp(&rule_sexp.list()?[2]);
+          let searcher: Pattern<SymbolLang> = lhs.to_string().parse().unwrap();
+          let applier: Pattern<SymbolLang> = rhs.to_string().parse().unwrap();
+          // check if this is a bidirectional rewrite
+          match rule_sexp.list()?[0].string()?.as_str() {
+            "=>" => {
+              let rw = Rewrite::new(
+                format!("hyp-lemma-{}", lhs),
+                searcher.clone(),
+                applier.clone(),
+              )
+              .unwrap();
+              local_rules.push(rw);
+              // println!("adding rewrite rule: {} => {}", lhs, rhs);
+            }
+            "<=>" => {
+              let rw = Rewrite::new(
+                format!("hyp-lemma-{}", lhs),
+                searcher.clone(),
+                applier.clone(),
+              )
+              .unwrap();
+              local_rules.push(rw);
+              let rw = Rewrite::new(
+                format!("hyp-lemma-{}", rhs),
+                applier.clone(),
+                searcher.clone(),
+              )
+              .unwrap();
+              local_rules.push(rw);
+            }
+            "~" => {
+              // An equivalence: used in both directions, but it changes what the goal means
+              for (searcher, applier) in [(&searcher, &applier), (&applier, &searcher)] {
                 let rw = Rewrite::new(
-                  format!("hyp-lemma-{}", lhs),
+                  format!("equiv-{}={}", searcher, applier),
                   searcher.clone(),
                   applier.clone(),
                 )
-                .unwrap();
-                local_rules.push(rw);
-                let rw = Rewrite::new(
-                  format!("hyp-lemma-{}", rhs),
-                  applier.clone(),
-                  searcher.clone(),
-                )
-                .unwrap();
-                local_rules.push(rw);
+                .map_err(|_| {
+                  SexpError::Other(format!(
+                    "both sides of an equivalence must have the same variables: {}",
+                    rule_sexp
+                  ))
+                })?;
+                equivalences.push(rw);
               }
-              _ => panic!("unknown rewrite rules: {}", rule_sexp),
+            }
+            _ => {
+              return Err(SexpError::Other(format!(
+                "unknown rewrite rule: {}",
+                rule_sexp
+              )))
             }
           }
         }
-
-        let raw_goal = RawGoal {
-          name,
-          premise,
-          equation,
-          params,
-          local_rules,
-        };
-        state.raw_goals.push(raw_goal);
-      }
-      "//" => {
-        // comment
       }
-      _ => panic!("unknown declaration: {}", decl),
+
+      let raw_goal = RawGoal {
+        name,
+        premises,
+        equations,
+        params,
+        local_rules,
+        equivalences,
+      };
+      state.raw_goals.push(raw_goal);
     }
+    "//" => {
+      // comment
+    }
+    _ => return Err(SexpError::Other(format!("unknown declaration: {}", decl))),
   }
-  Ok(state)
+  Ok(())
 }
@@ -0,0 +1,639 @@
+use std::collections::{HashMap, HashSet};
+use symbolic_expressions::{parser, Sexp};
+
+use crate::parser::{declaration_positions, parse_decls, read_file, ParseError, ParserState};
+
+/// Result of translating an SMT-LIB form; the error is a message naming the offending form
+type Translation<T> = Result<T, String>;
+
+/// Variables in scope, mapped to their types
+type Scope = HashMap<String, Sexp>;
+
+/// Constructors of a datatype with their field types
+type Constructors = Vec<(String, Vec<Sexp>)>;
+
+/// Native declarations that every SMT-LIB problem gets for free
+const PRELUDE: [&str; 6] = [
+  "(data Bool (True False))",
+  "(:: True Bool)",
+  "(:: False Bool)",
+  "(:: ite (-> (Bool a a) a))",
+  "(let ite (True ?x ?y) ?x)",
+  "(let ite (False ?x ?y) ?y)",
+];
+
+/// Parse an SMT-LIB file; see `parse_smtlib`.
+pub fn parse_smtlib_file(filename: &str) -> Result<ParserState, ParseError> {
+  let src = read_file(filename)?;
+  parse_smtlib(&src)
+}
+
+/// Parse a problem in the SMT-LIB style used by TIP and other inductive provers.
+///
+/// The supported commands are translated into native declarations:
+/// - `declare-datatype(s)` become datatypes, constructor signatures, and selector definitions;
+/// - `declare-fun` and `declare-const` become signatures;
+/// - `define-fun(s)(-rec)` become signatures and one definition per case of the
+///   (possibly nested) `match`es on their parameters at the top of the body;
+/// - `prove`, `assert-not`, and `(assert (not ...))` become goals named `goal0`, `goal1`, etc.
+pub fn parse_smtlib(src: &str) -> Result<ParserState, ParseError> {
+  // Wrap the commands in a list, so that they look like a native problem;
+  // this shifts all positions one line down
+  let src = format!("(\n{}\n)", blank_comments_and_strings(src));
+  let positions: Vec<(usize, usize)> = declaration_positions(&src)?
+    .into_iter()
+    .map(|(line, column)| (line - 1, column))
+    .collect();
+  let sexpr = parser::parse_str(&src).map_err(|e| ParseError {
+    line: 1,
+    column: 1,
+    message: e.to_string(),
+  })?;
+  let mut decls: Vec<(Sexp, (usize, usize))> = PRELUDE
+    .iter()
+    .map(|decl| (parser::parse_str(decl).unwrap(), (1, 1)))
+    .collect();
+  let mut translator = Translator::default();
+  if let Sexp::List(cmds) = &sexpr {
+    for (cmd, (line, column)) in cmds.iter().zip(positions) {
+      let translated = translator.command(cmd).map_err(|message| ParseError {
+        line,
+        column,
+        message,
+      })?;
+      decls.extend(translated.into_iter().map(|decl| (decl, (line, column))));
+    }
+  }
+  parse_decls(decls.iter().map(|(decl, pos)| (decl, *pos)))
+}
+
+/// Replace comments and string literals with spaces,
+/// so that they don't confuse the s-expression parser but positions are preserved
+fn blank_comments_and_strings(src: &str) -> String {
+  let mut res = String::with_capacity(src.len());
+  let mut in_comment = false;
+  let mut in_string = false;
+  for c in src.chars() {
+    if c == '\n' {
+      in_comment = false;
+      res.push(c);
+    } else if in_comment {
+      res.push(' ');
+    } else if in_string {
+      in_string = c != '"';
+      res.push(' ');
+    } else if c == ';' || c == '"' {
+      in_comment = c == ';';
+      in_string = c == '"';
+      res.push(' ');
+    } else {
+      res.push(c);
+    }
+  }
+  res
+}
+
+fn atom(s: &str) -> Sexp {
+  Sexp::String(s.to_string())
+}
+
+fn is_atom(sexp: &Sexp, s: &str) -> bool {
+  matches!(sexp, Sexp::String(x) if x == s)
+}
+
+fn list_of(sexp: &Sexp) -> Translation<&Vec<Sexp>> {
+  match sexp {
+    Sexp::List(xs) => Ok(xs),
+    _ => Err(format!("expected a list, got {}", sexp)),
+  }
+}
+
+fn string_of(sexp: &Sexp) -> Translation<&String> {
+  match sexp {
+    Sexp::String(s) => Ok(s),
+    _ => Err(format!("expected a symbol, got {}", sexp)),
+  }
+}
+
+/// A list, or Sexp::Empty if there are no elements
+/// (which is how the native format writes the arguments of a constant)
+fn list_or_empty(xs: Vec<Sexp>) -> Sexp {
+  if xs.is_empty() {
+    Sexp::Empty
+  } else {
+    Sexp::List(xs)
+  }
+}
+
+/// Strip `(par (a ...) body)`, since type variables need no declaration in the native format
+fn strip_par(sexp: &Sexp) -> Translation<&Sexp> {
+  match sexp {
+    Sexp::List(xs) if !xs.is_empty() && is_atom(&xs[0], "par") => {
+      if xs.len() != 3 {
+        return Err(format!("malformed par: {}", sexp));
+      }
+      Ok(&xs[2])
+    }
+    _ => Ok(sexp),
+  }
+}
+
+/// Replace every occurrence of the symbol var in sexp with replacement
+fn substitute(sexp: &Sexp, var: &str, replacement: &Sexp) -> Sexp {
+  match sexp {
+    Sexp::String(s) if s == var => replacement.clone(),
+    Sexp::List(xs) => Sexp::List(xs.iter().map(|x| substitute(x, var, replacement)).collect()),
+    _ => sexp.clone(),
+  }
+}
+
+/// Nest a variadic application of a right-associative operator into binary applications
+fn fold_right(op: &str, args: Vec<Sexp>) -> Sexp {
+  let mut args = args.into_iter().rev();
+  let last = args.next().unwrap();
+  args.fold(last, |acc, arg| Sexp::List(vec![atom(op), arg, acc]))
+}
+
+/// Translate a type; SMT-LIB function types `(=> a b c)` become `(-> (a b) c)`
+fn translate_type(ty: &Sexp) -> Translation<Sexp> {
+  match ty {
+    Sexp::String(_) => Ok(ty.clone()),
+    Sexp::List(xs) if xs.len() > 2 && is_atom(&xs[0], "=>") => {
+      let args = xs[1..xs.len() - 1]
+        .iter()
+        .map(translate_type)
+        .collect::<Translation<Vec<Sexp>>>()?;
+      let ret = translate_type(&xs[xs.len() - 1])?;
+      Ok(Sexp::List(vec![atom("->"), Sexp::List(args), ret]))
+    }
+    Sexp::List(xs) if !xs.is_empty() => Ok(Sexp::List(
+      xs.iter()
+        .map(translate_type)
+        .collect::<Translation<Vec<Sexp>>>()?,
+    )),
+    _ => Err(format!("unsupported type: {}", ty)),
+  }
+}
+
+/// Parse a list of sorted variables `((x T) ...)`
+fn sorted_vars(sexp: &Sexp) -> Translation<Vec<(String, Sexp)>> {
+  list_of(sexp)?
+    .iter()
+    .map(|binding| {
+      let binding = list_of(binding)?;
+      if binding.len() != 2 {
+        return Err(format!(
+          "malformed variable binding: {}",
+          Sexp::List(binding.clone())
+        ));
+      }
+      Ok((
+        string_of(&binding[0])?.clone(),
+        translate_type(&binding[1])?,
+      ))
+    })
+    .collect()
+}
+
+/// Translate a term; variables in scope become pattern variables
+fn translate_term(term: &Sexp, scope: &Scope) -> Translation<Sexp> {
+  match term {
+    Sexp::String(s) => Ok(match s.as_str() {
+      "true" => atom("True"),
+      "false" => atom("False"),
+      _ if scope.contains_key(s) => atom(&format!("?{}", s)),
+      _ => term.clone(),
+    }),
+    Sexp::List(xs) if !xs.is_empty() => {
+      let head =
+        string_of(&xs[0]).map_err(|_| format!("unsupported higher-order application: {}", term))?;
+      // Binders and built-in predicates have no native counterpart
+      if let "match" | "let" | "forall" | "exists" | "lambda" | "=" | "distinct" | "as" | "_"
+      | "!" = head.as_str()
+      {
+        return Err(format!("unsupported term: {}", term));
+      }
+      let args = xs[1..]
+        .iter()
+        .map(|x| translate_term(x, scope))
+        .collect::<Translation<Vec<Sexp>>>()?;
+      match head.as_str() {
+        "and" if args.is_empty() => Ok(atom("True")),
+        "or" if args.is_empty() => Ok(atom("False")),
+        "and" | "or" => Ok(fold_right(head, args)),
+        "=>" if !args.is_empty() => Ok(fold_right("implies", args)),
+        // Higher-order application
+        "@" if args.len() > 1 => {
+          let mut args = args.into_iter();
+          let fun = args.next().unwrap();
+          Ok(args.fold(fun, |acc, arg| Sexp::List(vec![atom("$"), acc, arg])))
+        }
+        "=>" | "@" => Err(format!("unsupported term: {}", term)),
+        _ => {
+          let mut app = vec![atom(head)];
+          app.extend(args);
+          Ok(Sexp::List(app))
+        }
+      }
+    }
+    _ => Err(format!("unsupported term: {}", term)),
+  }
+}
+
+/// Translates SMT-LIB commands one at a time,
+/// remembering the datatypes declared so far so that it can expand wildcard patterns
+#[derive(Default)]
+struct Translator {
+  /// Type parameters and constructors (with their field types) of every datatype
+  datatypes: HashMap<String, (Vec<String>, Constructors)>,
+  /// Number of goals translated so far, used to name them
+  num_goals: usize,
+}
+
+impl Translator {
+  /// Translate a single command into zero or more native declarations
+  fn command(&mut self, cmd: &Sexp) -> Translation<Vec<Sexp>> {
+    let xs = list_of(cmd)?;
+    let kind = string_of(xs.first().ok_or_else(|| "empty command".to_string())?)?;
+    match kind.as_str() {
+      "set-logic" | "set-info" | "set-option" | "check-sat" | "get-model" | "exit" => Ok(vec![]),
+      "declare-datatype" if xs.len() == 3 => {
+        let name = string_of(&xs[1])?;
+        self.datatype(name, 0, &xs[2])
+      }
+      "declare-datatypes" if xs.len() == 3 => {
+        let sorts = list_of(&xs[1])?;
+        let decls = list_of(&xs[2])?;
+        if sorts.len() != decls.len() {
+          return Err(format!("unsupported form of declare-datatypes: {}", cmd));
+        }
+        // Register all constructors first, since the datatypes can be mutually recursive
+        let mut res = vec![];
+        for (sort, decl) in sorts.iter().zip(decls) {
+          let sort =
+            list_of(sort).map_err(|_| format!("unsupported form of declare-datatypes: {}", cmd))?;
+          let arity = match sort.get(1) {
+            Some(Sexp::String(n)) => n
+              .parse()
+              .map_err(|_| format!("malformed arity in {}", cmd))?,
+            _ => return Err(format!("malformed datatype declaration: {}", cmd)),
+          };
+          res.extend(self.datatype(string_of(&sort[0])?, arity, decl)?);
+        }
+        Ok(res)
+      }
+      "declare-fun" if xs.len() == 4 => {
+        let args = list_of(&xs[2])?
+          .iter()
+          .map(translate_type)
+          .collect::<Translation<Vec<Sexp>>>()?;
+        self.signature(string_of(&xs[1])?, args, translate_type(&xs[3])?)
+      }
+      "declare-const" if xs.len() == 3 => {
+        self.signature(string_of(&xs[1])?, vec![], translate_type(&xs[2])?)
+      }
+      "define-fun" | "define-fun-rec" => {
+        let parts = match xs.len() {
+          5 => &xs[1..],
+          // The definition is polymorphic: (define-fun-rec (par (a) (f params ret body)))
+          2 => list_of(strip_par(&xs[1])?)?,
+          _ => return Err(format!("malformed {}: {}", kind, cmd)),
+        };
+        if parts.len() != 4 {
+          return Err(format!("malformed {}: {}", kind, cmd));
+        }
+        self.function(&parts[0], &parts[1], &parts[2], &parts[3])
+      }
+      "define-funs-rec" if xs.len() == 3 => {
+        let decls = list_of(&xs[1])?;
+        let bodies = list_of(&xs[2])?;
+        if decls.len() != bodies.len() {
+          return Err(format!("malformed define-funs-rec: {}", cmd));
+        }
+        let mut res = vec![];
+        for (decl, body) in decls.iter().zip(bodies) {
+          let decl = list_of(strip_par(decl)?)?;
+          if decl.len() != 3 {
+            return Err(format!("malformed define-funs-rec: {}", cmd));
+          }
+          res.extend(self.function(&decl[0], &decl[1], &decl[2], body)?);
+        }
+        Ok(res)
+      }
+      "prove" | "assert-not" if xs.len() == 2 => Ok(vec![self.goal(&xs[1])?]),
+      "assert" if xs.len() == 2 => match list_of(&xs[1]) {
+        Ok(negation) if negation.len() == 2 && is_atom(&negation[0], "not") => {
+          Ok(vec![self.goal(&negation[1])?])
+        }
+        _ => Err(format!("only negated goals can be asserted: {}", cmd)),
+      },
+      _ => Err(format!("unsupported command: {}", cmd)),
+    }
+  }
+
+  /// A type signature
+  fn signature(&self, name: &str, args: Vec<Sexp>, ret: Sexp) -> Translation<Vec<Sexp>> {
+    let ty = if args.is_empty() {
+      ret
+    } else {
+      Sexp::List(vec![atom("->"), Sexp::List(args), ret])
+    };
+    Ok(vec![Sexp::List(vec![atom("::"), atom(name), ty])])
+  }
+
+  /// A datatype declaration with the given number of type parameters;
+  /// decl is the list of constructors, possibly under a `par`
+  fn datatype(&mut self, name: &str, arity: usize, decl: &Sexp) -> Translation<Vec<Sexp>> {
+    let (params, cons_decls) = match decl {
+      Sexp::List(xs) if !xs.is_empty() && is_atom(&xs[0], "par") => {
+        let params = list_of(&xs[1])?
+          .iter()
+          .map(|p| string_of(p).cloned())
+          .collect::<Translation<Vec<String>>>()?;
+        (params, list_of(strip_par(decl)?)?)
+      }
+      _ => (vec![], list_of(decl)?),
+    };
+    if params.len() != arity {
+      return Err(format!(
+        "datatype {} has {} parameters but declares {}",
+        name,
+        arity,
+        params.len()
+      ));
+    }
+    // The type of values of this datatype
+    let ty = if params.is_empty() {
+      atom(name)
+    } else {
+      let mut app = vec![atom(name)];
+      app.extend(params.iter().map(|p| atom(p)));
+      Sexp::List(app)
+    };
+    let mut cons = vec![];
+    let mut selectors = vec![];
+    for cons_decl in cons_decls {
+      let cons_decl = list_of(cons_decl)?;
+      let con = string_of(
+        cons_decl
+          .first()
+          .ok_or_else(|| format!("empty constructor in {}", name))?,
+      )?;
+      let fields = sorted_vars(&Sexp::List(cons_decl[1..].to_vec()))?;
+      for (i, (selector, _)) in fields.iter().enumerate() {
+        selectors.push((selector.clone(), con.clone(), i));
+      }
+      cons.push((
+        con.clone(),
+        fields.into_iter().map(|(_, ty)| ty).collect::<Vec<Sexp>>(),
+      ));
+    }
+    let mut res = vec![];
+    let mut data = vec![atom("data"), atom(name)];
+    if !params.is_empty() {
+      data.push(Sexp::List(params.iter().map(|p| atom(p)).collect()));
+    }
+    data.push(Sexp::List(cons.iter().map(|(con, _)| atom(con)).collect()));
+    res.push(Sexp::List(data));
+    for (con, field_tys) in cons.iter() {
+      res.extend(self.signature(con, field_tys.clone(), ty.clone())?);
+    }
+    // Selectors that aren't valid native identifiers are skipped,
+    // since programs from other tools often don't use them anyway
+    for (selector, con, i) in selectors {
+      if selector.contains('_') || !selector.starts_with(char::is_lowercase) {
+        continue;
+      }
+      let field_tys = &cons.iter().find(|(c, _)| *c == con).unwrap().1;
+      res.extend(self.signature(&selector, vec![ty.clone()], field_tys[i].clone())?);
+      let fields: Vec<Sexp> = (0..field_tys.len())
+        .map(|j| atom(&format!("?x{}", j)))
+        .collect();
+      let mut con_app = vec![atom(&con)];
+      con_app.extend(fields);
+      res.push(Sexp::List(vec![
+        atom("let"),
+        atom(&selector),
+        Sexp::List(vec![Sexp::List(con_app)]),
+        atom(&format!("?x{}", i)),
+      ]));
+    }
+    self.datatypes.insert(name.to_string(), (params, cons));
+    Ok(res)
+  }
+
+  /// Constructors of the datatype ty, with their field types instantiated with ty's arguments
+  fn constructors(&self, ty: &Sexp) -> Translation<Constructors> {
+    let (name, args) = match ty {
+      Sexp::String(name) => (name, vec![]),
+      Sexp::List(xs) if !xs.is_empty() => (string_of(&xs[0])?, xs[1..].to_vec()),
+      _ => return Err(format!("cannot match on a value of type {}", ty)),
+    };
+    let (params, cons) = self
+      .datatypes
+      .get(name)
+      .ok_or_else(|| format!("cannot match on a value of type {}", ty))?;
+    Ok(
+      cons
+        .iter()
+        .map(|(con, field_tys)| {
+          let field_tys = field_tys
+            .iter()
+            .map(|field_ty| {
+              params
+                .iter()
+                .zip(args.iter())
+                .fold(field_ty.clone(), |acc, (param, arg)| {
+                  substitute(&acc, param, arg)
+                })
+            })
+            .collect();
+          (con.clone(), field_tys)
+        })
+        .collect(),
+    )
+  }
+
+  /// A function definition: its signature and one native definition per case
+  fn function(
+    &self,
+    name: &Sexp,
+    params: &Sexp,
+    ret: &Sexp,
+    body: &Sexp,
+  ) -> Translation<Vec<Sexp>> {
+    let name = string_of(name)?;
+    let params = sorted_vars(params)?;
+    let mut res = self.signature(
+      name,
+      params.iter().map(|(_, ty)| ty.clone()).collect(),
+      translate_type(ret)?,
+    )?;
+    let args = params.iter().map(|(x, _)| atom(x)).collect();
+    let scope = params.into_iter().collect();
+    let mut cases = vec![];
+    self.cases(args, body.clone(), scope, &mut cases)?;
+    for (args, body, scope) in cases {
+      let args = args
+        .iter()
+        .map(|arg| translate_term(arg, &scope))
+        .collect::<Translation<Vec<Sexp>>>()?;
+      res.push(Sexp::List(vec![
+        atom("let"),
+        atom(name),
+        list_or_empty(args),
+        translate_term(&body, &scope)?,
+      ]));
+    }
+    Ok(res)
+  }
+
+  /// Split a definition with the given argument patterns into cases
+  /// along the `match`es on variables at the top of its body.
+  /// Since SMT-LIB matches pick the first matching clause, a wildcard clause
+  /// is expanded into the constructors that were not matched before it.
+  fn cases(
+    &self,
+    args: Vec<Sexp>,
+    body: Sexp,
+    scope: Scope,
+    res: &mut Vec<(Vec<Sexp>, Sexp, Scope)>,
+  ) -> Translation<()> {
+    let xs = match &body {
+      Sexp::List(xs) if xs.len() == 3 && is_atom(&xs[0], "match") => xs,
+      _ => {
+        res.push((args, body, scope));
+        return Ok(());
+      }
+    };
+    let var = match &xs[1] {
+      Sexp::String(var) if scope.contains_key(var) => var,
+      _ => return Err(format!("only matching on variables is supported: {}", body)),
+    };
+    let cons = self.constructors(&scope[var])?;
+    let mut covered = HashSet::new();
+    for clause in list_of(&xs[2])? {
+      let clause = list_of(clause)?;
+      if clause.len() != 2 {
+        return Err(format!(
+          "malformed match clause: {}",
+          Sexp::List(clause.clone())
+        ));
+      }
+      let (pattern, rhs) = (&clause[0], &clause[1]);
+      // The constructors this clause covers, with the names of their fields,
+      // and the body with the names bound by the pattern replaced
+      let mut matched = vec![];
+      match pattern {
+        Sexp::String(con) if cons.iter().any(|(c, _)| c == con) => {
+          matched.push((con.clone(), vec![], rhs.clone()));
+        }
+        Sexp::String(binder) => {
+          for (con, field_tys) in cons.iter() {
+            let fields: Vec<String> = (0..field_tys.len())
+              .map(|i| {
+                // A fresh name for this field
+                (0..)
+                  .map(|j| format!("{}{}x{}", var, i, j))
+                  .find(|x| !scope.contains_key(x))
+                  .unwrap()
+              })
+              .collect();
+            let con_app = constructor_application(con, &fields);
+            matched.push((con.clone(), fields, substitute(rhs, binder, &con_app)));
+          }
+        }
+        Sexp::List(ps) if !ps.is_empty() => {
+          let con = string_of(&ps[0])?;
+          let fields = ps[1..]
+            .iter()
+            .map(|p| string_of(p).cloned())
+            .collect::<Translation<Vec<String>>>()?;
+          matched.push((con.clone(), fields, rhs.clone()));
+        }
+        _ => return Err(format!("unsupported pattern: {}", pattern)),
+      }
+      for (con, fields, rhs) in matched {
+        let field_tys = &cons
+          .iter()
+          .find(|(c, _)| *c == con)
+          .ok_or_else(|| format!("{} is not a constructor of {}", con, scope[var]))?
+          .1;
+        if field_tys.len() != fields.len() {
+          return Err(format!("wrong number of fields for {} in {}", con, pattern));
+        }
+        // Only the first clause matching a constructor applies
+        if !covered.insert(con.clone()) {
+          continue;
+        }
+        let con_app = constructor_application(&con, &fields);
+        let args = args
+          .iter()
+          .map(|arg| substitute(arg, var, &con_app))
+          .collect();
+        let rhs = substitute(&rhs, var, &con_app);
+        let mut scope = scope.clone();
+        scope.remove(var);
+        scope.extend(fields.into_iter().zip(field_tys.iter().cloned()));
+        self.cases(args, rhs, scope, res)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// A goal `(forall ((x T) ...) body)`, where body is an equation, a Boolean term,
+  /// or an implication whose conclusion is an equation
+  fn goal(&mut self, formula: &Sexp) -> Translation<Sexp> {
+    let formula = strip_par(formula)?;
+    let (params, body) = match formula {
+      Sexp::List(xs) if xs.len() == 3 && is_atom(&xs[0], "forall") => {
+        (sorted_vars(&xs[1])?, &xs[2])
+      }
+      _ => (vec![], formula),
+    };
+    let name = format!("goal{}", self.num_goals);
+    self.num_goals += 1;
+    // Goal parameters are plain symbols in the native format, so nothing is in scope here
+    let scope = Scope::new();
+    // The two sides of an equation, or the formula and True
+    let sides = |sexp: &Sexp| -> Translation<(Sexp, Sexp)> {
+      match sexp {
+        Sexp::List(xs) if xs.len() == 3 && is_atom(&xs[0], "=") => Ok((
+          translate_term(&xs[1], &scope)?,
+          translate_term(&xs[2], &scope)?,
+        )),
+        _ => Ok((translate_term(sexp, &scope)?, atom("True"))),
+      }
+    };
+    let mut res = vec![
+      atom("==="),
+      atom(&name),
+      Sexp::List(params.iter().map(|(x, _)| atom(x)).collect()),
+      Sexp::List(params.iter().map(|(_, ty)| ty.clone()).collect()),
+    ];
+    match body {
+      Sexp::List(xs) if xs.len() == 3 && is_atom(&xs[0], "=>") => {
+        res[0] = atom("==>");
+        let (premise_lhs, premise_rhs) = sides(&xs[1])?;
+        let (lhs, rhs) = sides(&xs[2])?;
+        res.extend([premise_lhs, premise_rhs, lhs, rhs]);
+      }
+      _ => {
+        let (lhs, rhs) = sides(body)?;
+        res.extend([lhs, rhs]);
+      }
+    }
+    Ok(Sexp::List(res))
+  }
+}
+
+/// A constructor applied to variables, or the constructor alone if it has no fields
+fn constructor_application(con: &str, fields: &[String]) -> Sexp {
+  if fields.is_empty() {
+    atom(con)
+  } else {
+    let mut app = vec![atom(con)];
+    app.extend(fields.iter().map(|x| atom(x)));
+    Sexp::List(app)
+  }
+}